@@ -1,8 +1,22 @@
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{AesKeyGenParams, CryptoKey, SubtleCrypto};
+use web_sys::{AesGcmParams, AesKeyGenParams, CryptoKey, Pbkdf2Params, SubtleCrypto};
+
+use super::error::MinionsError;
+
+/// A secret encrypted with AES-GCM under a key derived from a passphrase.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WrappedSecret {
+    pub salt: Vec<u8>,
+    pub iv: Vec<u8>,
+    pub iterations: u32,
+    pub ciphertext: Vec<u8>,
+}
+
+pub const PBKDF2_ITERATIONS: u32 = 600_000;
 
 pub struct BrowserCrypto {
     crypto: SubtleCrypto,
+    random: web_sys::Crypto,
 }
 impl Default for BrowserCrypto {
     fn default() -> Self {
@@ -10,11 +24,12 @@ impl Default for BrowserCrypto {
         let crypto = window.crypto().expect("no global `crypto` exists");
         Self {
             crypto: crypto.subtle(),
+            random: crypto,
         }
     }
 }
 impl BrowserCrypto {
-    pub async fn crypto_key_from_bytes(&self, p_key: &[u8; 32]) -> Result<CryptoKey, JsValue> {
+    pub async fn crypto_key_from_bytes(&self, p_key: &[u8; 32]) -> Result<CryptoKey, MinionsError> {
         let array = js_sys::Uint8Array::from(&p_key[..]);
         let key_object: js_sys::Object = array.buffer().into();
         let algo = AesKeyGenParams::new("AES-GCM", 256);
@@ -28,7 +43,141 @@ impl BrowserCrypto {
         let key: JsValue = wasm_bindgen_futures::JsFuture::from(key).await?;
         Ok(key.dyn_into()?)
     }
-    pub async fn crypto_key_to_hex(&self, js_value: CryptoKey) -> Result<String, JsValue> {
+    /// A random non-extractable AES-GCM key. It can be kept in IndexedDB,
+    /// but its bytes can never be read back out of the browser.
+    pub async fn generate_storage_key(&self) -> Result<CryptoKey, MinionsError> {
+        let algo = AesKeyGenParams::new("AES-GCM", 256);
+        let usage_tags: js_sys::Array =
+            vec![JsValue::from_str("encrypt"), JsValue::from_str("decrypt")]
+                .iter()
+                .collect();
+        let key = self.crypto.generate_key_with_object(&algo, false, &usage_tags)?;
+        let key: JsValue = wasm_bindgen_futures::JsFuture::from(key).await?;
+        Ok(key.dyn_into()?)
+    }
+    pub fn random_bytes<const N: usize>(&self) -> Result<[u8; N], MinionsError> {
+        let mut bytes = [0u8; N];
+        self.random.get_random_values_with_u8_array(&mut bytes)?;
+        Ok(bytes)
+    }
+    /// Derives a non-extractable AES-GCM key from a passphrase with PBKDF2-SHA256.
+    pub async fn key_from_passphrase(
+        &self,
+        passphrase: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Result<CryptoKey, MinionsError> {
+        let passphrase: js_sys::Object = js_sys::Uint8Array::from(passphrase.as_bytes()).into();
+        let usage_tags: js_sys::Array = vec![JsValue::from_str("deriveKey")].iter().collect();
+        let base_key =
+            self.crypto
+                .import_key_with_str("raw", &passphrase, "PBKDF2", false, &usage_tags)?;
+        let base_key: CryptoKey = wasm_bindgen_futures::JsFuture::from(base_key)
+            .await?
+            .dyn_into()?;
+        let salt: js_sys::Object = js_sys::Uint8Array::from(salt).into();
+        let params = Pbkdf2Params::new("PBKDF2", &JsValue::from_str("SHA-256"), iterations, &salt);
+        let algo = AesKeyGenParams::new("AES-GCM", 256);
+        let usage_tags: js_sys::Array =
+            vec![JsValue::from_str("encrypt"), JsValue::from_str("decrypt")]
+                .iter()
+                .collect();
+        let key = self.crypto.derive_key_with_object_and_object(
+            &params,
+            &base_key,
+            &algo,
+            false,
+            &usage_tags,
+        )?;
+        let key: JsValue = wasm_bindgen_futures::JsFuture::from(key).await?;
+        Ok(key.dyn_into()?)
+    }
+    pub async fn encrypt(
+        &self,
+        key: &CryptoKey,
+        iv: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, MinionsError> {
+        self.encrypt_with_aad(key, iv, data, &[]).await
+    }
+    /// Encrypts `data` bound to `aad`, which is authenticated but not stored.
+    /// Decrypting with any other `aad` fails.
+    pub async fn encrypt_with_aad(
+        &self,
+        key: &CryptoKey,
+        iv: &[u8],
+        data: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, MinionsError> {
+        let params = Self::aes_gcm_params(iv, aad);
+        let data: js_sys::Object = js_sys::Uint8Array::from(data).into();
+        let encrypted = self
+            .crypto
+            .encrypt_with_object_and_buffer_source(&params, key, &data)?;
+        let encrypted = wasm_bindgen_futures::JsFuture::from(encrypted).await?;
+        Ok(js_sys::Uint8Array::new(&encrypted).to_vec())
+    }
+    pub async fn decrypt(
+        &self,
+        key: &CryptoKey,
+        iv: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, MinionsError> {
+        self.decrypt_with_aad(key, iv, data, &[]).await
+    }
+    pub async fn decrypt_with_aad(
+        &self,
+        key: &CryptoKey,
+        iv: &[u8],
+        data: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, MinionsError> {
+        let params = Self::aes_gcm_params(iv, aad);
+        let data: js_sys::Object = js_sys::Uint8Array::from(data).into();
+        let decrypted = self
+            .crypto
+            .decrypt_with_object_and_buffer_source(&params, key, &data)?;
+        let decrypted = wasm_bindgen_futures::JsFuture::from(decrypted).await?;
+        Ok(js_sys::Uint8Array::new(&decrypted).to_vec())
+    }
+    fn aes_gcm_params(iv: &[u8], aad: &[u8]) -> AesGcmParams {
+        let iv: js_sys::Object = js_sys::Uint8Array::from(iv).into();
+        let params = AesGcmParams::new("AES-GCM", &iv);
+        if !aad.is_empty() {
+            params.set_additional_data(&js_sys::Uint8Array::from(aad).into());
+        }
+        params
+    }
+    pub async fn wrap_secret(
+        &self,
+        secret: &[u8],
+        passphrase: &str,
+    ) -> Result<WrappedSecret, MinionsError> {
+        let salt = self.random_bytes::<16>()?.to_vec();
+        let iv = self.random_bytes::<12>()?.to_vec();
+        let key = self
+            .key_from_passphrase(passphrase, &salt, PBKDF2_ITERATIONS)
+            .await?;
+        let ciphertext = self.encrypt(&key, &iv, secret).await?;
+        Ok(WrappedSecret {
+            salt,
+            iv,
+            iterations: PBKDF2_ITERATIONS,
+            ciphertext,
+        })
+    }
+    /// Fails with `MinionsError::Crypto` if the passphrase is wrong.
+    pub async fn unwrap_secret(
+        &self,
+        wrapped: &WrappedSecret,
+        passphrase: &str,
+    ) -> Result<Vec<u8>, MinionsError> {
+        let key = self
+            .key_from_passphrase(passphrase, &wrapped.salt, wrapped.iterations)
+            .await?;
+        self.decrypt(&key, &wrapped.iv, &wrapped.ciphertext).await
+    }
+    pub async fn crypto_key_to_hex(&self, js_value: CryptoKey) -> Result<String, MinionsError> {
         let key =
             wasm_bindgen_futures::JsFuture::from(self.crypto.export_key("raw", &js_value)?).await?;
         let key_array: js_sys::ArrayBuffer = key.into();
@@ -59,4 +208,26 @@ mod tests {
         let hex = crypto.crypto_key_to_hex(key).await.unwrap();
         assert_eq!(hex.len(), 64);
     }
+    #[wasm_bindgen_test]
+    async fn _test_generate_storage_key() {
+        let crypto = BrowserCrypto::default();
+        let key = crypto.generate_storage_key().await.unwrap();
+        assert!(!key.extractable());
+        assert!(crypto.crypto_key_to_hex(key).await.is_err());
+    }
+    #[wasm_bindgen_test]
+    async fn _test_wrap_secret() {
+        let crypto = BrowserCrypto::default();
+        let wrapped = crypto
+            .wrap_secret(&[7; 32], "minion passphrase")
+            .await
+            .unwrap();
+        assert_ne!(wrapped.ciphertext[..32], [7; 32]);
+        let secret = crypto
+            .unwrap_secret(&wrapped, "minion passphrase")
+            .await
+            .unwrap();
+        assert_eq!(secret, vec![7; 32]);
+        assert!(crypto.unwrap_secret(&wrapped, "wrong").await.is_err());
+    }
 }
@@ -0,0 +1,320 @@
+use std::{cell::RefCell, collections::HashMap, future::Future, marker::PhantomData, rc::Rc};
+
+use js_sys::{Object, Reflect, Uint8Array};
+use tokio::sync::OnceCell;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::CryptoKey;
+
+use super::crypto::BrowserCrypto;
+use super::error::MinionsError;
+use super::idb_schema::IdbSchema;
+use super::indexed_db::{IdbStoreConfig, IdbStoreManager};
+
+const AES_GCM_IV_LENGTH: usize = 12;
+
+thread_local! {
+    /// Storage keys by name, loaded or created once per page.
+    static STORAGE_KEYS: RefCell<HashMap<&'static str, Rc<OnceCell<CryptoKey>>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Records that are serialized and AES-GCM encrypted before they reach
+/// IndexedDB. Only the document key is stored in the clear, so it must not be
+/// sensitive itself. The ciphertext is bound to its store and key, a record
+/// copied under another key does not decrypt.
+///
+/// The methods mirror `IdbStoreManager`, implement one trait or the other.
+pub trait EncryptedIdbStore: serde::Serialize + serde::de::DeserializeOwned + 'static {
+    fn config() -> IdbStoreConfig;
+    fn key(&self) -> JsValue;
+    fn schema() -> IdbSchema {
+        IdbSchema::single_store(&Self::config())
+    }
+    /// Name of the key records are encrypted under, one per database by default.
+    fn storage_key_name() -> &'static str {
+        Self::config().db_name
+    }
+
+    fn save_to_store(self) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Sized,
+    {
+        async move {
+            let crypto = BrowserCrypto::default();
+            let key = storage_key(Self::storage_key_name()).await?;
+            let plaintext = serde_json::to_vec(&self)?;
+            let iv = crypto.random_bytes::<AES_GCM_IV_LENGTH>()?;
+            let record_key = self.key();
+            let aad = EncryptedRecord::<Self>::additional_data(&record_key)?;
+            let ciphertext = crypto.encrypt_with_aad(&key, &iv, &plaintext, &aad).await?;
+            EncryptedRecord::<Self>::new(&record_key, &iv, &ciphertext)?
+                .save_to_store()
+                .await
+        }
+    }
+    fn retrieve_from_store(key: &JsValue) -> impl Future<Output = Result<Self, MinionsError>>
+    where
+        Self: Sized,
+    {
+        async move {
+            let record: EncryptedRecord<Self> =
+                EncryptedRecord::<Self>::retrieve_from_store(key).await?;
+            record.decrypt().await
+        }
+    }
+    fn retrieve_all_from_store() -> impl Future<Output = Result<Vec<Self>, MinionsError>>
+    where
+        Self: Sized,
+    {
+        async {
+            let mut values = vec![];
+            for record in EncryptedRecord::<Self>::retrieve_all_from_store().await? {
+                values.push(record.decrypt().await?);
+            }
+            Ok(values)
+        }
+    }
+    fn delete_from_store(&self) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Sized,
+    {
+        async {
+            let record = EncryptedRecord::<Self>::new(&self.key(), &[], &[])?;
+            record.delete_from_store().await
+        }
+    }
+}
+
+/// What an `EncryptedIdbStore` type actually writes: its document key next to
+/// the IV and ciphertext of the serialized value.
+pub struct EncryptedRecord<T> {
+    record: Object,
+    _value: PhantomData<T>,
+}
+impl<T: EncryptedIdbStore> EncryptedRecord<T> {
+    fn new(key: &JsValue, iv: &[u8], ciphertext: &[u8]) -> Result<Self, MinionsError> {
+        let record = Object::new();
+        Reflect::set(&record, &JsValue::from_str(T::config().document_key), key)?;
+        Reflect::set(&record, &JsValue::from_str("iv"), &Uint8Array::from(iv))?;
+        Reflect::set(
+            &record,
+            &JsValue::from_str("ciphertext"),
+            &Uint8Array::from(ciphertext),
+        )?;
+        Ok(Self {
+            record,
+            _value: PhantomData,
+        })
+    }
+    pub fn iv(&self) -> Result<Vec<u8>, MinionsError> {
+        self.bytes("iv")
+    }
+    pub fn ciphertext(&self) -> Result<Vec<u8>, MinionsError> {
+        self.bytes("ciphertext")
+    }
+    /// Fails with `MinionsError::Crypto` if the record was tampered with,
+    /// moved to another key or the storage key was replaced.
+    pub async fn decrypt(&self) -> Result<T, MinionsError> {
+        let key = storage_key(T::storage_key_name()).await?;
+        let aad = Self::additional_data(&IdbStoreManager::key(self))?;
+        let plaintext = BrowserCrypto::default()
+            .decrypt_with_aad(&key, &self.iv()?, &self.ciphertext()?, &aad)
+            .await?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+    /// Where the record lives, `db/store/key`, authenticated with its ciphertext.
+    fn additional_data(record_key: &JsValue) -> Result<Vec<u8>, MinionsError> {
+        let config = T::config();
+        let record_key: String = js_sys::JSON::stringify(record_key)?.into();
+        Ok(format!("{}/{}/{}", config.db_name, config.store_name, record_key).into_bytes())
+    }
+    fn bytes(&self, field: &str) -> Result<Vec<u8>, MinionsError> {
+        Ok(Reflect::get(&self.record, &JsValue::from_str(field))?
+            .dyn_into::<Uint8Array>()
+            .map_err(MinionsError::decode)?
+            .to_vec())
+    }
+}
+impl<T> Into<JsValue> for EncryptedRecord<T> {
+    fn into(self) -> JsValue {
+        self.record.into()
+    }
+}
+impl<T> TryFrom<JsValue> for EncryptedRecord<T> {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            record: value.dyn_into()?,
+            _value: PhantomData,
+        })
+    }
+}
+impl<T: EncryptedIdbStore> IdbStoreManager for EncryptedRecord<T> {
+    fn config() -> IdbStoreConfig {
+        T::config()
+    }
+    fn schema() -> IdbSchema {
+        T::schema()
+    }
+    fn key(&self) -> JsValue {
+        Reflect::get(&self.record, &JsValue::from_str(T::config().document_key))
+            .unwrap_or(JsValue::UNDEFINED)
+    }
+}
+
+/// A non-extractable storage key, kept as a `CryptoKey` object in IndexedDB.
+struct StoredKey {
+    name: String,
+    key: CryptoKey,
+}
+impl Into<JsValue> for StoredKey {
+    fn into(self) -> JsValue {
+        let record = Object::new();
+        let _ = Reflect::set(&record, &JsValue::from_str("name"), &self.name.into());
+        let _ = Reflect::set(&record, &JsValue::from_str("key"), &self.key);
+        record.into()
+    }
+}
+impl TryFrom<JsValue> for StoredKey {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: Reflect::get(&value, &JsValue::from_str("name"))?
+                .as_string()
+                .ok_or(JsValue::from_str("Storage key has no name"))?,
+            key: Reflect::get(&value, &JsValue::from_str("key"))?.dyn_into()?,
+        })
+    }
+}
+impl IdbStoreManager for StoredKey {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 1,
+            db_name: "minions_storage_keys",
+            store_name: "storage_keys",
+            document_key: "name",
+            indexes: &[],
+        }
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.name)
+    }
+}
+
+/// Loads the storage key called `name`, creating it on first use. Concurrent
+/// callers wait for the same key instead of racing to create two. Another tab
+/// can still create it first, then the key is only added once and both tabs
+/// use the one that was stored.
+async fn storage_key(name: &'static str) -> Result<CryptoKey, MinionsError> {
+    let cell = STORAGE_KEYS.with(|keys| keys.borrow_mut().entry(name).or_default().clone());
+    cell.get_or_try_init(|| async move {
+        let record_key = JsValue::from_str(name);
+        match StoredKey::retrieve_from_store::<StoredKey>(&record_key).await {
+            Ok(stored) => return Ok(stored.key),
+            Err(e) if e.is_not_found() => {}
+            Err(e) => return Err(e),
+        }
+        let key = BrowserCrypto::default().generate_storage_key().await?;
+        let created = StoredKey {
+            name: name.to_string(),
+            key: key.clone(),
+        };
+        match created.add_to_store().await {
+            Ok(()) => Ok(key),
+            Err(MinionsError::AlreadyExists(_)) => {
+                let stored: StoredKey = StoredKey::retrieve_from_store(&record_key).await?;
+                Ok(stored.key)
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+    .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct CustomerAddress {
+        id: String,
+        street: String,
+        latitude: f64,
+        longitude: f64,
+    }
+    impl EncryptedIdbStore for CustomerAddress {
+        fn config() -> IdbStoreConfig {
+            IdbStoreConfig {
+                db_name: "test_encrypted_db",
+                db_version: 1,
+                store_name: "addresses",
+                document_key: "id",
+                indexes: &[],
+            }
+        }
+        fn key(&self) -> JsValue {
+            JsValue::from_str(&self.id)
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn _encrypted_idb_store() -> Result<(), JsValue> {
+        let address = CustomerAddress {
+            id: "customer-1".to_string(),
+            street: "1 Minion Street".to_string(),
+            latitude: 19.43,
+            longitude: -99.13,
+        };
+        address.clone().save_to_store().await?;
+        CustomerAddress {
+            id: "customer-2".to_string(),
+            ..address.clone()
+        }
+        .save_to_store()
+        .await?;
+
+        let retrieved = CustomerAddress::retrieve_from_store(&address.key()).await?;
+        assert_eq!(retrieved, address);
+
+        let records = EncryptedRecord::<CustomerAddress>::retrieve_all_from_store().await?;
+        assert_eq!(records.len(), 2);
+        assert_ne!(records[0].iv()?, records[1].iv()?);
+        for record in &records {
+            let ciphertext = String::from_utf8_lossy(&record.ciphertext()?).to_string();
+            assert!(!ciphertext.contains("Minion Street"));
+        }
+
+        // A ciphertext copied onto another record's key is rejected.
+        let moved = EncryptedRecord::<CustomerAddress>::new(
+            &records[1].key(),
+            &records[0].iv()?,
+            &records[0].ciphertext()?,
+        )?;
+        assert!(matches!(
+            moved.decrypt().await,
+            Err(MinionsError::Crypto(_))
+        ));
+
+        // The storage key is only ever added once.
+        let storage_key = storage_key(CustomerAddress::storage_key_name()).await?;
+        let duplicate = StoredKey {
+            name: CustomerAddress::storage_key_name().to_string(),
+            key: storage_key,
+        };
+        assert!(matches!(
+            duplicate.add_to_store().await,
+            Err(MinionsError::AlreadyExists(_))
+        ));
+
+        let all = CustomerAddress::retrieve_all_from_store().await?;
+        assert_eq!(all.len(), 2);
+        for stored in all {
+            stored.delete_from_store().await?;
+        }
+        assert!(CustomerAddress::retrieve_all_from_store().await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{DomException, IdbRequest, IdbTransaction};
+
+/// Why a browser API call failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MinionsError {
+    /// A record, element or form field that does not exist.
+    NotFound(String),
+    /// A record with the same key was added first, by this tab or another one.
+    AlreadyExists(String),
+    /// The browser refused to store more data for this origin.
+    QuotaExceeded,
+    /// The database is at a newer version, or an upgrade is blocked by another tab.
+    VersionConflict(String),
+    /// The user or the browser denied access, like a refused location prompt.
+    PermissionDenied(String),
+    /// A value that does not encode, decode or fit the expected type.
+    Serialization(String),
+    /// Decryption failed, the key is wrong or the data was tampered with.
+    Crypto(String),
+    /// A transaction or request was aborted before it finished.
+    Aborted(String),
+    /// The API itself is missing, no window, no IndexedDB or no geolocation.
+    Unavailable(String),
+    /// Any other JavaScript exception.
+    JsException { name: String, message: String },
+}
+impl MinionsError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "NotFound",
+            Self::AlreadyExists(_) => "AlreadyExists",
+            Self::QuotaExceeded => "QuotaExceeded",
+            Self::VersionConflict(_) => "VersionConflict",
+            Self::PermissionDenied(_) => "PermissionDenied",
+            Self::Serialization(_) => "Serialization",
+            Self::Crypto(_) => "Crypto",
+            Self::Aborted(_) => "Aborted",
+            Self::Unavailable(_) => "Unavailable",
+            Self::JsException { .. } => "JsException",
+        }
+    }
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::NotFound(_))
+    }
+    /// A value that failed to convert into a Rust type.
+    pub fn decode(value: JsValue) -> Self {
+        Self::Serialization(js_message(&value))
+    }
+    fn from_dom_exception(exception: &DomException) -> Self {
+        let message = exception.message();
+        match exception.name().as_str() {
+            "NotFoundError" => Self::NotFound(message),
+            "ConstraintError" => Self::AlreadyExists(message),
+            "QuotaExceededError" => Self::QuotaExceeded,
+            "VersionError" => Self::VersionConflict(message),
+            "NotAllowedError" | "SecurityError" => Self::PermissionDenied(message),
+            "DataCloneError" | "EncodingError" => Self::Serialization(message),
+            "OperationError" => Self::Crypto(message),
+            "AbortError" => Self::Aborted(message),
+            name => Self::JsException {
+                name: name.to_string(),
+                message,
+            },
+        }
+    }
+}
+impl std::fmt::Display for MinionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(what) => write!(f, "Not found: {}", what),
+            Self::AlreadyExists(message) => write!(f, "Already exists: {}", message),
+            Self::QuotaExceeded => write!(f, "Storage quota exceeded"),
+            Self::VersionConflict(message) => write!(f, "Version conflict: {}", message),
+            Self::PermissionDenied(message) => write!(f, "Permission denied: {}", message),
+            Self::Serialization(message) => write!(f, "Serialization error: {}", message),
+            Self::Crypto(message) => write!(f, "Crypto error: {}", message),
+            Self::Aborted(message) => write!(f, "Aborted: {}", message),
+            Self::Unavailable(api) => write!(f, "{} is not available", api),
+            Self::JsException { name, message } => write!(f, "{}: {}", name, message),
+        }
+    }
+}
+impl std::error::Error for MinionsError {}
+
+/// Sorts a thrown value by its `DOMException` name. Error events of IndexedDB
+/// requests and transactions are read through to the exception behind them.
+impl From<JsValue> for MinionsError {
+    fn from(value: JsValue) -> Self {
+        if let Some(exception) = value.dyn_ref::<DomException>() {
+            return Self::from_dom_exception(exception);
+        }
+        if let Some(event) = value.dyn_ref::<web_sys::Event>() {
+            let target = event.target().map(JsValue::from);
+            let exception = match target {
+                Some(target) if target.is_instance_of::<IdbRequest>() => target
+                    .unchecked_into::<IdbRequest>()
+                    .error()
+                    .ok()
+                    .flatten(),
+                Some(target) if target.is_instance_of::<IdbTransaction>() => {
+                    target.unchecked_into::<IdbTransaction>().error()
+                }
+                _ => None,
+            };
+            return match exception {
+                Some(exception) => Self::from_dom_exception(&exception),
+                None => Self::JsException {
+                    name: event.type_(),
+                    message: "Event without an error".to_string(),
+                },
+            };
+        }
+        if let Some(error) = value.dyn_ref::<js_sys::Error>() {
+            return Self::JsException {
+                name: error.name().into(),
+                message: error.message().into(),
+            };
+        }
+        Self::JsException {
+            name: "Error".to_string(),
+            message: js_message(&value),
+        }
+    }
+}
+impl From<MinionsError> for JsValue {
+    fn from(error: MinionsError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name(error.name());
+        js_error.into()
+    }
+}
+impl From<serde_json::Error> for MinionsError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Serialization(error.to_string())
+    }
+}
+impl From<serde_wasm_bindgen::Error> for MinionsError {
+    fn from(error: serde_wasm_bindgen::Error) -> Self {
+        Self::Serialization(error.to_string())
+    }
+}
+impl From<yew::platform::pinned::oneshot::RecvError> for MinionsError {
+    fn from(error: yew::platform::pinned::oneshot::RecvError) -> Self {
+        Self::Aborted(error.to_string())
+    }
+}
+
+fn js_message(value: &JsValue) -> String {
+    if let Some(error) = value.dyn_ref::<js_sys::Error>() {
+        return error.message().into();
+    }
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _minions_error_from_js() {
+        let quota = DomException::new_with_message_and_name("Full", "QuotaExceededError").unwrap();
+        assert_eq!(MinionsError::from(JsValue::from(quota)), MinionsError::QuotaExceeded);
+        let missing = DomException::new_with_message_and_name("Gone", "NotFoundError").unwrap();
+        assert!(MinionsError::from(JsValue::from(missing)).is_not_found());
+        let taken = DomException::new_with_message_and_name("Taken", "ConstraintError").unwrap();
+        assert_eq!(
+            MinionsError::from(JsValue::from(taken)),
+            MinionsError::AlreadyExists("Taken".to_string())
+        );
+        let thrown = MinionsError::from(JsValue::from(js_sys::TypeError::new("Bad")));
+        assert_eq!(
+            thrown,
+            MinionsError::JsException {
+                name: "TypeError".to_string(),
+                message: "Bad".to_string(),
+            }
+        );
+
+        let error: JsValue = MinionsError::NotFound("note".to_string()).into();
+        let error: js_sys::Error = error.dyn_into().unwrap();
+        assert_eq!(String::from(error.name()), "NotFound");
+        assert_eq!(String::from(error.message()), "Not found: note");
+    }
+}
@@ -1,3 +1,10 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasm_bindgen::{closure::Closure, JsValue};
+use yew::platform::pinned::oneshot;
+
+use super::error::MinionsError;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct GeolocationCoordinates {
     pub accuracy: f64,
@@ -8,13 +15,13 @@ pub struct GeolocationCoordinates {
     pub longitude: f64,
     pub speed: Option<f64>,
 }
-impl Into<wasm_bindgen::JsValue> for GeolocationCoordinates {
-    fn into(self) -> wasm_bindgen::JsValue {
+impl Into<JsValue> for GeolocationCoordinates {
+    fn into(self) -> JsValue {
         serde_wasm_bindgen::to_value(&self).unwrap()
     }
 }
-impl From<wasm_bindgen::JsValue> for GeolocationCoordinates {
-    fn from(value: wasm_bindgen::JsValue) -> Self {
+impl From<JsValue> for GeolocationCoordinates {
+    fn from(value: JsValue) -> Self {
         serde_wasm_bindgen::from_value(value).unwrap()
     }
 }
@@ -25,43 +32,66 @@ pub struct GeolocationPosition {
     pub timestamp: f64,
 }
 impl GeolocationPosition {
-    pub async fn locate() -> Result<Self, wasm_bindgen::JsValue> {
-        let window =
-            web_sys::window().ok_or(wasm_bindgen::JsValue::from_str("No window available"))?;
-        let geolocation = window.navigator().geolocation()?;
-        let (sender, receiver) = yew::platform::pinned::oneshot::channel::<GeolocationPosition>();
+    /// Fails with `MinionsError::PermissionDenied` if the user refuses the prompt.
+    pub async fn locate() -> Result<Self, MinionsError> {
+        let window = web_sys::window().ok_or(MinionsError::Unavailable("Window".to_string()))?;
+        let geolocation = window
+            .navigator()
+            .geolocation()
+            .map_err(|_| MinionsError::Unavailable("Geolocation".to_string()))?;
+        let (sender, receiver) = oneshot::channel::<Result<GeolocationPosition, MinionsError>>();
+        let sender = Rc::new(RefCell::new(Some(sender)));
+        let error_sender = sender.clone();
         let on_success: js_sys::Function =
-            wasm_bindgen::closure::Closure::once_into_js(move |event: web_sys::Geolocation| {
-                if let Ok(geo) = GeolocationPosition::try_from(event) {
-                    let _ = sender.send(geo);
+            Closure::once_into_js(move |event: web_sys::Geolocation| {
+                if let Some(sender) = sender.borrow_mut().take() {
+                    let _ = sender.send(GeolocationPosition::try_from(event));
                 }
             })
             .into();
-        geolocation.get_current_position(&on_success)?;
-        receiver
-            .await
-            .map_err(|e| wasm_bindgen::JsValue::from_str(&e.to_string()))
+        let on_error: js_sys::Function = Closure::once_into_js(move |error: JsValue| {
+            if let Some(sender) = error_sender.borrow_mut().take() {
+                let _ = sender.send(Err(position_error(&error)));
+            }
+        })
+        .into();
+        geolocation.get_current_position_with_error_callback(&on_success, Some(&on_error))?;
+        receiver.await?
     }
 }
-impl TryFrom<wasm_bindgen::JsValue> for GeolocationPosition {
-    type Error = wasm_bindgen::JsValue;
-    fn try_from(value: wasm_bindgen::JsValue) -> Result<Self, Self::Error> {
+impl TryFrom<JsValue> for GeolocationPosition {
+    type Error = MinionsError;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
         Ok(serde_wasm_bindgen::from_value(value)?)
     }
 }
-impl TryInto<wasm_bindgen::JsValue> for GeolocationPosition {
-    type Error = wasm_bindgen::JsValue;
-    fn try_into(self) -> Result<wasm_bindgen::JsValue, Self::Error> {
+impl TryInto<JsValue> for GeolocationPosition {
+    type Error = MinionsError;
+    fn try_into(self) -> Result<JsValue, Self::Error> {
         Ok(serde_wasm_bindgen::to_value(&self)?)
     }
 }
 impl TryFrom<web_sys::Geolocation> for GeolocationPosition {
-    type Error = wasm_bindgen::JsValue;
+    type Error = MinionsError;
     fn try_from(value: web_sys::Geolocation) -> Result<Self, Self::Error> {
         Ok(serde_wasm_bindgen::from_value(value.into())?)
     }
 }
 
+/// Reads a `GeolocationPositionError` by its numeric code.
+fn position_error(error: &JsValue) -> MinionsError {
+    let field = |name: &str| js_sys::Reflect::get(error, &JsValue::from_str(name)).ok();
+    let message = field("message")
+        .and_then(|message| message.as_string())
+        .unwrap_or_default();
+    match field("code").and_then(|code| code.as_f64()) {
+        Some(code) if code == 1.0 => MinionsError::PermissionDenied(message),
+        Some(code) if code == 2.0 => MinionsError::Unavailable("Position".to_string()),
+        Some(code) if code == 3.0 => MinionsError::Aborted(message),
+        _ => MinionsError::from(error.clone()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,36 +1,38 @@
-use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::JsCast;
 use web_sys::{HtmlFormElement, HtmlInputElement, HtmlSelectElement, SubmitEvent};
 
+use super::error::MinionsError;
+
 pub struct HtmlDocument {
     document: web_sys::Document,
 }
 impl HtmlDocument {
-    pub fn new() -> Result<Self, JsValue> {
-        let window = web_sys::window().ok_or(JsValue::from_str("No window available"))?;
+    pub fn new() -> Result<Self, MinionsError> {
+        let window = web_sys::window().ok_or(MinionsError::Unavailable("Window".to_string()))?;
         let document = window
             .document()
-            .ok_or(JsValue::from_str("No document available"))?;
+            .ok_or(MinionsError::Unavailable("Document".to_string()))?;
         Ok(Self { document })
     }
-    pub fn find_element_by_id<T>(&self, id: &str) -> Result<T, JsValue>
+    pub fn find_element_by_id<T>(&self, id: &str) -> Result<T, MinionsError>
     where
         T: JsCast,
     {
         self.document
             .get_element_by_id(id)
-            .ok_or(JsValue::from_str("Element not found"))?
+            .ok_or(MinionsError::NotFound(format!("Element #{}", id)))?
             .dyn_into::<T>()
-            .map_err(|_| JsValue::from_str("Failed to cast element"))
+            .map_err(|_| MinionsError::Serialization(format!("#{} is another element", id)))
     }
-    pub fn query_selector<T>(&self, selector: &str) -> Result<T, JsValue>
+    pub fn query_selector<T>(&self, selector: &str) -> Result<T, MinionsError>
     where
         T: JsCast,
     {
         self.document
             .query_selector(selector)?
-            .ok_or(JsValue::from_str("Elements not found"))?
+            .ok_or(MinionsError::NotFound(format!("Elements {}", selector)))?
             .dyn_into::<T>()
-            .map_err(|_| JsValue::from_str("Failed to cast element"))
+            .map_err(|_| MinionsError::Serialization(format!("{} is another element", selector)))
     }
 }
 
@@ -38,31 +40,31 @@ pub struct HtmlForm {
     form: HtmlFormElement,
 }
 impl HtmlForm {
-    pub fn new(submit_event: SubmitEvent) -> Result<Self, JsValue> {
-        let form = submit_event.target();
-        if form.is_none() {
-            return Err(JsValue::from_str("Form not found"));
-        }
-        let form = form.unwrap().dyn_into::<HtmlFormElement>()?;
+    pub fn new(submit_event: SubmitEvent) -> Result<Self, MinionsError> {
+        let form = submit_event
+            .target()
+            .ok_or(MinionsError::NotFound("Form".to_string()))?
+            .dyn_into::<HtmlFormElement>()
+            .map_err(|_| MinionsError::Serialization("Target is not a form".to_string()))?;
         Ok(HtmlForm { form })
     }
-    pub fn input<T>(&self, name: &str) -> Result<T, JsValue>
+    pub fn input<T>(&self, name: &str) -> Result<T, MinionsError>
     where
         T: JsCast,
     {
-        let input = self.form.get_with_name(name);
-        if input.is_none() {
-            return Err(JsValue::from_str("Input not found"));
-        }
-        Ok(input.unwrap().dyn_into::<T>()?)
+        self.form
+            .get_with_name(name)
+            .ok_or(MinionsError::NotFound(format!("Input {}", name)))?
+            .dyn_into::<T>()
+            .map_err(|_| MinionsError::Serialization(format!("Input {} is another element", name)))
     }
-    pub fn input_value(&self, name: &str) -> Result<String, JsValue> {
+    pub fn input_value(&self, name: &str) -> Result<String, MinionsError> {
         Ok(self.input::<HtmlInputElement>(name)?.value())
     }
-    pub fn select_value(&self, name: &str) -> Result<String, JsValue> {
+    pub fn select_value(&self, name: &str) -> Result<String, MinionsError> {
         Ok(self.input::<HtmlSelectElement>(name)?.value())
     }
-    pub fn textarea_value(&self, name: &str) -> Result<String, JsValue> {
+    pub fn textarea_value(&self, name: &str) -> Result<String, MinionsError> {
         Ok(self.input::<web_sys::HtmlTextAreaElement>(name)?.value())
     }
 }
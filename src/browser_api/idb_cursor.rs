@@ -0,0 +1,277 @@
+use std::marker::PhantomData;
+
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{IdbCursor, IdbCursorWithValue, IdbRequest};
+
+use super::error::MinionsError;
+use super::idb_query::{request_result, IdbQuery};
+
+type CursorEvents = async_channel::Receiver<Result<Option<IdbCursor>, MinionsError>>;
+
+/// The moving parts shared by value and key cursors. A cursor only moves
+/// when the next item is asked for.
+struct RawCursor {
+    request: IdbRequest,
+    events: CursorEvents,
+    current: Option<IdbCursor>,
+    offset: u32,
+    remaining: Option<u32>,
+    finished: bool,
+    _on_success: Closure<dyn FnMut(web_sys::Event)>,
+    _on_error: Closure<dyn FnMut(web_sys::Event)>,
+}
+impl RawCursor {
+    fn new(request: IdbRequest, query: &IdbQuery) -> Self {
+        let (sender, events) = async_channel::unbounded();
+        let success_sender = sender.clone();
+        let success_request = request.clone();
+        let on_success = Closure::<dyn FnMut(web_sys::Event)>::new(move |_: web_sys::Event| {
+            let cursor = success_request
+                .result()
+                .map(|result| result.dyn_into::<IdbCursor>().ok())
+                .map_err(MinionsError::from);
+            let _ = success_sender.try_send(cursor);
+        });
+        let on_error = Closure::<dyn FnMut(web_sys::Event)>::new(move |event: web_sys::Event| {
+            let _ = sender.try_send(Err(MinionsError::from(JsValue::from(event))));
+        });
+        request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+        request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        Self {
+            request,
+            events,
+            current: None,
+            offset: query.offset,
+            remaining: query.limit,
+            finished: false,
+            _on_success: on_success,
+            _on_error: on_error,
+        }
+    }
+
+    async fn next(&mut self) -> Option<Result<IdbCursor, MinionsError>> {
+        if self.finished || self.remaining == Some(0) {
+            self.finished = true;
+            return None;
+        }
+        if let Some(cursor) = self.current.take() {
+            if let Err(e) = cursor.continue_() {
+                self.finished = true;
+                return Some(Err(e.into()));
+            }
+        }
+        loop {
+            match self.events.recv().await {
+                Ok(Ok(Some(cursor))) if self.offset > 0 => {
+                    if let Err(e) = cursor.advance(std::mem::take(&mut self.offset)) {
+                        self.finished = true;
+                        return Some(Err(e.into()));
+                    }
+                }
+                Ok(Ok(Some(cursor))) => {
+                    self.remaining = self.remaining.map(|remaining| remaining - 1);
+                    self.current = Some(cursor.clone());
+                    return Some(Ok(cursor));
+                }
+                Ok(Ok(None)) | Err(_) => {
+                    self.finished = true;
+                    return None;
+                }
+                Ok(Err(e)) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+    fn current(&self) -> Result<&IdbCursor, MinionsError> {
+        self.current.as_ref().ok_or(MinionsError::NotFound(
+            "The cursor is not on a record".to_string(),
+        ))
+    }
+}
+impl Drop for RawCursor {
+    fn drop(&mut self) {
+        self.request.set_onsuccess(None);
+        self.request.set_onerror(None);
+    }
+}
+
+/// Records read one at a time through `openCursor`. Stop calling `next`, or
+/// drop the stream, to end early.
+///
+/// The transaction stays open only while the cursor is driven, awaiting
+/// unrelated work between items lets it commit and the next item fails.
+///
+/// ```ignore
+/// let mut notes = CachedNote::open_cursor(IdbQuery::all()).await?;
+/// while let Some(note) = notes.next().await {
+///     match note {
+///         Ok(note) if note.note.get_kind() == 5 => notes.delete().await?,
+///         Ok(_) => {}
+///         Err(e) => gloo::console::error!("Skipping bad record", e),
+///     }
+/// }
+/// ```
+pub struct CursorStream<T> {
+    cursor: RawCursor,
+    _record: PhantomData<T>,
+}
+impl<T> CursorStream<T>
+where
+    T: TryFrom<JsValue, Error = JsValue>,
+{
+    pub(crate) fn new(request: IdbRequest, query: &IdbQuery) -> Self {
+        Self {
+            cursor: RawCursor::new(request, query),
+            _record: PhantomData,
+        }
+    }
+    /// The next record. A record that does not decode is yielded as an error
+    /// and the stream goes on, a failing cursor yields its error and ends.
+    pub async fn next(&mut self) -> Option<Result<T, MinionsError>> {
+        let cursor = match self.cursor.next().await? {
+            Ok(cursor) => cursor,
+            Err(e) => return Some(Err(e)),
+        };
+        let value = match cursor.dyn_ref::<IdbCursorWithValue>() {
+            Some(cursor) => cursor.value().map_err(MinionsError::from),
+            None => Err(MinionsError::Serialization(
+                "Not a value cursor".to_string(),
+            )),
+        };
+        Some(value.and_then(|value| T::try_from(value).map_err(MinionsError::decode)))
+    }
+    /// Primary key of the record last returned by `next`.
+    pub fn primary_key(&self) -> Result<JsValue, MinionsError> {
+        Ok(self.cursor.current()?.primary_key()?)
+    }
+    /// Replaces the record last returned by `next`. The primary key must not change.
+    pub async fn update(&self, record: T) -> Result<(), MinionsError>
+    where
+        T: Into<JsValue>,
+    {
+        let request = self.cursor.current()?.update(&record.into())?;
+        request_result(&request).await.map(|_| ())
+    }
+    /// Deletes the record last returned by `next`.
+    pub async fn delete(&self) -> Result<(), MinionsError> {
+        let request = self.cursor.current()?.delete()?;
+        request_result(&request).await.map(|_| ())
+    }
+    /// Reads the remaining records, keeping per-record errors.
+    pub async fn collect(mut self) -> Vec<Result<T, MinionsError>> {
+        let mut records = vec![];
+        while let Some(record) = self.next().await {
+            records.push(record);
+        }
+        records
+    }
+}
+
+/// Primary keys read through `openKeyCursor`, without loading the records.
+pub struct KeyCursorStream {
+    cursor: RawCursor,
+}
+impl KeyCursorStream {
+    pub(crate) fn new(request: IdbRequest, query: &IdbQuery) -> Self {
+        Self {
+            cursor: RawCursor::new(request, query),
+        }
+    }
+    pub async fn next(&mut self) -> Option<Result<JsValue, MinionsError>> {
+        match self.cursor.next().await? {
+            Ok(cursor) => Some(cursor.primary_key().map_err(MinionsError::from)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser_api::indexed_db::test_record;
+    use crate::browser_api::{IdbSchema, IdbStoreManager, MigrationStep};
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn cursor_schema() -> IdbSchema {
+        IdbSchema::new("test_cursor_db").migration(
+            1,
+            vec![MigrationStep::CreateStore {
+                store: "items",
+                key_path: "id",
+            }],
+        )
+    }
+    test_record!(CursorItem, "test_cursor_db", "items", 1, cursor_schema());
+
+    #[wasm_bindgen_test]
+    async fn _idb_cursor_stream() -> Result<(), JsValue> {
+        for id in 1..=5 {
+            let name = format!("item {}", id);
+            CursorItem {
+                id,
+                name,
+                shout: None,
+            }
+            .save_to_store()
+            .await?;
+        }
+        // A record another version of the app wrote with the wrong shape.
+        let malformed = js_sys::Object::new();
+        js_sys::Reflect::set(&malformed, &"id".into(), &6.into())?;
+        js_sys::Reflect::set(&malformed, &"name".into(), &42.into())?;
+        CursorItem::request_store_open().await?.put(&malformed)?;
+
+        let mut first_two = CursorItem::open_cursor(IdbQuery::all()).await?;
+        assert_eq!(
+            first_two.next().await.transpose()?.map(|item| item.id),
+            Some(1)
+        );
+        assert_eq!(
+            first_two.next().await.transpose()?.map(|item| item.id),
+            Some(2)
+        );
+        drop(first_two);
+
+        let mut items = CursorItem::open_cursor(IdbQuery::all().reverse()).await?;
+        assert!(items.next().await.expect("No malformed record").is_err());
+        while let Some(item) = items.next().await {
+            let item = item?;
+            match item.id {
+                4 => items.delete().await?,
+                2 => {
+                    let shout = Some(item.name.to_uppercase());
+                    items.update(CursorItem { shout, ..item }).await?
+                }
+                _ => {}
+            }
+        }
+        let mut keys = CursorItem::open_key_cursor(IdbQuery::all().offset(1).limit(3)).await?;
+        let mut found = vec![];
+        while let Some(key) = keys.next().await {
+            found.push(key?.as_f64().unwrap_or_default() as u32);
+        }
+        assert_eq!(found, vec![2, 3, 5]);
+
+        let items = CursorItem::open_cursor(IdbQuery::all().limit(5))
+            .await?
+            .collect()
+            .await;
+        assert_eq!(items.len(), 5);
+        assert_eq!(
+            items[1].as_ref().ok().and_then(|item| item.shout.clone()),
+            Some("ITEM 2".to_string())
+        );
+        assert!(items[4].is_err());
+        assert!(CursorItem::retrieve_all_from_store().await.is_err());
+
+        let mut cleanup = CursorItem::open_cursor(IdbQuery::all()).await?;
+        while cleanup.next().await.is_some() {
+            cleanup.delete().await?;
+        }
+        assert!(CursorItem::retrieve_all_from_store().await?.is_empty());
+        Ok(())
+    }
+}
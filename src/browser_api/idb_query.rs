@@ -0,0 +1,405 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{IdbCursorDirection, IdbCursorWithValue, IdbKeyRange, IdbObjectStore, IdbRequest};
+use yew::platform::pinned::oneshot;
+
+use super::error::MinionsError;
+
+/// Which keys a query matches. Compound index keys are arrays,
+/// `js_sys::Array::of2(&kind.into(), &created_at.into())`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyRange {
+    Only(JsValue),
+    /// Keys above a value, excluding it when `open`.
+    Lower {
+        lower: JsValue,
+        open: bool,
+    },
+    /// Keys below a value, excluding it when `open`.
+    Upper {
+        upper: JsValue,
+        open: bool,
+    },
+    Bound {
+        lower: JsValue,
+        upper: JsValue,
+        lower_open: bool,
+        upper_open: bool,
+    },
+}
+impl KeyRange {
+    /// Fails if a key is not a valid IndexedDB key or the bounds are reversed.
+    pub fn to_idb(&self) -> Result<IdbKeyRange, MinionsError> {
+        let range = match self {
+            KeyRange::Only(key) => IdbKeyRange::only(key),
+            KeyRange::Lower { lower, open } => IdbKeyRange::lower_bound_with_open(lower, *open),
+            KeyRange::Upper { upper, open } => IdbKeyRange::upper_bound_with_open(upper, *open),
+            KeyRange::Bound {
+                lower,
+                upper,
+                lower_open,
+                upper_open,
+            } => IdbKeyRange::bound_with_lower_open_and_upper_open(
+                lower,
+                upper,
+                *lower_open,
+                *upper_open,
+            ),
+        };
+        Ok(range?)
+    }
+}
+
+/// A read over a store or one of its indexes.
+///
+/// ```ignore
+/// let this_week = CalendarEvent::query_from_store(
+///     IdbQuery::index("by_start").bound(monday.into(), sunday.into()).limit(50),
+/// )
+/// .await?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdbQuery {
+    pub index: Option<&'static str>,
+    pub range: Option<KeyRange>,
+    pub reverse: bool,
+    pub offset: u32,
+    pub limit: Option<u32>,
+}
+impl IdbQuery {
+    /// Every record by primary key.
+    pub fn all() -> Self {
+        Self::default()
+    }
+    /// Records in the order of a secondary index.
+    pub fn index(name: &'static str) -> Self {
+        Self {
+            index: Some(name),
+            ..Default::default()
+        }
+    }
+    pub fn only(mut self, key: JsValue) -> Self {
+        self.range = Some(KeyRange::Only(key));
+        self
+    }
+    /// Keys at or above `lower`.
+    pub fn lower(mut self, lower: JsValue) -> Self {
+        self.range = Some(KeyRange::Lower { lower, open: false });
+        self
+    }
+    /// Keys at or below `upper`.
+    pub fn upper(mut self, upper: JsValue) -> Self {
+        self.range = Some(KeyRange::Upper { upper, open: false });
+        self
+    }
+    /// Keys between `lower` and `upper`, both included.
+    pub fn bound(mut self, lower: JsValue, upper: JsValue) -> Self {
+        self.range = Some(KeyRange::Bound {
+            lower,
+            upper,
+            lower_open: false,
+            upper_open: false,
+        });
+        self
+    }
+    pub fn range(mut self, range: KeyRange) -> Self {
+        self.range = Some(range);
+        self
+    }
+    /// Highest keys first.
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.offset = offset;
+        self
+    }
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub(crate) fn key_range(&self) -> Result<JsValue, MinionsError> {
+        match &self.range {
+            Some(range) => Ok(range.to_idb()?.into()),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+    pub(crate) fn direction(&self) -> IdbCursorDirection {
+        match self.reverse {
+            true => IdbCursorDirection::Prev,
+            false => IdbCursorDirection::Next,
+        }
+    }
+
+    /// The raw values the query matches, without loading the rest of the store.
+    pub async fn values(&self, store: &IdbObjectStore) -> Result<Vec<JsValue>, MinionsError> {
+        let range = self.key_range()?;
+        if self.limit == Some(0) {
+            return Ok(vec![]);
+        }
+        // Forward reads from the start are a single `getAll`.
+        if !self.reverse && self.offset == 0 {
+            let limit = self.limit.unwrap_or(0);
+            let request = match self.index {
+                Some(index) => store
+                    .index(index)?
+                    .get_all_with_key_and_limit(&range, limit)?,
+                None => store.get_all_with_key_and_limit(&range, limit)?,
+            };
+            let result: js_sys::Array = request_result(&request)
+                .await?
+                .dyn_into()
+                .map_err(MinionsError::decode)?;
+            return Ok(result.iter().collect());
+        }
+        let request = self.open_cursor(store)?;
+        collect_cursor(&request, self.offset, self.limit).await
+    }
+    /// How many records the query matches, after offset and limit.
+    pub async fn count(&self, store: &IdbObjectStore) -> Result<u32, MinionsError> {
+        let range = self.key_range()?;
+        let request = match self.index {
+            Some(index) => store.index(index)?.count_with_key(&range)?,
+            None => store.count_with_key(&range)?,
+        };
+        let total = request_result(&request)
+            .await?
+            .as_f64()
+            .ok_or(MinionsError::Serialization(
+                "Count is not a number".to_string(),
+            ))? as u32;
+        let count = total.saturating_sub(self.offset);
+        Ok(self.limit.map_or(count, |limit| count.min(limit)))
+    }
+    pub(crate) fn open_cursor(&self, store: &IdbObjectStore) -> Result<IdbRequest, MinionsError> {
+        let range = self.key_range()?;
+        let request = match self.index {
+            Some(index) => store
+                .index(index)?
+                .open_cursor_with_range_and_direction(&range, self.direction()),
+            None => store.open_cursor_with_range_and_direction(&range, self.direction()),
+        };
+        Ok(request?)
+    }
+    pub(crate) fn open_key_cursor(
+        &self,
+        store: &IdbObjectStore,
+    ) -> Result<IdbRequest, MinionsError> {
+        let range = self.key_range()?;
+        let request = match self.index {
+            Some(index) => store
+                .index(index)?
+                .open_key_cursor_with_range_and_direction(&range, self.direction()),
+            None => store.open_key_cursor_with_range_and_direction(&range, self.direction()),
+        };
+        Ok(request?)
+    }
+}
+
+/// Waits for a request to succeed or fail.
+pub(crate) async fn request_result(request: &IdbRequest) -> Result<JsValue, MinionsError> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+    let error_sender = sender.clone();
+    let success_request = request.clone();
+    let on_success = Closure::once_into_js(move |_: web_sys::Event| {
+        if let Some(sender) = sender.borrow_mut().take() {
+            let _ = sender.send(success_request.result().map_err(MinionsError::from));
+        }
+    });
+    let on_error = Closure::once_into_js(move |event: web_sys::Event| {
+        if let Some(sender) = error_sender.borrow_mut().take() {
+            let _ = sender.send(Err(MinionsError::from(JsValue::from(event))));
+        }
+    });
+    request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+    request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    receiver.await?
+}
+
+/// Collects cursor values, skipping `offset` records with a single `advance`.
+async fn collect_cursor(
+    request: &IdbRequest,
+    offset: u32,
+    limit: Option<u32>,
+) -> Result<Vec<JsValue>, MinionsError> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+    let error_sender = sender.clone();
+    let values = Rc::new(RefCell::new(vec![]));
+    let cursor_request = request.clone();
+    let mut skipped = offset == 0;
+    let on_success = Closure::<dyn FnMut(web_sys::Event)>::new(move |_: web_sys::Event| {
+        let mut step = || -> Result<bool, JsValue> {
+            let result = cursor_request.result()?;
+            if result.is_null() || result.is_undefined() {
+                return Ok(true);
+            }
+            let cursor: IdbCursorWithValue = result.dyn_into()?;
+            if !skipped {
+                skipped = true;
+                cursor.advance(offset)?;
+                return Ok(false);
+            }
+            values.borrow_mut().push(cursor.value()?);
+            if limit.is_some_and(|limit| values.borrow().len() >= limit as usize) {
+                return Ok(true);
+            }
+            cursor.continue_()?;
+            Ok(false)
+        };
+        let done = match step() {
+            Ok(false) => return,
+            Ok(true) => Ok(values.take()),
+            Err(e) => Err(MinionsError::from(e)),
+        };
+        if let Some(sender) = sender.borrow_mut().take() {
+            let _ = sender.send(done);
+        }
+    });
+    let on_error = Closure::once_into_js(move |event: web_sys::Event| {
+        if let Some(sender) = error_sender.borrow_mut().take() {
+            let _ = sender.send(Err(MinionsError::from(JsValue::from(event))));
+        }
+    });
+    request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+    request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    let values = receiver.await?;
+    // The cursor is done with the closure once a value was sent.
+    drop(on_success);
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser_api::{IdbIndexConfig, IdbStoreConfig, IdbStoreManager};
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct CalendarEvent {
+        id: u32,
+        start: f64,
+        kind: u32,
+        tags: Vec<String>,
+    }
+    impl Into<JsValue> for CalendarEvent {
+        fn into(self) -> JsValue {
+            serde_wasm_bindgen::to_value(&self).unwrap()
+        }
+    }
+    impl TryFrom<JsValue> for CalendarEvent {
+        type Error = JsValue;
+        fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+            Ok(serde_wasm_bindgen::from_value(value)?)
+        }
+    }
+    impl IdbStoreManager for CalendarEvent {
+        fn config() -> IdbStoreConfig {
+            IdbStoreConfig {
+                db_name: "test_query_db",
+                db_version: 1,
+                store_name: "events",
+                document_key: "id",
+                indexes: &[
+                    IdbIndexConfig {
+                        name: "by_start",
+                        key_path: &["start"],
+                        unique: false,
+                        multi_entry: false,
+                    },
+                    IdbIndexConfig {
+                        name: "by_kind_start",
+                        key_path: &["kind", "start"],
+                        unique: false,
+                        multi_entry: false,
+                    },
+                    IdbIndexConfig {
+                        name: "by_tag",
+                        key_path: &["tags"],
+                        unique: false,
+                        multi_entry: true,
+                    },
+                ],
+            }
+        }
+        fn key(&self) -> JsValue {
+            JsValue::from(self.id)
+        }
+    }
+
+    fn ids(events: Vec<CalendarEvent>) -> Vec<u32> {
+        events.into_iter().map(|event| event.id).collect()
+    }
+
+    #[wasm_bindgen_test]
+    async fn _idb_queries() -> Result<(), JsValue> {
+        for id in 1..=5 {
+            let mut tags = vec!["event".to_string()];
+            if id % 2 == 1 {
+                tags.push("minion".to_string());
+            }
+            CalendarEvent {
+                id,
+                start: id as f64 * 10.0,
+                kind: id % 2,
+                tags,
+            }
+            .save_to_store()
+            .await?;
+        }
+        let by_start = || IdbQuery::index("by_start");
+        let between = by_start().bound(20.0.into(), 40.0.into());
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(between.clone()).await?),
+            vec![2, 3, 4]
+        );
+        assert_eq!(CalendarEvent::count_from_store(between.clone()).await?, 3);
+        assert_eq!(
+            CalendarEvent::count_from_store(between.offset(1).limit(1)).await?,
+            1
+        );
+        let latest = by_start().lower(30.0.into()).reverse().limit(2);
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(latest).await?),
+            vec![5, 4]
+        );
+        let earliest = by_start().upper(20.0.into());
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(earliest).await?),
+            vec![1, 2]
+        );
+        let page = IdbQuery::all().offset(1).limit(2);
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(page).await?),
+            vec![2, 3]
+        );
+
+        let compound = IdbQuery::index("by_kind_start").bound(
+            js_sys::Array::of2(&1.into(), &0.0.into()).into(),
+            js_sys::Array::of2(&1.into(), &30.0.into()).into(),
+        );
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(compound).await?),
+            vec![1, 3]
+        );
+        let tagged = IdbQuery::index("by_tag").only("minion".into());
+        assert_eq!(
+            ids(CalendarEvent::query_from_store(tagged).await?),
+            vec![1, 3, 5]
+        );
+        assert!(CalendarEvent::query_from_store(IdbQuery::all().limit(0))
+            .await?
+            .is_empty());
+
+        for event in CalendarEvent::retrieve_all_from_store().await? {
+            event.delete_from_store().await?;
+        }
+        assert_eq!(CalendarEvent::count_from_store(IdbQuery::all()).await?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
+
+use gloo::console::error;
+use tokio::sync::OnceCell;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{
+    IdbCursorWithValue, IdbDatabase, IdbIndexParameters, IdbObjectStoreParameters,
+    IdbOpenDbRequest, IdbTransaction, IdbVersionChangeEvent,
+};
+use yew::platform::{pinned::oneshot, spawn_local};
+
+use super::error::MinionsError;
+use super::indexed_db::{IdbIndexConfig, IdbStoreConfig};
+
+thread_local! {
+    /// One open connection per database, shared by every type stored in it.
+    static CONNECTIONS: RefCell<HashMap<&'static str, Rc<OnceCell<IdbDatabase>>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Rewrites one stored record during an upgrade, `None` deletes it.
+pub type RecordTransform = fn(JsValue) -> Result<Option<JsValue>, MinionsError>;
+
+/// One change to the shape of a database. Steps are idempotent, creating
+/// something that exists or deleting something that does not is skipped.
+#[derive(Clone, Debug)]
+pub enum MigrationStep {
+    CreateStore {
+        store: &'static str,
+        key_path: &'static str,
+    },
+    DeleteStore {
+        store: &'static str,
+    },
+    CreateIndex {
+        store: &'static str,
+        index: IdbIndexConfig,
+    },
+    DeleteIndex {
+        store: &'static str,
+        index: &'static str,
+    },
+    /// Runs after the other steps of its version and before any step of the
+    /// next one, so it sees the stores and indexes of its own version.
+    TransformRecords {
+        store: &'static str,
+        transform: RecordTransform,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct IdbMigration {
+    pub version: u32,
+    pub steps: Vec<MigrationStep>,
+}
+impl IdbMigration {
+    /// The schema steps in order, then the record transforms.
+    fn ordered_steps(&self) -> impl Iterator<Item = MigrationStep> + '_ {
+        let is_transform =
+            |step: &&MigrationStep| matches!(step, MigrationStep::TransformRecords { .. });
+        let schema = self.steps.iter().filter(move |step| !is_transform(step));
+        schema
+            .chain(self.steps.iter().filter(is_transform))
+            .cloned()
+    }
+}
+
+/// Every store and index of a database, as the migrations that build it.
+/// Types that share a database must return the same schema.
+///
+/// ```ignore
+/// IdbSchema::new("minions_app")
+///     .migration(1, vec![MigrationStep::CreateStore { store: "notes", key_path: "id" }])
+///     .migration(2, vec![MigrationStep::CreateStore { store: "profiles", key_path: "pubkey" }])
+/// ```
+#[derive(Clone, Debug)]
+pub struct IdbSchema {
+    pub db_name: &'static str,
+    pub version: u32,
+    pub migrations: Vec<IdbMigration>,
+}
+impl IdbSchema {
+    pub fn new(db_name: &'static str) -> Self {
+        Self {
+            db_name,
+            version: 0,
+            migrations: vec![],
+        }
+    }
+    /// Steps applied when upgrading a database from below `version`.
+    pub fn migration(mut self, version: u32, steps: Vec<MigrationStep>) -> Self {
+        self.version = self.version.max(version);
+        self.migrations.push(IdbMigration { version, steps });
+        self.migrations.sort_by_key(|migration| migration.version);
+        self
+    }
+    /// The database of a type that has it to itself, one store and its
+    /// indexes at `config.db_version`.
+    pub fn single_store(config: &IdbStoreConfig) -> Self {
+        let version = config.db_version.max(1);
+        let schema = Self::new(config.db_name).migration(
+            1,
+            vec![MigrationStep::CreateStore {
+                store: config.store_name,
+                key_path: config.document_key,
+            }],
+        );
+        let indexes = config
+            .indexes
+            .iter()
+            .map(|index| MigrationStep::CreateIndex {
+                store: config.store_name,
+                index: *index,
+            })
+            .collect();
+        schema.migration(version, indexes)
+    }
+
+    /// The shared connection to this database, opened and upgraded on first
+    /// use. A connection at an older version is closed and upgraded.
+    /// Fails with `MinionsError::VersionConflict` if the stored database is
+    /// newer, or if another tab keeps an older version open.
+    pub async fn open(&self) -> Result<IdbDatabase, MinionsError> {
+        let db = self.connection().await?;
+        if db.version() as u32 >= self.version {
+            return Ok(db);
+        }
+        db.close();
+        CONNECTIONS.with(|connections| connections.borrow_mut().remove(self.db_name));
+        self.connection().await
+    }
+
+    async fn connection(&self) -> Result<IdbDatabase, MinionsError> {
+        let cell = CONNECTIONS.with(|connections| {
+            connections
+                .borrow_mut()
+                .entry(self.db_name)
+                .or_default()
+                .clone()
+        });
+        cell.get_or_try_init(|| self.connect()).await.cloned()
+    }
+    async fn connect(&self) -> Result<IdbDatabase, MinionsError> {
+        let window = web_sys::window().ok_or(MinionsError::Unavailable("Window".to_string()))?;
+        let idb_factory = window
+            .indexed_db()?
+            .ok_or(MinionsError::Unavailable("IndexedDB".to_string()))?;
+        let open_request = idb_factory.open_with_u32(self.db_name, self.version)?;
+
+        let schema = self.clone();
+        let upgrade_request = open_request.clone();
+        let on_upgrade_needed = Closure::once_into_js(move |event: IdbVersionChangeEvent| {
+            if let Err(e) = schema.upgrade(&upgrade_request, event.old_version() as u32) {
+                error!(&e);
+                if let Some(transaction) = upgrade_request.transaction() {
+                    let _ = transaction.abort();
+                }
+            }
+        });
+        let db_name = self.db_name;
+        let (sender, receiver) = oneshot::channel();
+        let sender = Rc::new(RefCell::new(Some(sender)));
+        let error_sender = sender.clone();
+        let blocked_sender = sender.clone();
+        let on_blocked = Closure::once_into_js(move |_: web_sys::Event| {
+            if let Some(sender) = blocked_sender.borrow_mut().take() {
+                let _ = sender.send(Err(MinionsError::VersionConflict(format!(
+                    "Upgrade of {} is blocked by another tab",
+                    db_name
+                ))));
+            }
+        });
+        let success_request = open_request.clone();
+        let on_success = Closure::once_into_js(move |_: web_sys::Event| {
+            let db = success_request
+                .result()
+                .and_then(|result| result.dyn_into::<IdbDatabase>())
+                .map_err(MinionsError::from);
+            match sender.borrow_mut().take() {
+                Some(sender) => {
+                    let _ = sender.send(db);
+                }
+                // Opened after the caller gave up on a blocked upgrade.
+                None => {
+                    if let Ok(db) = db {
+                        db.close();
+                    }
+                }
+            }
+        });
+        let on_error = Closure::once_into_js(move |event: web_sys::Event| {
+            if let Some(sender) = error_sender.borrow_mut().take() {
+                let _ = sender.send(Err(MinionsError::from(JsValue::from(event))));
+            }
+        });
+        open_request.set_onupgradeneeded(Some(on_upgrade_needed.as_ref().unchecked_ref()));
+        open_request.set_onblocked(Some(on_blocked.as_ref().unchecked_ref()));
+        open_request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+        open_request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        let db = receiver.await??;
+
+        // Another tab wants to upgrade, step aside and reconnect on next use.
+        let closing_db = db.clone();
+        let on_version_change = Closure::once_into_js(move |_: web_sys::Event| {
+            closing_db.close();
+            CONNECTIONS.with(|connections| connections.borrow_mut().remove(db_name));
+        });
+        db.set_onversionchange(Some(on_version_change.as_ref().unchecked_ref()));
+        Ok(db)
+    }
+
+    fn upgrade(&self, request: &IdbOpenDbRequest, old_version: u32) -> Result<(), JsValue> {
+        let db: IdbDatabase = request.result()?.dyn_into()?;
+        let transaction = request
+            .transaction()
+            .ok_or(JsValue::from_str("No upgrade transaction"))?;
+        let steps = self
+            .migrations
+            .iter()
+            .filter(|migration| migration.version > old_version)
+            .filter(|migration| migration.version <= self.version)
+            .flat_map(IdbMigration::ordered_steps)
+            .collect();
+        apply_steps(db, transaction, steps)
+    }
+}
+impl From<IdbStoreConfig> for IdbSchema {
+    fn from(config: IdbStoreConfig) -> Self {
+        Self::single_store(&config)
+    }
+}
+
+/// Applies `steps` in order. A transform walks its records before the steps
+/// after it are applied, all inside the same upgrade transaction.
+fn apply_steps(
+    db: IdbDatabase,
+    transaction: IdbTransaction,
+    mut steps: VecDeque<MigrationStep>,
+) -> Result<(), JsValue> {
+    while let Some(step) = steps.pop_front() {
+        match step {
+            MigrationStep::CreateStore { store, key_path } => {
+                if !db.object_store_names().contains(store) {
+                    let params = IdbObjectStoreParameters::new();
+                    params.set_key_path(&JsValue::from_str(key_path));
+                    db.create_object_store_with_optional_parameters(store, &params)?;
+                }
+            }
+            MigrationStep::DeleteStore { store } => {
+                if db.object_store_names().contains(store) {
+                    db.delete_object_store(store)?;
+                }
+            }
+            MigrationStep::CreateIndex { store, index } => {
+                let store = transaction.object_store(store)?;
+                if !store.index_names().contains(index.name) {
+                    let params = IdbIndexParameters::new();
+                    params.set_unique(index.unique);
+                    params.set_multi_entry(index.multi_entry);
+                    store.create_index_with_str_sequence_and_optional_parameters(
+                        index.name,
+                        &index.key_path_value(),
+                        &params,
+                    )?;
+                }
+            }
+            MigrationStep::DeleteIndex { store, index } => {
+                let store = transaction.object_store(store)?;
+                if store.index_names().contains(index) {
+                    store.delete_index(index)?;
+                }
+            }
+            MigrationStep::TransformRecords { store, transform } => {
+                let next = transaction.clone();
+                return transform_records(&transaction, store, transform, move || {
+                    apply_steps(db, next, steps)
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Walks every record of `store` inside the upgrade transaction, then calls
+/// `then`. A failing transform aborts the upgrade, leaving the database at
+/// its old version.
+fn transform_records(
+    transaction: &IdbTransaction,
+    store: &str,
+    transform: RecordTransform,
+    then: impl FnOnce() -> Result<(), JsValue> + 'static,
+) -> Result<(), JsValue> {
+    let request = transaction.object_store(store)?.open_cursor()?;
+    // Holds the handler until the walk ends, it is dropped outside its own call.
+    let handler: Rc<RefCell<Option<Closure<dyn FnMut(web_sys::Event)>>>> = Rc::default();
+    let own_handler = handler.clone();
+    let cursor_request = request.clone();
+    let aborting = transaction.clone();
+    let mut then = Some(then);
+    let on_event = Closure::<dyn FnMut(web_sys::Event)>::new(move |event: web_sys::Event| {
+        let mut step = || -> Result<bool, JsValue> {
+            if event.type_() == "error" {
+                return Err(event.clone().into());
+            }
+            let result = cursor_request.result()?;
+            if result.is_null() || result.is_undefined() {
+                if let Some(then) = then.take() {
+                    then()?;
+                }
+                return Ok(true);
+            }
+            let cursor: IdbCursorWithValue = result.dyn_into()?;
+            match transform(cursor.value()?)? {
+                Some(value) => cursor.update(&value)?,
+                None => cursor.delete()?,
+            };
+            cursor.continue_()?;
+            Ok(false)
+        };
+        let finished = step().unwrap_or_else(|e| {
+            error!(&e);
+            let _ = aborting.abort();
+            true
+        });
+        if finished {
+            cursor_request.set_onsuccess(None);
+            cursor_request.set_onerror(None);
+            let own_handler = own_handler.clone();
+            spawn_local(async move {
+                own_handler.borrow_mut().take();
+            });
+        }
+    });
+    request.set_onsuccess(Some(on_event.as_ref().unchecked_ref()));
+    request.set_onerror(Some(on_event.as_ref().unchecked_ref()));
+    handler.borrow_mut().replace(on_event);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser_api::indexed_db::test_record;
+    use crate::browser_api::IdbStoreManager;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn shared_schema() -> IdbSchema {
+        IdbSchema::new("test_shared_db")
+            .migration(
+                1,
+                vec![MigrationStep::CreateStore {
+                    store: "notes",
+                    key_path: "id",
+                }],
+            )
+            .migration(
+                2,
+                vec![MigrationStep::CreateStore {
+                    store: "drafts",
+                    key_path: "id",
+                }],
+            )
+    }
+    test_record!(SharedNote, "test_shared_db", "notes", 2, shared_schema());
+    test_record!(SharedDraft, "test_shared_db", "drafts", 2, shared_schema());
+
+    #[wasm_bindgen_test]
+    async fn _idb_shared_database() -> Result<(), JsValue> {
+        let note = SharedNote {
+            id: 1,
+            name: "note".to_string(),
+            shout: None,
+        };
+        let draft = SharedDraft {
+            id: 1,
+            name: "draft".to_string(),
+            shout: None,
+        };
+        note.clone().save_to_store().await?;
+        draft.clone().save_to_store().await?;
+        assert_eq!(
+            SharedNote::retrieve_all_from_store().await?,
+            vec![note.clone()]
+        );
+        assert_eq!(
+            SharedDraft::retrieve_all_from_store().await?,
+            vec![draft.clone()]
+        );
+        let db = SharedNote::request_db_open().await?;
+        assert_eq!(db, SharedDraft::request_db_open().await?);
+        assert_eq!(db.object_store_names().length(), 2);
+        note.delete_from_store().await?;
+        draft.delete_from_store().await?;
+        Ok(())
+    }
+
+    fn shout_names(value: JsValue) -> Result<Option<JsValue>, MinionsError> {
+        let name = js_sys::Reflect::get(&value, &JsValue::from_str("name"))?
+            .as_string()
+            .unwrap_or_default();
+        if name == "obsolete" {
+            return Ok(None);
+        }
+        js_sys::Reflect::set(
+            &value,
+            &JsValue::from_str("shout"),
+            &JsValue::from_str(&name.to_uppercase()),
+        )?;
+        Ok(Some(value))
+    }
+    fn migration_schema_v1() -> IdbSchema {
+        IdbSchema::new("test_migration_db").migration(
+            1,
+            vec![
+                MigrationStep::CreateStore {
+                    store: "items",
+                    key_path: "id",
+                },
+                MigrationStep::CreateStore {
+                    store: "scratch",
+                    key_path: "id",
+                },
+            ],
+        )
+    }
+    fn migration_schema_v2() -> IdbSchema {
+        migration_schema_v1().migration(
+            2,
+            vec![
+                MigrationStep::DeleteStore { store: "scratch" },
+                MigrationStep::CreateIndex {
+                    store: "items",
+                    index: IdbIndexConfig {
+                        name: "by_name",
+                        key_path: &["name"],
+                        unique: false,
+                        multi_entry: false,
+                    },
+                },
+                MigrationStep::TransformRecords {
+                    store: "items",
+                    transform: shout_names,
+                },
+            ],
+        )
+    }
+    test_record!(
+        ItemV1,
+        "test_migration_db",
+        "items",
+        1,
+        migration_schema_v1()
+    );
+    test_record!(
+        ItemV2,
+        "test_migration_db",
+        "items",
+        2,
+        migration_schema_v2()
+    );
+
+    #[wasm_bindgen_test]
+    async fn _idb_schema_migration() -> Result<(), JsValue> {
+        for (id, name) in [(1, "minion"), (2, "obsolete")] {
+            ItemV1 {
+                id,
+                name: name.to_string(),
+                shout: None,
+            }
+            .save_to_store()
+            .await?;
+        }
+        assert_eq!(ItemV1::request_db_open().await?.version(), 1.0);
+
+        let items = ItemV2::retrieve_all_from_store().await?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].shout.as_deref(), Some("MINION"));
+        let db = ItemV2::request_db_open().await?;
+        assert_eq!(db.version(), 2.0);
+        assert!(!db.object_store_names().contains("scratch"));
+        // Types still on the old schema keep working on the upgraded database.
+        assert_eq!(ItemV1::retrieve_all_from_store().await?.len(), 1);
+        for item in items {
+            item.delete_from_store().await?;
+        }
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _idb_schema_transform_order() -> Result<(), JsValue> {
+        // Upgrading from nothing straight to 3, the transform of version 2
+        // still finds the store that version 3 deletes.
+        let schema = IdbSchema::new("test_migration_order_db")
+            .migration(
+                1,
+                vec![MigrationStep::CreateStore {
+                    store: "drafts",
+                    key_path: "id",
+                }],
+            )
+            .migration(
+                2,
+                vec![MigrationStep::TransformRecords {
+                    store: "drafts",
+                    transform: shout_names,
+                }],
+            )
+            .migration(3, vec![MigrationStep::DeleteStore { store: "drafts" }]);
+        let db = schema.open().await?;
+        assert_eq!(db.version(), 3.0);
+        assert!(!db.object_store_names().contains("drafts"));
+        Ok(())
+    }
+}
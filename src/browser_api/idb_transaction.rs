@@ -0,0 +1,277 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{IdbObjectStore, IdbRequest, IdbTransaction, IdbTransactionMode};
+use yew::platform::pinned::oneshot;
+
+use super::error::MinionsError;
+use super::idb_schema::IdbSchema;
+use super::indexed_db::IdbStoreManager;
+
+/// A readwrite transaction over one or more stores of a database. Every
+/// change queued on it is committed together or not at all.
+///
+/// Queue the changes right after opening it, IndexedDB commits a transaction
+/// as soon as it has nothing pending. Dropping it without `commit` rolls back.
+///
+/// ```ignore
+/// let transaction = IdbWriteTransaction::open(app_schema(), &["notes", "profiles"]).await?;
+/// transaction.put_many(notes)?;
+/// transaction.put(profile)?;
+/// transaction.commit().await?;
+/// ```
+pub struct IdbWriteTransaction {
+    db_name: &'static str,
+    transaction: IdbTransaction,
+    done: Option<oneshot::Receiver<Result<(), MinionsError>>>,
+}
+impl IdbWriteTransaction {
+    pub async fn open(schema: IdbSchema, stores: &[&'static str]) -> Result<Self, MinionsError> {
+        let db = schema.open().await?;
+        let store_names: js_sys::Array = stores
+            .iter()
+            .map(|store| JsValue::from_str(store))
+            .collect();
+        let transaction =
+            db.transaction_with_str_sequence_and_mode(&store_names, IdbTransactionMode::Readwrite)?;
+
+        let (sender, receiver) = oneshot::channel();
+        let sender = Rc::new(RefCell::new(Some(sender)));
+        let abort_sender = sender.clone();
+        let on_complete = Closure::once_into_js(move |_: web_sys::Event| {
+            if let Some(sender) = sender.borrow_mut().take() {
+                let _ = sender.send(Ok(()));
+            }
+        });
+        let aborted = transaction.clone();
+        let on_abort = Closure::once_into_js(move |_: web_sys::Event| {
+            let error = match aborted.error() {
+                Some(exception) => MinionsError::from(JsValue::from(exception)),
+                None => MinionsError::Aborted("Transaction rolled back".to_string()),
+            };
+            if let Some(sender) = abort_sender.borrow_mut().take() {
+                let _ = sender.send(Err(error));
+            }
+        });
+        transaction.set_oncomplete(Some(on_complete.as_ref().unchecked_ref()));
+        transaction.set_onabort(Some(on_abort.as_ref().unchecked_ref()));
+        Ok(Self {
+            db_name: schema.db_name,
+            transaction,
+            done: Some(receiver),
+        })
+    }
+    /// A transaction over the store of `T` alone.
+    pub async fn for_store<T: IdbStoreManager>() -> Result<Self, MinionsError> {
+        Self::open(T::schema(), &[T::config().store_name]).await
+    }
+
+    pub fn put<T>(&self, record: T) -> Result<(), MinionsError>
+    where
+        T: IdbStoreManager + Into<JsValue>,
+    {
+        let store = self.store::<T>()?;
+        self.queue(|| store.put(&record.into()))
+    }
+    /// Like `put`, but the commit fails with `MinionsError::AlreadyExists`
+    /// if a record with the same key is already stored.
+    pub fn add<T>(&self, record: T) -> Result<(), MinionsError>
+    where
+        T: IdbStoreManager + Into<JsValue>,
+    {
+        let store = self.store::<T>()?;
+        self.queue(|| store.add(&record.into()))
+    }
+    pub fn put_many<T>(&self, records: impl IntoIterator<Item = T>) -> Result<(), MinionsError>
+    where
+        T: IdbStoreManager + Into<JsValue>,
+    {
+        let store = self.store::<T>()?;
+        for record in records {
+            self.queue(|| store.put(&record.into()))?;
+        }
+        Ok(())
+    }
+    pub fn delete<T: IdbStoreManager>(&self, record: &T) -> Result<(), MinionsError> {
+        let store = self.store::<T>()?;
+        self.queue(|| store.delete(&record.key()))
+    }
+    pub fn delete_many<'a, T>(
+        &self,
+        records: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), MinionsError>
+    where
+        T: IdbStoreManager + 'a,
+    {
+        let store = self.store::<T>()?;
+        for record in records {
+            self.queue(|| store.delete(&record.key()))?;
+        }
+        Ok(())
+    }
+
+    /// Resolves once every change is durable, or with the error that rolled
+    /// them all back, `MinionsError::QuotaExceeded` when the disk is full.
+    pub async fn commit(mut self) -> Result<(), MinionsError> {
+        let done = self.done.take().ok_or(MinionsError::Aborted(
+            "Transaction already finished".to_string(),
+        ))?;
+        // Fails if IndexedDB is already committing on its own, which is fine.
+        let _ = self.transaction.commit();
+        done.await?
+    }
+    /// Throws away every queued change.
+    pub fn rollback(mut self) -> Result<(), MinionsError> {
+        self.done = None;
+        Ok(self.transaction.abort()?)
+    }
+
+    fn store<T: IdbStoreManager>(&self) -> Result<IdbObjectStore, MinionsError> {
+        let config = T::config();
+        if config.db_name != self.db_name {
+            let _ = self.transaction.abort();
+            return Err(MinionsError::NotFound(format!(
+                "{} in {}",
+                config.store_name, self.db_name
+            )));
+        }
+        self.transaction
+            .object_store(config.store_name)
+            .map_err(|e| {
+                let _ = self.transaction.abort();
+                MinionsError::from(e)
+            })
+    }
+    /// A request that fails to queue rolls back everything queued before it.
+    fn queue(
+        &self,
+        request: impl FnOnce() -> Result<IdbRequest, JsValue>,
+    ) -> Result<(), MinionsError> {
+        match request() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let _ = self.transaction.abort();
+                Err(e.into())
+            }
+        }
+    }
+}
+impl Drop for IdbWriteTransaction {
+    fn drop(&mut self) {
+        if self.done.is_some() {
+            let _ = self.transaction.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser_api::indexed_db::test_record;
+    use crate::browser_api::{IdbQuery, MigrationStep};
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn transaction_schema() -> IdbSchema {
+        IdbSchema::new("test_transaction_db").migration(
+            1,
+            vec![
+                MigrationStep::CreateStore {
+                    store: "notes",
+                    key_path: "id",
+                },
+                MigrationStep::CreateStore {
+                    store: "drafts",
+                    key_path: "id",
+                },
+            ],
+        )
+    }
+    test_record!(
+        TransactionNote,
+        "test_transaction_db",
+        "notes",
+        1,
+        transaction_schema()
+    );
+    test_record!(
+        TransactionDraft,
+        "test_transaction_db",
+        "drafts",
+        1,
+        transaction_schema()
+    );
+    test_record!(
+        Elsewhere,
+        "test_transaction_other_db",
+        "items",
+        1,
+        IdbSchema::new("test_transaction_other_db").migration(
+            1,
+            vec![MigrationStep::CreateStore {
+                store: "items",
+                key_path: "id",
+            }],
+        )
+    );
+
+    #[wasm_bindgen_test]
+    async fn _idb_write_transaction() -> Result<(), JsValue> {
+        let notes: Vec<TransactionNote> = (1..=500)
+            .map(|id| TransactionNote {
+                id,
+                name: format!("note {}", id),
+                shout: None,
+            })
+            .collect();
+        let draft = TransactionDraft {
+            id: 1,
+            name: "draft".to_string(),
+            shout: None,
+        };
+        let transaction =
+            IdbWriteTransaction::open(transaction_schema(), &["notes", "drafts"]).await?;
+        transaction.put_many(notes.clone())?;
+        transaction.put(draft.clone())?;
+        transaction.commit().await?;
+        assert_eq!(
+            TransactionNote::count_from_store(IdbQuery::all()).await?,
+            500
+        );
+        assert_eq!(
+            TransactionDraft::retrieve_all_from_store().await?,
+            vec![draft.clone()]
+        );
+
+        // Nothing queued before a failure or a rollback reaches the store.
+        let transaction =
+            IdbWriteTransaction::open(transaction_schema(), &["notes", "drafts"]).await?;
+        transaction.delete(&draft)?;
+        let elsewhere = Elsewhere {
+            id: 1,
+            name: "elsewhere".to_string(),
+            shout: None,
+        };
+        assert!(transaction.put(elsewhere).is_err());
+        assert!(transaction.commit().await.is_err());
+        let transaction = IdbWriteTransaction::for_store::<TransactionNote>().await?;
+        transaction.delete_many(&notes)?;
+        transaction.rollback()?;
+        assert_eq!(
+            TransactionNote::count_from_store(IdbQuery::all()).await?,
+            500
+        );
+        assert_eq!(
+            TransactionDraft::retrieve_all_from_store().await?,
+            vec![draft.clone()]
+        );
+
+        TransactionNote::delete_many_from_store(&notes).await?;
+        assert_eq!(TransactionNote::count_from_store(IdbQuery::all()).await?, 0);
+        TransactionNote::save_many_to_store(notes[..3].to_vec()).await?;
+        assert_eq!(TransactionNote::count_from_store(IdbQuery::all()).await?, 3);
+        TransactionNote::delete_many_from_store(&notes[..3]).await?;
+        draft.delete_from_store().await?;
+        Ok(())
+    }
+}
@@ -1,132 +1,192 @@
 use std::future::Future;
 
-use gloo::console::error;
-use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{IdbObjectStore, IdbTransactionMode};
-use yew::platform::pinned::oneshot::{self};
+
+use super::error::MinionsError;
+use super::idb_cursor::{CursorStream, KeyCursorStream};
+use super::idb_query::{request_result, IdbQuery};
+use super::idb_schema::IdbSchema;
+use super::idb_transaction::IdbWriteTransaction;
 
 pub struct IdbStoreConfig {
     pub db_name: &'static str,
     pub db_version: u32,
     pub store_name: &'static str,
     pub document_key: &'static str,
+    /// Created when the database reaches `db_version`, bump it to add one.
+    pub indexes: &'static [IdbIndexConfig],
+}
+
+/// A secondary index. Key paths may be dotted, `note.kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdbIndexConfig {
+    pub name: &'static str,
+    /// One key path, or several for a compound index queried with array keys.
+    pub key_path: &'static [&'static str],
+    pub unique: bool,
+    /// Indexes every element of an array value on its own. Browsers reject it
+    /// on compound indexes.
+    pub multi_entry: bool,
+}
+impl IdbIndexConfig {
+    pub fn key_path_value(&self) -> JsValue {
+        match self.key_path {
+            [key_path] => JsValue::from_str(key_path),
+            key_paths => key_paths
+                .iter()
+                .map(|key_path| JsValue::from_str(key_path))
+                .collect::<js_sys::Array>()
+                .into(),
+        }
+    }
 }
 
 pub trait IdbStoreManager {
     fn config() -> IdbStoreConfig;
     fn key(&self) -> JsValue;
-    fn save_to_store(self) -> impl Future<Output = Result<(), JsValue>>
+    /// The database `config().store_name` lives in. Override it to share a
+    /// database with other types or to migrate an existing one.
+    fn schema() -> IdbSchema {
+        IdbSchema::single_store(&Self::config())
+    }
+    /// Resolves once the record is committed.
+    fn save_to_store(self) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Into<JsValue> + Sized,
+    {
+        async {
+            let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+            transaction.put(self)?;
+            transaction.commit().await
+        }
+    }
+    /// Saves a record that must not exist yet, fails with
+    /// `MinionsError::AlreadyExists` otherwise.
+    fn add_to_store(self) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Into<JsValue> + Sized,
+    {
+        async {
+            let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+            transaction.add(self)?;
+            transaction.commit().await
+        }
+    }
+    /// Saves every record in one transaction, all of them or none.
+    fn save_many_to_store(records: Vec<Self>) -> impl Future<Output = Result<(), MinionsError>>
     where
         Self: Into<JsValue> + Sized,
     {
         async {
-            let object_store_request = Self::request_store_open().await?;
-            let request = object_store_request.put(self.into().as_ref())?;
-            let req_clone = request.clone();
-            let (sender, receiver) = oneshot::channel();
-            let on_success =
-                Closure::once_into_js(move |_: web_sys::Event| match req_clone.result() {
-                    Ok(_) => {
-                        let _ = sender.send(());
-                    }
-                    Err(e) => {
-                        error!(&e);
-                    }
-                });
-            let on_error = Closure::once_into_js(move |event: web_sys::Event| {
-                error!(&event);
-            });
-            request.set_onsuccess(Some(on_success.dyn_ref().unwrap()));
-            request.set_onerror(Some(on_error.dyn_ref().unwrap()));
-            receiver
-                .await
-                .map_err(|e| JsValue::from_str(&e.to_string()))
-        }
-    }
-    fn retrieve_from_store<T>(key: &JsValue) -> impl Future<Output = Result<T, JsValue>>
+            let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+            transaction.put_many(records)?;
+            transaction.commit().await
+        }
+    }
+    /// Fails with `MinionsError::NotFound` if no record has the key.
+    fn retrieve_from_store<T>(key: &JsValue) -> impl Future<Output = Result<T, MinionsError>>
     where
         T: TryFrom<JsValue> + 'static,
     {
         async move {
             let object_store = Self::request_store_open().await?;
-            let (success_sender, success_receiver) = oneshot::channel::<T>();
-            let request = object_store.get(key)?;
-            let req_clone = request.clone();
-            let on_success =
-                Closure::once_into_js(move |_event: web_sys::Event| match req_clone.result() {
-                    Ok(result) => {
-                        if result.is_null() || result.is_undefined() {
-                            error!("Result is null or undefined");
-                            return;
-                        }
-                        match result.try_into() {
-                            Ok(value) => {
-                                let _ = success_sender.send(value);
-                            }
-                            Err(_) => {
-                                error!("Error converting to T");
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!(&e);
-                    }
-                });
-            let on_error = Closure::once_into_js(move |event: web_sys::Event| {
-                gloo::console::log!("Error retrieving from store");
-                error!(&event);
-            });
-            request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
-            request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
-            success_receiver
-                .await
-                .map_err(|e| JsValue::from_str(&e.to_string()))
-        }
-    }
-    fn retrieve_all_from_store() -> impl Future<Output = Result<Vec<Self>, JsValue>>
+            let result = request_result(&object_store.get(key)?).await?;
+            if result.is_null() || result.is_undefined() {
+                return Err(MinionsError::NotFound(format!(
+                    "{:?} in {}",
+                    key,
+                    Self::config().store_name
+                )));
+            }
+            T::try_from(result)
+                .map_err(|_| MinionsError::Serialization(format!("{:?} does not decode", key)))
+        }
+    }
+    fn retrieve_all_from_store() -> impl Future<Output = Result<Vec<Self>, MinionsError>>
     where
-        Self: TryFrom<JsValue, Error = JsValue> + 'static + serde::de::DeserializeOwned,
+        Self: TryFrom<JsValue, Error = JsValue> + 'static,
     {
         async {
             let object_store = Self::request_store_open().await?;
-            let request = object_store.get_all()?;
-            let req_clone = request.clone();
-            let (sender, receiver) = oneshot::channel();
-            let on_success = Closure::once_into_js(move |_event: web_sys::Event| {
-                let result: JsValue = req_clone.result().unwrap();
-                let js_array: js_sys::Array = result.dyn_into().unwrap();
-                let result: Vec<Self> = js_array
-                    .iter()
-                    .map(|value| {
-                        let value: JsValue = value.into();
-                        value.try_into().unwrap()
-                    })
-                    .collect();
-                let _ = sender.send(result.into());
-            });
-            request.set_onsuccess(Some(on_success.dyn_ref().unwrap()));
-            receiver
-                .await
-                .map_err(|e| JsValue::from_str(&e.to_string()))
-        }
-    }
-    fn delete_from_store(&self) -> impl Future<Output = Result<(), JsValue>> {
+            let values: js_sys::Array = request_result(&object_store.get_all()?)
+                .await?
+                .dyn_into()
+                .map_err(MinionsError::decode)?;
+            values
+                .iter()
+                .map(|value| Self::try_from(value).map_err(MinionsError::decode))
+                .collect()
+        }
+    }
+    /// Records matching `query`, read through an index or key range instead
+    /// of the whole store.
+    fn query_from_store(query: IdbQuery) -> impl Future<Output = Result<Vec<Self>, MinionsError>>
+    where
+        Self: TryFrom<JsValue, Error = JsValue> + 'static,
+    {
+        async move {
+            let object_store = Self::request_store_open().await?;
+            query
+                .values(&object_store)
+                .await?
+                .into_iter()
+                .map(|value| Self::try_from(value).map_err(MinionsError::decode))
+                .collect()
+        }
+    }
+    /// Streams the records matching `query` instead of loading them at once.
+    fn open_cursor(
+        query: IdbQuery,
+    ) -> impl Future<Output = Result<CursorStream<Self>, MinionsError>>
+    where
+        Self: TryFrom<JsValue, Error = JsValue> + Sized,
+    {
+        async move {
+            let object_store = Self::request_store_open().await?;
+            Ok(CursorStream::new(query.open_cursor(&object_store)?, &query))
+        }
+    }
+    /// Streams the primary keys matching `query`.
+    fn open_key_cursor(
+        query: IdbQuery,
+    ) -> impl Future<Output = Result<KeyCursorStream, MinionsError>> {
+        async move {
+            let object_store = Self::request_store_open().await?;
+            Ok(KeyCursorStream::new(
+                query.open_key_cursor(&object_store)?,
+                &query,
+            ))
+        }
+    }
+    fn count_from_store(query: IdbQuery) -> impl Future<Output = Result<u32, MinionsError>> {
+        async move {
+            let object_store = Self::request_store_open().await?;
+            query.count(&object_store).await
+        }
+    }
+    fn delete_from_store(&self) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Sized,
+    {
         async {
-            let object_store_request = Self::request_store_open().await?;
-            let request = object_store_request.delete(&self.key())?;
-            let req_clone = request.clone();
-            let (sender, receiver) = oneshot::channel();
-            let on_success = Closure::once_into_js(move |_event: web_sys::Event| {
-                let _result: JsValue = req_clone.result().unwrap();
-                let _ = sender.send(());
-            });
-            request.set_onsuccess(Some(on_success.dyn_ref().unwrap()));
-            receiver
-                .await
-                .map_err(|e| JsValue::from_str(&e.to_string()))
-        }
-    }
-    fn request_store_open() -> impl Future<Output = Result<IdbObjectStore, JsValue>> {
+            let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+            transaction.delete(self)?;
+            transaction.commit().await
+        }
+    }
+    /// Deletes every record in one transaction, all of them or none.
+    fn delete_many_from_store(records: &[Self]) -> impl Future<Output = Result<(), MinionsError>>
+    where
+        Self: Sized,
+    {
+        async {
+            let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+            transaction.delete_many(records)?;
+            transaction.commit().await
+        }
+    }
+    fn request_store_open() -> impl Future<Output = Result<IdbObjectStore, MinionsError>> {
         async {
             let db = Self::request_db_open().await?;
             let store_name_str = Self::config().store_name;
@@ -136,68 +196,65 @@ pub trait IdbStoreManager {
             Ok(object_store)
         }
     }
-    fn request_db_open() -> impl Future<Output = Result<web_sys::IdbDatabase, JsValue>> {
+    fn request_db_open() -> impl Future<Output = Result<web_sys::IdbDatabase, MinionsError>> {
         async {
-            let window = web_sys::window().ok_or(JsValue::from_str("No window available."))?;
-            let idb_factory = window
-                .indexed_db()?
-                .ok_or(JsValue::from_str("No IndexedDB"))?;
-            let idb_open_request =
-                idb_factory.open_with_u32(Self::config().db_name, Self::config().db_version)?;
-            let on_upgrade_needed = Closure::once_into_js(move |event: web_sys::Event| {
-                let target = event
-                    .target()
-                    .ok_or(JsValue::from_str("Error upgrading database"))?;
-                let db = target
-                    .dyn_into::<web_sys::IdbOpenDbRequest>()?
-                    .result()?
-                    .dyn_into::<web_sys::IdbDatabase>()?;
-                if let Err(e) = Self::create_data_store(db) {
-                    error!(&e);
-                }
-                Ok::<(), JsValue>(())
-            });
-            let on_error = Closure::once_into_js(move |event: web_sys::Event| {
-                error!(&event);
-            });
-
-            let db_handle = idb_open_request.clone();
-            let (sender, receiver) = oneshot::channel();
-            let on_success = Closure::once_into_js(move |_: web_sys::Event| {
-                match db_handle.result() {
-                    Ok(result) => {
-                        if result.is_null() || result.is_undefined() {
-                            return Err(JsValue::from_str("Result is null or undefined"));
-                        }
-                        let db: web_sys::IdbDatabase = result.dyn_into()?;
-                        sender.send(db)?;
-                    }
-                    Err(e) => {
-                        error!(&e);
-                        drop(sender);
-                    }
-                }
-                Ok::<(), JsValue>(())
-            });
-            idb_open_request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
-            idb_open_request.set_onupgradeneeded(Some(on_upgrade_needed.as_ref().unchecked_ref()));
-            idb_open_request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
-            let db = receiver
-                .await
-                .map_err(|e| JsValue::from_str(&e.to_string()))?;
-            Ok(db)
-        }
-    }
-    fn create_data_store(db: web_sys::IdbDatabase) -> Result<(), JsValue> {
-        let user_relay_params = web_sys::IdbObjectStoreParameters::new();
-        user_relay_params.set_key_path(&JsValue::from_str(Self::config().document_key));
-        db.create_object_store_with_optional_parameters(
-            &Self::config().store_name,
-            &user_relay_params,
-        )?;
-        Ok(())
+            let schema = Self::schema();
+            if schema.db_name != Self::config().db_name {
+                return Err(MinionsError::NotFound(format!(
+                    "{} in the schema of {}",
+                    Self::config().db_name,
+                    schema.db_name
+                )));
+            }
+            schema.open().await
+        }
     }
 }
+/// A serde record stored in `$store` of the database described by `$schema`,
+/// for the IndexedDB tests.
+#[cfg(test)]
+macro_rules! test_record {
+    ($name:ident, $db:expr, $store:expr, $version:expr, $schema:expr) => {
+        #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+        struct $name {
+            pub id: u32,
+            pub name: String,
+            #[serde(default)]
+            pub shout: Option<String>,
+        }
+        impl Into<wasm_bindgen::JsValue> for $name {
+            fn into(self) -> wasm_bindgen::JsValue {
+                serde_wasm_bindgen::to_value(&self).unwrap()
+            }
+        }
+        impl TryFrom<wasm_bindgen::JsValue> for $name {
+            type Error = wasm_bindgen::JsValue;
+            fn try_from(value: wasm_bindgen::JsValue) -> Result<Self, Self::Error> {
+                Ok(serde_wasm_bindgen::from_value(value)?)
+            }
+        }
+        impl $crate::browser_api::IdbStoreManager for $name {
+            fn config() -> $crate::browser_api::IdbStoreConfig {
+                $crate::browser_api::IdbStoreConfig {
+                    db_name: $db,
+                    db_version: $version,
+                    store_name: $store,
+                    document_key: "id",
+                    indexes: &[],
+                }
+            }
+            fn key(&self) -> wasm_bindgen::JsValue {
+                wasm_bindgen::JsValue::from(self.id)
+            }
+            fn schema() -> $crate::browser_api::IdbSchema {
+                $schema
+            }
+        }
+    };
+}
+#[cfg(test)]
+pub(crate) use test_record;
+
 #[cfg(test)]
 mod tests {
 
@@ -228,6 +285,7 @@ mod tests {
                 db_version: 1,
                 store_name: "test_store",
                 document_key: "id",
+                indexes: &[],
             }
         }
         fn key(&self) -> JsValue {
@@ -249,6 +307,8 @@ mod tests {
         let _ = retrieved.delete_from_store().await?;
         let new_all = TestStruct::retrieve_all_from_store().await?;
         assert_eq!(new_all.len(), all.len() - 1);
+        let missing = TestStruct::retrieve_from_store::<TestStruct>(&JsValue::from(3)).await;
+        assert!(missing.is_err_and(|e| e.is_not_found()));
         Ok(())
     }
 }
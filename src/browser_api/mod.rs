@@ -1,12 +1,24 @@
 mod crypto;
+mod encrypted_idb;
+mod error;
 mod geolocation;
 mod html;
+mod idb_cursor;
+mod idb_query;
+mod idb_schema;
+mod idb_transaction;
 mod indexed_db;
 mod service_worker;
 
-pub use crypto::BrowserCrypto;
+pub use crypto::{BrowserCrypto, WrappedSecret};
+pub use encrypted_idb::*;
+pub use error::MinionsError;
 pub use geolocation::{GeolocationPosition, GeolocationCoordinates};
 pub use html::{HtmlDocument, HtmlForm};
+pub use idb_cursor::*;
+pub use idb_query::*;
+pub use idb_schema::*;
+pub use idb_transaction::IdbWriteTransaction;
 pub use indexed_db::*;
 pub use service_worker::AppServiceWorker;
 
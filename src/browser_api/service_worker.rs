@@ -1,15 +1,15 @@
-use wasm_bindgen::JsValue;
+use super::error::MinionsError;
 
 pub struct AppServiceWorker {
     sw: web_sys::ServiceWorkerContainer,
 }
 impl AppServiceWorker {
-    pub fn new() -> Result<Self, JsValue> {
-        let window = web_sys::window().ok_or("No window")?;
+    pub fn new() -> Result<Self, MinionsError> {
+        let window = web_sys::window().ok_or(MinionsError::Unavailable("Window".to_string()))?;
         let sw = window.navigator().service_worker();
         Ok(Self { sw })
     }
-    pub async fn install(&self, file_path: &str) -> Result<(), JsValue> {
+    pub async fn install(&self, file_path: &str) -> Result<(), MinionsError> {
         let register = self.sw.register(file_path);
         let register = wasm_bindgen_futures::JsFuture::from(register).await?;
         gloo::console::info!(register);
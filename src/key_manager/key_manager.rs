@@ -1,74 +1,465 @@
 use std::rc::Rc;
+use wasm_bindgen::JsValue;
 use yew::{platform::spawn_local, prelude::*};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+use super::nip46::Nip46Signer;
+use super::nostr_id::{parse_secret_key, ActiveIdentity, UserIdentity};
+use super::signer::{Nip07Signer, NostrSigner};
+use crate::browser_api::IdbStoreManager;
+use crate::relay_pool::NostrProfile;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct NostrId {
     has_loaded: bool,
-    identity: Option<super::nostr_id::UserIdentity>,
-    keys: Option<nostro2::userkeys::UserKeys>,
+    accounts: Vec<UserIdentity>,
+    identity: Option<UserIdentity>,
+    signer: Option<NostrSigner>,
+    profile: Option<NostrProfile>,
+    extension_available: bool,
 }
 impl NostrId {
     pub fn finished_loading(&self) -> bool {
         self.has_loaded
     }
+    /// Local keys, `None` when locked or when an extension or bunker signs for us.
     pub fn get_nostr_key(&self) -> Option<nostro2::userkeys::UserKeys> {
-        self.keys.clone()
+        match &self.signer {
+            Some(NostrSigner::Local(keys)) => Some(keys.clone()),
+            _ => None,
+        }
+    }
+    pub fn get_signer(&self) -> Option<NostrSigner> {
+        self.signer.clone()
     }
-    pub fn get_identity(&self) -> Option<super::nostr_id::UserIdentity> {
+    pub fn get_identity(&self) -> Option<UserIdentity> {
         self.identity.clone()
     }
+    /// Kind 0 profile of the active account, filled in by the `RelayProvider`.
+    pub fn get_profile(&self) -> Option<NostrProfile> {
+        self.profile
+            .clone()
+            .filter(|profile| Some(&profile.pubkey) == self.active_pubkey().as_ref())
+    }
+    /// Every stored account, active or not.
+    pub fn accounts(&self) -> &[UserIdentity] {
+        &self.accounts
+    }
+    /// Pubkey of whoever signs right now, known even while the account is locked.
+    pub fn active_pubkey(&self) -> Option<String> {
+        self.signer
+            .as_ref()
+            .map(|signer| signer.pubkey())
+            .or(self.identity.as_ref().map(|identity| identity.get_pubkey()))
+            .filter(|pubkey| !pubkey.is_empty())
+    }
+    /// True while an encrypted identity waits for its passphrase.
+    pub fn is_locked(&self) -> bool {
+        self.identity.is_some() && self.signer.is_none()
+    }
+    /// True if a NIP-07 extension was found on the page.
+    pub fn extension_available(&self) -> bool {
+        self.extension_available
+    }
 }
 
 pub enum NostrIdAction {
     FinishedLoadingKey,
-    LoadIdentity(super::nostr_id::UserIdentity, nostro2::userkeys::UserKeys),
+    LoadAccounts(Vec<UserIdentity>),
+    AddAccount(UserIdentity),
+    RemoveAccount(String),
+    LoadIdentity(UserIdentity, nostro2::userkeys::UserKeys),
+    LoadLockedIdentity(UserIdentity),
+    Unlock(nostro2::userkeys::UserKeys),
+    Lock,
+    Logout,
+    ExtensionDetected,
+    LoadProfile(NostrProfile),
+    UseExtension(Nip07Signer),
+    UseRemoteSigner(Nip46Signer),
+    /// Swaps the account with the given pubkey for its rotated replacement.
+    RotateIdentity(String, UserIdentity, nostro2::userkeys::UserKeys),
+    /// Puts back the signer that was active before a failed switch.
+    RestoreSigner(Option<NostrSigner>),
 }
 impl Reducible for NostrId {
     type Action = NostrIdAction;
 
     fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
         match action {
+            NostrIdAction::LoadAccounts(accounts) => Rc::new(NostrId {
+                accounts,
+                ..(*self).clone()
+            }),
+            NostrIdAction::AddAccount(identity) => Rc::new(NostrId {
+                accounts: self.with_account(identity),
+                ..(*self).clone()
+            }),
+            NostrIdAction::RemoveAccount(pubkey) => {
+                let mut accounts = self.accounts.clone();
+                accounts.retain(|account| account.get_pubkey() != pubkey);
+                let active = self.identity.as_ref().map(|identity| identity.get_pubkey());
+                match active == Some(pubkey) {
+                    true => Rc::new(NostrId {
+                        accounts,
+                        identity: None,
+                        signer: None,
+                        ..(*self).clone()
+                    }),
+                    false => Rc::new(NostrId {
+                        accounts,
+                        ..(*self).clone()
+                    }),
+                }
+            }
             NostrIdAction::LoadIdentity(identity, key) => Rc::new(NostrId {
-                has_loaded: self.has_loaded,
+                accounts: self.with_account(identity.clone()),
                 identity: Some(identity),
-                keys: Some(key),
+                signer: Some(NostrSigner::Local(key)),
+                ..(*self).clone()
+            }),
+            NostrIdAction::RotateIdentity(pubkey, identity, key) => {
+                let mut accounts = self.with_account(identity.clone());
+                accounts.retain(|account| account.get_pubkey() != pubkey);
+                Rc::new(NostrId {
+                    accounts,
+                    identity: Some(identity),
+                    signer: Some(NostrSigner::Local(key)),
+                    ..(*self).clone()
+                })
+            }
+            NostrIdAction::LoadLockedIdentity(identity) => Rc::new(NostrId {
+                accounts: self.with_account(identity.clone()),
+                identity: Some(identity),
+                signer: None,
+                ..(*self).clone()
+            }),
+            NostrIdAction::Unlock(key) => Rc::new(NostrId {
+                signer: Some(NostrSigner::Local(key)),
+                ..(*self).clone()
+            }),
+            NostrIdAction::Lock => Rc::new(NostrId {
+                signer: None,
+                ..(*self).clone()
+            }),
+            NostrIdAction::Logout => Rc::new(NostrId {
+                identity: None,
+                signer: None,
+                ..(*self).clone()
             }),
             NostrIdAction::FinishedLoadingKey => Rc::new(NostrId {
                 has_loaded: true,
-                identity: self.identity.clone(),
-                keys: self.keys.clone(),
+                ..(*self).clone()
+            }),
+            NostrIdAction::ExtensionDetected => Rc::new(NostrId {
+                extension_available: true,
+                ..(*self).clone()
+            }),
+            NostrIdAction::LoadProfile(profile) => Rc::new(NostrId {
+                profile: Some(profile),
+                ..(*self).clone()
+            }),
+            NostrIdAction::UseExtension(signer) => Rc::new(NostrId {
+                signer: Some(NostrSigner::Extension(signer)),
+                ..(*self).clone()
+            }),
+            NostrIdAction::UseRemoteSigner(signer) => Rc::new(NostrId {
+                signer: Some(NostrSigner::Remote(signer)),
+                ..(*self).clone()
+            }),
+            NostrIdAction::RestoreSigner(signer) => Rc::new(NostrId {
+                signer,
+                ..(*self).clone()
             }),
         }
     }
 }
+impl NostrId {
+    /// Accounts with `identity` added, replacing a stored copy or a legacy record.
+    fn with_account(&self, identity: UserIdentity) -> Vec<UserIdentity> {
+        let mut accounts: Vec<UserIdentity> = self
+            .accounts
+            .iter()
+            .filter(|account| account.get_pubkey() != identity.get_pubkey())
+            .filter(|account| !(account.is_legacy() && Some(*account) == self.identity.as_ref()))
+            .cloned()
+            .collect();
+        accounts.push(identity);
+        accounts
+    }
+}
 pub type NostrIdStore = UseReducerHandle<NostrId>;
 
+/// Times the provider looks for `window.nostr`, extensions may inject it late.
+const EXTENSION_DETECTION_ATTEMPTS: u32 = 10;
+const EXTENSION_DETECTION_INTERVAL_MS: u32 = 200;
+
+/// Switches `store` to the NIP-07 extension, asking it for the public key.
+pub async fn connect_extension(store: NostrIdStore) -> Result<(), JsValue> {
+    let signer = Nip07Signer::connect().await?;
+    store.dispatch(NostrIdAction::UseExtension(signer));
+    Ok(())
+}
+
+/// Switches `store` to the NIP-46 bunker behind `uri`. The signer is handed
+/// over first so the `RelayProvider` can carry its requests, then connected.
+/// If connecting fails the previous signer is active again.
+pub async fn connect_bunker(store: NostrIdStore, uri: &str) -> Result<String, JsValue> {
+    let signer = Nip46Signer::from_bunker_uri(uri)?;
+    let previous = store.get_signer();
+    store.dispatch(NostrIdAction::UseRemoteSigner(signer.clone()));
+    let pubkey = match signer.connect().await {
+        Ok(pubkey) => pubkey,
+        Err(e) => {
+            store.dispatch(NostrIdAction::RestoreSigner(previous));
+            return Err(e);
+        }
+    };
+    // Dispatched again so consumers see the user's pubkey.
+    store.dispatch(NostrIdAction::UseRemoteSigner(signer));
+    Ok(pubkey)
+}
+
+/// Unlocks the encrypted identity held by `store` with `passphrase`.
+pub async fn unlock_identity(store: NostrIdStore, passphrase: String) -> Result<(), JsValue> {
+    let identity = store
+        .get_identity()
+        .ok_or(JsValue::from_str("No identity to unlock"))?;
+    let keys = identity.unlock(&passphrase).await?;
+    if identity.is_legacy() {
+        let identity = identity.migrate_legacy(keys.get_public_key()).await?;
+        ActiveIdentity::set(&identity.get_pubkey()).await?;
+        store.dispatch(NostrIdAction::LoadIdentity(identity, keys));
+        return Ok(());
+    }
+    store.dispatch(NostrIdAction::Unlock(keys));
+    Ok(())
+}
+
+/// Makes the stored account `pubkey` the active one. Encrypted accounts are
+/// switched to locked and need [`unlock_identity`].
+pub async fn switch_account(store: NostrIdStore, pubkey: &str) -> Result<(), JsValue> {
+    let identity = store
+        .accounts()
+        .iter()
+        .find(|account| account.get_pubkey() == pubkey)
+        .cloned()
+        .ok_or(JsValue::from_str("Account not found"))?;
+    load_account(store.dispatcher(), identity).await?;
+    ActiveIdentity::set(pubkey).await
+}
+
+/// Stores `identity` as an extra account without switching to it.
+pub async fn add_account(store: NostrIdStore, identity: UserIdentity) -> Result<(), JsValue> {
+    identity.clone().save_to_store().await?;
+    store.dispatch(NostrIdAction::AddAccount(identity));
+    Ok(())
+}
+
+/// Deletes the stored account `pubkey`. Removing the active account signs out.
+pub async fn remove_account(store: NostrIdStore, pubkey: &str) -> Result<(), JsValue> {
+    let identity = store
+        .accounts()
+        .iter()
+        .find(|account| account.get_pubkey() == pubkey)
+        .cloned()
+        .ok_or(JsValue::from_str("Account not found"))?;
+    identity.delete_from_store().await?;
+    if store.get_identity().as_ref() == Some(&identity) {
+        ActiveIdentity::clear().await?;
+    }
+    store.dispatch(NostrIdAction::RemoveAccount(pubkey.to_string()));
+    Ok(())
+}
+
+/// Signs out, stored accounts stay available to switch back to.
+pub async fn logout(store: NostrIdStore) -> Result<(), JsValue> {
+    ActiveIdentity::clear().await?;
+    store.dispatch(NostrIdAction::Logout);
+    Ok(())
+}
+
+/// Wipes the active account's stored key and signs out.
+pub async fn delete_identity(store: NostrIdStore) -> Result<(), JsValue> {
+    let identity = store
+        .get_identity()
+        .ok_or(JsValue::from_str("No identity to delete"))?;
+    remove_account(store, &identity.get_pubkey()).await
+}
+
+/// Creates a new key, stores it under `passphrase` if one is given and signs in with it.
+pub async fn generate_identity(
+    store: NostrIdStore,
+    label: Option<String>,
+    passphrase: Option<String>,
+) -> Result<UserIdentity, JsValue> {
+    let keys = nostro2::userkeys::UserKeys::generate_extractable();
+    activate_keys(store, keys, label, passphrase).await
+}
+
+/// Replaces the active account with a new key and signs in with it. The old
+/// key is deleted, an encrypted account needs its `passphrase`.
+pub async fn rotate_identity(
+    store: NostrIdStore,
+    passphrase: Option<String>,
+) -> Result<UserIdentity, JsValue> {
+    let identity = store
+        .get_identity()
+        .ok_or(JsValue::from_str("No identity to rotate"))?;
+    let (rotated, keys) = identity.rotate(passphrase.as_deref()).await?;
+    ActiveIdentity::set(&rotated.get_pubkey()).await?;
+    store.dispatch(NostrIdAction::RotateIdentity(
+        identity.get_pubkey(),
+        rotated.clone(),
+        keys,
+    ));
+    Ok(rotated)
+}
+
+/// Signs in with an `nsec`, hex or `ncryptsec` key. `password` decrypts an
+/// `ncryptsec` and, when given, also protects the stored copy.
+pub async fn import_identity(
+    store: NostrIdStore,
+    secret: &str,
+    password: Option<String>,
+    label: Option<String>,
+) -> Result<UserIdentity, JsValue> {
+    let keys = parse_secret_key(secret, password.as_deref())?;
+    activate_keys(store, keys, label, password).await
+}
+
+async fn activate_keys(
+    store: NostrIdStore,
+    keys: nostro2::userkeys::UserKeys,
+    label: Option<String>,
+    passphrase: Option<String>,
+) -> Result<UserIdentity, JsValue> {
+    let mut identity = match passphrase.filter(|passphrase| !passphrase.is_empty()) {
+        Some(passphrase) => {
+            UserIdentity::from_keys_with_passphrase(keys.clone(), &passphrase).await?
+        }
+        None => UserIdentity::from_new_keys(keys.clone()).await?,
+    };
+    if let Some(label) = label {
+        identity = identity.with_label(&label).await?;
+    }
+    ActiveIdentity::set(&identity.get_pubkey()).await?;
+    store.dispatch(NostrIdAction::LoadIdentity(identity.clone(), keys));
+    Ok(identity)
+}
+
+async fn load_account(
+    dispatcher: UseReducerDispatcher<NostrId>,
+    identity: UserIdentity,
+) -> Result<(), JsValue> {
+    if identity.is_encrypted() {
+        dispatcher.dispatch(NostrIdAction::LoadLockedIdentity(identity));
+        return Ok(());
+    }
+    let keys = identity.get_user_keys().await?;
+    dispatcher.dispatch(NostrIdAction::LoadIdentity(identity, keys));
+    Ok(())
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct NostrIdProviderProps {
+    pub children: Children,
+    /// Milliseconds an unlocked encrypted identity stays usable, `None` keeps
+    /// it unlocked until the page is closed.
+    #[prop_or(Some(15 * 60 * 1000))]
+    pub session_timeout_ms: Option<u32>,
+}
+
 #[function_component(NostrIdProvider)]
-pub fn key_handler(props: &yew::html::ChildrenProps) -> Html {
-    let ctx = use_reducer(|| NostrId {
-        has_loaded: false,
-        identity: None,
-        keys: None,
-    });
+pub fn key_handler(props: &NostrIdProviderProps) -> Html {
+    let ctx = use_reducer(NostrId::default);
 
-    let ctx_clone = ctx.clone();
+    let dispatcher = ctx.dispatcher();
     use_effect_with((), |_| {
         spawn_local(async move {
-            if let Ok(id) = super::nostr_id::UserIdentity::find_local_identity().await {
-                let keys = id.get_user_keys().await.expect("Error getting user keys");
-                ctx_clone.dispatch(NostrIdAction::LoadIdentity(id, keys));
-                ctx_clone.dispatch(NostrIdAction::FinishedLoadingKey);
-            } else {
-                ctx_clone.dispatch(NostrIdAction::FinishedLoadingKey);
-                gloo::console::error!("Loaded with no keys");
+            match UserIdentity::find_all_identities().await {
+                Ok(accounts) => dispatcher.dispatch(NostrIdAction::LoadAccounts(accounts)),
+                Err(e) => gloo::console::error!("Error loading accounts: {:?}", e),
+            }
+            // With no stored identity the app shows `NostrOnboarding`.
+            if let Ok(id) = UserIdentity::find_local_identity().await {
+                if let Err(e) = load_account(dispatcher.clone(), id).await {
+                    gloo::console::error!("Error getting user keys: {:?}", e);
+                }
+            }
+            dispatcher.dispatch(NostrIdAction::FinishedLoadingKey);
+        });
+        || {}
+    });
+
+    let dispatcher = ctx.dispatcher();
+    use_effect_with((), move |_| {
+        spawn_local(async move {
+            for _ in 0..EXTENSION_DETECTION_ATTEMPTS {
+                if Nip07Signer::is_available() {
+                    dispatcher.dispatch(NostrIdAction::ExtensionDetected);
+                    return;
+                }
+                gloo_timers::future::TimeoutFuture::new(EXTENSION_DETECTION_INTERVAL_MS).await;
             }
         });
         || {}
     });
 
+    let encrypted = ctx
+        .get_identity()
+        .map(|identity| identity.is_encrypted())
+        .unwrap_or_default();
+    let unlocked = ctx.get_nostr_key().is_some();
+    let dispatcher = ctx.dispatcher();
+    use_effect_with(
+        (encrypted && unlocked, props.session_timeout_ms),
+        move |(session_open, timeout)| {
+            let relock = match (session_open, timeout) {
+                (true, Some(timeout)) => {
+                    Some(gloo_timers::callback::Timeout::new(*timeout, move || {
+                        dispatcher.dispatch(NostrIdAction::Lock)
+                    }))
+                }
+                _ => None,
+            };
+            move || drop(relock)
+        },
+    );
+
     html! {
         <ContextProvider<NostrIdStore> context={ctx}>
             {props.children.clone()}
         </ContextProvider<NostrIdStore>>
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn _nostr_id_rotate_identity() -> Result<(), JsValue> {
+        let current = UserIdentity::new_local_identity().await?;
+        let other = UserIdentity::new_local_identity().await?;
+        let keys = current.get_user_keys().await?;
+        let state = Rc::new(NostrId::default())
+            .reduce(NostrIdAction::AddAccount(other.clone()))
+            .reduce(NostrIdAction::LoadIdentity(current.clone(), keys));
+
+        let (rotated, rotated_keys) = current.rotate(None).await?;
+        let state = state.reduce(NostrIdAction::RotateIdentity(
+            current.get_pubkey(),
+            rotated.clone(),
+            rotated_keys.clone(),
+        ));
+        assert_eq!(state.get_identity(), Some(rotated.clone()));
+        assert_eq!(state.get_nostr_key(), Some(rotated_keys));
+        assert_eq!(state.accounts(), &[other.clone(), rotated.clone()]);
+
+        rotated.delete_from_store().await?;
+        other.delete_from_store().await?;
+        Ok(())
+    }
+}
@@ -1,4 +1,20 @@
 pub mod key_manager;
+pub mod nip04;
+pub mod nip05;
+pub mod nip19;
+pub mod nip44;
+pub mod nip46;
+pub mod nip49;
 pub mod nostr_id;
+pub mod onboarding;
+pub mod signer;
 pub use key_manager::*;
+pub use nip04::*;
+pub use nip05::*;
+pub use nip19::*;
+pub use nip44::*;
+pub use nip46::*;
+pub use nip49::*;
 pub use nostr_id::*;
+pub use onboarding::*;
+pub use signer::*;
@@ -0,0 +1,92 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use secp256k1::{ecdh::shared_secret_point, PublicKey, SecretKey};
+use wasm_bindgen::JsValue;
+
+use crate::browser_api::BrowserCrypto;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// X coordinate of the ECDH point between our secret and a hex x-only pubkey.
+pub(crate) fn shared_x(secret_key: &[u8; 32], pubkey: &str) -> Result<[u8; 32], JsValue> {
+    let secret_key =
+        SecretKey::from_slice(secret_key).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mut compressed = [2u8; 33];
+    hex::decode_to_slice(pubkey, &mut compressed[1..])
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let pubkey =
+        PublicKey::from_slice(&compressed).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let point = shared_secret_point(&pubkey, &secret_key);
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&point[..32]);
+    Ok(x)
+}
+
+/// NIP-04 `<ciphertext>?iv=<iv>` for `pubkey`. Deprecated in favor of NIP-44,
+/// kept for clients that only speak NIP-04.
+pub fn nip04_encrypt(
+    secret_key: &[u8; 32],
+    pubkey: &str,
+    plaintext: &str,
+) -> Result<String, JsValue> {
+    let key = shared_x(secret_key, pubkey)?;
+    let iv = BrowserCrypto::default().random_bytes::<16>()?;
+    let ciphertext = Aes256CbcEnc::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+    Ok(format!(
+        "{}?iv={}",
+        STANDARD.encode(ciphertext),
+        STANDARD.encode(iv)
+    ))
+}
+
+pub fn nip04_decrypt(
+    secret_key: &[u8; 32],
+    pubkey: &str,
+    payload: &str,
+) -> Result<String, JsValue> {
+    let (ciphertext, iv) = payload
+        .split_once("?iv=")
+        .ok_or(JsValue::from_str("Missing iv in NIP-04 payload"))?;
+    let ciphertext = STANDARD
+        .decode(ciphertext)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let iv: [u8; 16] = STANDARD
+        .decode(iv)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?
+        .try_into()
+        .map_err(|_| JsValue::from_str("Invalid iv length"))?;
+    let key = shared_x(secret_key, pubkey)?;
+    let plaintext = Aes256CbcDec::new(&key.into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+        .map_err(|_| JsValue::from_str("Could not decrypt NIP-04 payload"))?;
+    String::from_utf8(plaintext).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _nip04_roundtrip() {
+        let mut alice = [0u8; 32];
+        alice[31] = 1;
+        let mut bob = [0u8; 32];
+        bob[31] = 2;
+        let alice_pubkey = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let bob_pubkey = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let payload = nip04_encrypt(&alice, bob_pubkey, "Minion secret").unwrap();
+        assert!(payload.contains("?iv="));
+        assert_eq!(
+            nip04_decrypt(&bob, alice_pubkey, &payload).unwrap(),
+            "Minion secret"
+        );
+        assert_ne!(
+            nip04_decrypt(&bob, bob_pubkey, &payload).ok().as_deref(),
+            Some("Minion secret")
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{cell::RefCell, collections::HashMap, future::Future, rc::Rc};
+
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{RequestInit, RequestRedirect, Response};
+use yew::{platform::spawn_local, prelude::*};
+
+use crate::relay_pool::NostrProfile;
+
+/// How long a resolved identifier is trusted before it is fetched again.
+pub const NIP05_TTL_MS: f64 = 60.0 * 60.0 * 1000.0;
+/// Failed lookups are retried sooner, the domain may just have been down.
+pub const NIP05_FAILURE_TTL_MS: f64 = 5.0 * 60.0 * 1000.0;
+
+/// A `name@domain` identifier, lowercased. A bare domain means `_@domain`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Nip05Identifier {
+    pub name: String,
+    pub domain: String,
+}
+impl Nip05Identifier {
+    pub fn parse(identifier: &str) -> Result<Self, JsValue> {
+        let identifier = identifier.trim().to_lowercase();
+        let (name, domain) = identifier
+            .split_once('@')
+            .unwrap_or(("_", identifier.as_str()));
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        let valid_domain = domain.contains('.')
+            && !domain.starts_with('.')
+            && domain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-.:".contains(c));
+        if !valid_name || !valid_domain {
+            return Err(JsValue::from_str("Invalid NIP-05 identifier"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            domain: domain.to_string(),
+        })
+    }
+    pub fn url(&self) -> String {
+        format!(
+            "https://{}/.well-known/nostr.json?name={}",
+            self.domain, self.name
+        )
+    }
+}
+impl std::fmt::Display for Nip05Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name.as_str() {
+            "_" => write!(f, "{}", self.domain),
+            name => write!(f, "{}@{}", name, self.domain),
+        }
+    }
+}
+
+/// What a domain's `nostr.json` says about one name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nip05Profile {
+    pub pubkey: String,
+    pub relays: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct NostrJson {
+    #[serde(default)]
+    names: HashMap<String, String>,
+    #[serde(default)]
+    relays: HashMap<String, Vec<String>>,
+}
+
+/// Fetches the body of a `nostr.json`. Swap it out to resolve against
+/// something other than the network.
+pub trait Nip05Fetch {
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String, JsValue>>;
+}
+
+/// `window.fetch`, refusing redirects as NIP-05 requires.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BrowserFetch;
+impl Nip05Fetch for BrowserFetch {
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String, JsValue>> {
+        let url = url.to_string();
+        async move {
+            let window = web_sys::window().ok_or(JsValue::from_str("No window available."))?;
+            let init = RequestInit::new();
+            init.set_redirect(RequestRedirect::Error);
+            let response: Response = JsFuture::from(window.fetch_with_str_and_init(&url, &init))
+                .await?
+                .into();
+            if !response.ok() {
+                return Err(JsValue::from_str(&format!(
+                    "nostr.json returned {}",
+                    response.status()
+                )));
+            }
+            JsFuture::from(response.text()?)
+                .await?
+                .as_string()
+                .ok_or(JsValue::from_str("No response body"))
+        }
+    }
+}
+
+struct CachedLookup {
+    fetched_at: f64,
+    result: Result<Nip05Profile, String>,
+}
+
+/// Resolves and verifies NIP-05 identifiers, caching answers for a TTL.
+/// Clones share the cache.
+pub struct Nip05Resolver<F: Nip05Fetch = BrowserFetch> {
+    fetch: Rc<F>,
+    ttl_ms: f64,
+    failure_ttl_ms: f64,
+    cache: Rc<RefCell<HashMap<Nip05Identifier, CachedLookup>>>,
+}
+impl Default for Nip05Resolver<BrowserFetch> {
+    fn default() -> Self {
+        Self::new(BrowserFetch)
+    }
+}
+impl<F: Nip05Fetch> Clone for Nip05Resolver<F> {
+    fn clone(&self) -> Self {
+        Self {
+            fetch: self.fetch.clone(),
+            ttl_ms: self.ttl_ms,
+            failure_ttl_ms: self.failure_ttl_ms,
+            cache: self.cache.clone(),
+        }
+    }
+}
+impl<F: Nip05Fetch> PartialEq for Nip05Resolver<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.cache, &other.cache)
+    }
+}
+impl<F: Nip05Fetch> Nip05Resolver<F> {
+    pub fn new(fetch: F) -> Self {
+        Self {
+            fetch: Rc::new(fetch),
+            ttl_ms: NIP05_TTL_MS,
+            failure_ttl_ms: NIP05_FAILURE_TTL_MS,
+            cache: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+    pub fn with_ttl(mut self, ttl_ms: f64, failure_ttl_ms: f64) -> Self {
+        self.ttl_ms = ttl_ms;
+        self.failure_ttl_ms = failure_ttl_ms;
+        self
+    }
+
+    /// The pubkey and relay hints behind `identifier`.
+    pub async fn resolve(&self, identifier: &str) -> Result<Nip05Profile, JsValue> {
+        let identifier = Nip05Identifier::parse(identifier)?;
+        let now = js_sys::Date::now();
+        if let Some(cached) = self.cache.borrow().get(&identifier) {
+            let ttl = match cached.result {
+                Ok(_) => self.ttl_ms,
+                Err(_) => self.failure_ttl_ms,
+            };
+            if now - cached.fetched_at < ttl {
+                return cached.result.clone().map_err(|e| JsValue::from_str(&e));
+            }
+        }
+        let result = self.lookup(&identifier).await;
+        self.cache.borrow_mut().insert(
+            identifier,
+            CachedLookup {
+                fetched_at: now,
+                result: result
+                    .clone()
+                    .map_err(|e| e.as_string().unwrap_or(format!("{:?}", e))),
+            },
+        );
+        result
+    }
+    /// True if `identifier` currently points at `pubkey`.
+    pub async fn verify(&self, identifier: &str, pubkey: &str) -> bool {
+        match self.resolve(identifier).await {
+            Ok(profile) => profile.pubkey == pubkey.to_lowercase(),
+            Err(_) => false,
+        }
+    }
+    /// Checks the nip05 a kind 0 profile claims, false if it claims none.
+    pub async fn verify_profile(&self, profile: &NostrProfile) -> bool {
+        match &profile.nip05 {
+            Some(nip05) => self.verify(nip05, &profile.pubkey).await,
+            None => false,
+        }
+    }
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    async fn lookup(&self, identifier: &Nip05Identifier) -> Result<Nip05Profile, JsValue> {
+        let body = self.fetch.fetch(&identifier.url()).await?;
+        let nostr_json: NostrJson =
+            serde_json::from_str(&body).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let pubkey = nostr_json
+            .names
+            .iter()
+            .find(|(name, _)| name.to_lowercase() == identifier.name)
+            .map(|(_, pubkey)| pubkey.to_lowercase())
+            .ok_or(JsValue::from_str(&format!("{} is not listed", identifier)))?;
+        if pubkey.len() != 64 || hex::decode(&pubkey).is_err() {
+            return Err(JsValue::from_str("nostr.json lists an invalid pubkey"));
+        }
+        let relays = nostr_json.relays.get(&pubkey).cloned().unwrap_or_default();
+        Ok(Nip05Profile { pubkey, relays })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nip05Status {
+    /// The profile claims no identifier.
+    None,
+    Checking,
+    Verified,
+    Failed,
+}
+
+/// Verification state of a profile's nip05, for showing badges. Provide a
+/// `Nip05Resolver` as context so a whole directory shares one cache.
+#[hook]
+pub fn use_nip05_status(profile: Option<NostrProfile>) -> Nip05Status {
+    let fallback = use_memo((), |_| Nip05Resolver::default());
+    let resolver = use_context::<Nip05Resolver>().unwrap_or((*fallback).clone());
+    let status = use_state(|| Nip05Status::None);
+    // A lookup only reports back if its claim is still the profile's.
+    let current_claim = use_mut_ref(|| None::<(String, String)>);
+    let setter = status.setter();
+    let claim = profile
+        .as_ref()
+        .and_then(|profile| Some((profile.nip05.clone()?, profile.pubkey.clone())));
+    use_effect_with(claim, move |claim| {
+        *current_claim.borrow_mut() = claim.clone();
+        match claim.clone() {
+            Some((nip05, pubkey)) => {
+                setter.set(Nip05Status::Checking);
+                spawn_local(async move {
+                    let verified = resolver.verify(&nip05, &pubkey).await;
+                    if *current_claim.borrow() != Some((nip05, pubkey)) {
+                        return;
+                    }
+                    match verified {
+                        true => setter.set(Nip05Status::Verified),
+                        false => setter.set(Nip05Status::Failed),
+                    }
+                });
+            }
+            None => setter.set(Nip05Status::None),
+        }
+        || {}
+    });
+    *status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const MINION_PUBKEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    /// Serves `nostr.json` bodies by URL and counts requests.
+    #[derive(Default)]
+    struct StubServer {
+        files: HashMap<String, String>,
+        requests: RefCell<u32>,
+    }
+    impl Nip05Fetch for StubServer {
+        fn fetch(&self, url: &str) -> impl Future<Output = Result<String, JsValue>> {
+            *self.requests.borrow_mut() += 1;
+            let file = self.files.get(url).cloned();
+            async move { file.ok_or(JsValue::from_str("404")) }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn _nip05_identifier() {
+        let identifier = Nip05Identifier::parse("Bob@Example.com").unwrap();
+        assert_eq!(
+            identifier.url(),
+            "https://example.com/.well-known/nostr.json?name=bob"
+        );
+        let root = Nip05Identifier::parse("example.com").unwrap();
+        assert_eq!(root.name, "_");
+        assert_eq!(root.to_string(), "example.com");
+        assert!(Nip05Identifier::parse("bob@").is_err());
+        assert!(Nip05Identifier::parse("b ob@example.com").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn _nip05_resolver() {
+        let mut server = StubServer::default();
+        server.files.insert(
+            "https://example.com/.well-known/nostr.json?name=minion".to_string(),
+            serde_json::json!({
+                "names": { "minion": MINION_PUBKEY },
+                "relays": { MINION_PUBKEY: ["wss://relay.example.com"] },
+            })
+            .to_string(),
+        );
+        let resolver = Nip05Resolver::new(server);
+
+        let profile = resolver.resolve("minion@example.com").await.unwrap();
+        assert_eq!(profile.pubkey, MINION_PUBKEY);
+        assert_eq!(profile.relays, vec!["wss://relay.example.com".to_string()]);
+        assert!(resolver.verify("minion@example.com", MINION_PUBKEY).await);
+        assert!(!resolver.verify("minion@example.com", &"a".repeat(64)).await);
+        assert!(!resolver.verify("nobody@example.com", MINION_PUBKEY).await);
+        // Both verifications of minion@ were answered from the cache.
+        assert_eq!(*resolver.fetch.requests.borrow(), 2);
+
+        let claimed = NostrProfile {
+            pubkey: MINION_PUBKEY.to_string(),
+            nip05: Some("minion@example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(resolver.verify_profile(&claimed).await);
+
+        let resolver = resolver.with_ttl(0.0, 0.0);
+        resolver.resolve("minion@example.com").await.unwrap();
+        assert_eq!(*resolver.fetch.requests.borrow(), 3);
+    }
+}
@@ -0,0 +1,78 @@
+use bech32::{Bech32, Hrp};
+use wasm_bindgen::JsValue;
+
+/// Bech32 encodes 32 bytes of hex under `prefix`, e.g. `npub` or `nsec`.
+pub fn hex_to_bech32(prefix: &str, hex_key: &str) -> Result<String, JsValue> {
+    let bytes = hex::decode(hex_key).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    if bytes.len() != 32 {
+        return Err(JsValue::from_str("Keys must be 32 bytes long"));
+    }
+    encode(prefix, &bytes)
+}
+
+/// Decodes a bech32 key, checking its prefix, back to hex.
+pub fn bech32_to_hex(prefix: &str, bech32_key: &str) -> Result<String, JsValue> {
+    let bytes = decode(prefix, bech32_key)?;
+    if bytes.len() != 32 {
+        return Err(JsValue::from_str("Keys must be 32 bytes long"));
+    }
+    Ok(hex::encode(bytes))
+}
+
+pub fn npub_from_hex(pubkey: &str) -> Result<String, JsValue> {
+    hex_to_bech32("npub", pubkey)
+}
+pub fn npub_to_hex(npub: &str) -> Result<String, JsValue> {
+    bech32_to_hex("npub", npub)
+}
+/// `npub1abcdef…wxyz`, for showing keys that have no name.
+pub fn short_npub(pubkey: &str) -> String {
+    match npub_from_hex(pubkey) {
+        Ok(npub) => format!("{}…{}", &npub[..10], &npub[npub.len() - 4..]),
+        Err(_) => pubkey.chars().take(8).collect(),
+    }
+}
+pub fn nsec_from_hex(secret_key: &str) -> Result<String, JsValue> {
+    hex_to_bech32("nsec", secret_key)
+}
+pub fn nsec_to_hex(nsec: &str) -> Result<String, JsValue> {
+    bech32_to_hex("nsec", nsec)
+}
+
+pub(crate) fn encode(prefix: &str, data: &[u8]) -> Result<String, JsValue> {
+    let hrp = Hrp::parse(prefix).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    bech32::encode::<Bech32>(hrp, data).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+pub(crate) fn decode(prefix: &str, encoded: &str) -> Result<Vec<u8>, JsValue> {
+    let (hrp, data) =
+        bech32::decode(encoded.trim()).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    if hrp.as_str() != prefix {
+        return Err(JsValue::from_str(&format!(
+            "Expected a {} key, got {}",
+            prefix, hrp
+        )));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _nip19_keys() {
+        // Vectors from NIP-19.
+        let pubkey = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        let secret_key = "67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa";
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        assert_eq!(npub_from_hex(pubkey).unwrap(), npub);
+        assert_eq!(npub_to_hex(npub).unwrap(), pubkey);
+        assert_eq!(nsec_from_hex(secret_key).unwrap(), nsec);
+        assert_eq!(nsec_to_hex(nsec).unwrap(), secret_key);
+        assert!(nsec_to_hex(npub).is_err());
+    }
+}
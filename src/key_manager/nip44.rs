@@ -0,0 +1,179 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    ChaCha20,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use wasm_bindgen::JsValue;
+
+use super::nip04::shared_x;
+use crate::browser_api::BrowserCrypto;
+
+const VERSION: u8 = 2;
+const MIN_PLAINTEXT_SIZE: usize = 1;
+const MAX_PLAINTEXT_SIZE: usize = 65_535;
+
+/// Key shared by two parties for every NIP-44 v2 message between them.
+pub fn conversation_key(secret_key: &[u8; 32], pubkey: &str) -> Result<[u8; 32], JsValue> {
+    let shared = shared_x(secret_key, pubkey)?;
+    let (conversation_key, _) = Hkdf::<Sha256>::extract(Some(b"nip44-v2"), &shared);
+    Ok(conversation_key.into())
+}
+
+pub fn nip44_encrypt(
+    secret_key: &[u8; 32],
+    pubkey: &str,
+    plaintext: &str,
+) -> Result<String, JsValue> {
+    let nonce = BrowserCrypto::default().random_bytes::<32>()?;
+    encrypt_with_nonce(&conversation_key(secret_key, pubkey)?, plaintext, &nonce)
+}
+
+pub fn nip44_decrypt(
+    secret_key: &[u8; 32],
+    pubkey: &str,
+    payload: &str,
+) -> Result<String, JsValue> {
+    decrypt(&conversation_key(secret_key, pubkey)?, payload)
+}
+
+pub fn encrypt_with_nonce(
+    conversation_key: &[u8; 32],
+    plaintext: &str,
+    nonce: &[u8; 32],
+) -> Result<String, JsValue> {
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, nonce)?;
+    let mut ciphertext = pad(plaintext)?;
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut ciphertext);
+    let mac = hmac_aad(&hmac_key, &ciphertext, nonce)?
+        .finalize()
+        .into_bytes();
+    let mut payload = Vec::with_capacity(1 + 32 + ciphertext.len() + 32);
+    payload.push(VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&ciphertext);
+    payload.extend_from_slice(&mac);
+    Ok(STANDARD.encode(payload))
+}
+
+pub fn decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String, JsValue> {
+    if payload.is_empty() || payload.starts_with('#') {
+        return Err(JsValue::from_str("Unknown NIP-44 version"));
+    }
+    if !(132..=87_472).contains(&payload.len()) {
+        return Err(JsValue::from_str("Invalid NIP-44 payload size"));
+    }
+    let data = STANDARD
+        .decode(payload)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    if !(99..=65_603).contains(&data.len()) || data[0] != VERSION {
+        return Err(JsValue::from_str("Unknown NIP-44 version"));
+    }
+    let nonce: [u8; 32] = data[1..33].try_into().unwrap();
+    let (ciphertext, mac) = data[33..].split_at(data.len() - 33 - 32);
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, &nonce)?;
+    hmac_aad(&hmac_key, ciphertext, &nonce)?
+        .verify_slice(mac)
+        .map_err(|_| JsValue::from_str("Invalid NIP-44 MAC"))?;
+    let mut padded = ciphertext.to_vec();
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    unpad(&padded)
+}
+
+fn message_keys(
+    conversation_key: &[u8; 32],
+    nonce: &[u8; 32],
+) -> Result<([u8; 32], [u8; 12], [u8; 32]), JsValue> {
+    let hkdf = Hkdf::<Sha256>::from_prk(conversation_key)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mut keys = [0u8; 76];
+    hkdf.expand(nonce, &mut keys)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok((
+        keys[0..32].try_into().unwrap(),
+        keys[32..44].try_into().unwrap(),
+        keys[44..76].try_into().unwrap(),
+    ))
+}
+
+fn hmac_aad(key: &[u8; 32], message: &[u8], aad: &[u8; 32]) -> Result<Hmac<Sha256>, JsValue> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    mac.update(aad);
+    mac.update(message);
+    Ok(mac)
+}
+
+fn padded_len(unpadded_len: usize) -> usize {
+    if unpadded_len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (unpadded_len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((unpadded_len - 1) / chunk + 1)
+}
+
+fn pad(plaintext: &str) -> Result<Vec<u8>, JsValue> {
+    let unpadded = plaintext.as_bytes();
+    if !(MIN_PLAINTEXT_SIZE..=MAX_PLAINTEXT_SIZE).contains(&unpadded.len()) {
+        return Err(JsValue::from_str("Invalid NIP-44 plaintext length"));
+    }
+    let mut padded = Vec::with_capacity(2 + padded_len(unpadded.len()));
+    padded.extend_from_slice(&(unpadded.len() as u16).to_be_bytes());
+    padded.extend_from_slice(unpadded);
+    padded.resize(2 + padded_len(unpadded.len()), 0);
+    Ok(padded)
+}
+
+fn unpad(padded: &[u8]) -> Result<String, JsValue> {
+    let unpadded_len = u16::from_be_bytes([padded[0], padded[1]]) as usize;
+    if unpadded_len < MIN_PLAINTEXT_SIZE || padded.len() != 2 + padded_len(unpadded_len) {
+        return Err(JsValue::from_str("Invalid NIP-44 padding"));
+    }
+    String::from_utf8(padded[2..2 + unpadded_len].to_vec())
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _nip44_vector() {
+        // First encrypt_decrypt vector from the NIP-44 test suite.
+        let mut sec1 = [0u8; 32];
+        sec1[31] = 1;
+        let pub2 = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let key = conversation_key(&sec1, pub2).unwrap();
+        assert_eq!(
+            hex::encode(key),
+            "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d"
+        );
+        let mut nonce = [0u8; 32];
+        nonce[31] = 1;
+        let payload = encrypt_with_nonce(&key, "a", &nonce).unwrap();
+        assert_eq!(
+            payload,
+            "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb"
+        );
+        assert_eq!(decrypt(&key, &payload).unwrap(), "a");
+        assert!(decrypt(&key, "#Atqupco0WyaOW2IGDKcshwxI9xO8HgD/P8Ddt46CbxDbrhdG8VmJZE0UICD06CVvEvXRDnHvdZY3bgUDTDLCpP0").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn _nip44_padding() {
+        assert_eq!(padded_len(1), 32);
+        assert_eq!(padded_len(32), 32);
+        assert_eq!(padded_len(33), 64);
+        assert_eq!(padded_len(257), 320);
+        assert_eq!(padded_len(65_535), 65_536);
+    }
+}
@@ -0,0 +1,534 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use gloo_timers::future::TimeoutFuture;
+use nostro2::{
+    notes::{Note, SignedNote},
+    relays::{NostrFilter, NostrSubscription},
+    userkeys::UserKeys,
+};
+use tokio::sync::oneshot;
+use wasm_bindgen::JsValue;
+use yew::Callback;
+
+use super::nip04::nip04_decrypt;
+use super::nip44::{nip44_decrypt, nip44_encrypt};
+use crate::browser_api::BrowserCrypto;
+use crate::relay_pool::{note_tags, verify_signed_request};
+
+pub const NIP46_KIND: u32 = 24133;
+/// How long a request waits for the remote signer, which may ask its user.
+const REQUEST_TIMEOUT_MS: u32 = 60_000;
+
+/// A parsed `bunker://` or `nostrconnect://` URI.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Nip46Uri {
+    /// Given by the remote signer: its pubkey, relays and an optional secret.
+    Bunker {
+        remote_pubkey: String,
+        relays: Vec<String>,
+        secret: Option<String>,
+    },
+    /// Shown by the client for the remote signer to scan.
+    NostrConnect {
+        client_pubkey: String,
+        relays: Vec<String>,
+        secret: String,
+        name: Option<String>,
+    },
+}
+impl Nip46Uri {
+    pub fn parse(uri: &str) -> Result<Self, JsValue> {
+        let (scheme, rest) = uri
+            .trim()
+            .split_once("://")
+            .ok_or(JsValue::from_str("Not a NIP-46 URI"))?;
+        let (pubkey, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if pubkey.len() != 64 || hex::decode(pubkey).is_err() {
+            return Err(JsValue::from_str("Invalid pubkey in NIP-46 URI"));
+        }
+        let mut relays = Vec::new();
+        let mut params = HashMap::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value: String = js_sys::decode_uri_component(value)
+                .map_err(|_| JsValue::from_str("Invalid NIP-46 URI encoding"))?
+                .into();
+            match key {
+                "relay" => relays.push(value),
+                _ => {
+                    params.insert(key.to_string(), value);
+                }
+            }
+        }
+        if relays.is_empty() {
+            return Err(JsValue::from_str("NIP-46 URI has no relays"));
+        }
+        match scheme {
+            "bunker" => Ok(Nip46Uri::Bunker {
+                remote_pubkey: pubkey.to_string(),
+                relays,
+                secret: params.remove("secret"),
+            }),
+            "nostrconnect" => Ok(Nip46Uri::NostrConnect {
+                client_pubkey: pubkey.to_string(),
+                relays,
+                secret: params
+                    .remove("secret")
+                    .ok_or(JsValue::from_str("nostrconnect URI has no secret"))?,
+                name: params.remove("name"),
+            }),
+            _ => Err(JsValue::from_str("Not a NIP-46 URI")),
+        }
+    }
+    pub fn to_uri(&self) -> String {
+        let encode = |value: &str| String::from(js_sys::encode_uri_component(value));
+        let relays = |relays: &[String]| {
+            relays
+                .iter()
+                .map(|relay| format!("relay={}", encode(relay)))
+                .collect::<Vec<_>>()
+                .join("&")
+        };
+        match self {
+            Nip46Uri::Bunker {
+                remote_pubkey,
+                relays: urls,
+                secret,
+            } => {
+                let secret = secret
+                    .as_ref()
+                    .map(|secret| format!("&secret={}", encode(secret)))
+                    .unwrap_or_default();
+                format!("bunker://{}?{}{}", remote_pubkey, relays(urls), secret)
+            }
+            Nip46Uri::NostrConnect {
+                client_pubkey,
+                relays: urls,
+                secret,
+                name,
+            } => {
+                let name = name
+                    .as_ref()
+                    .map(|name| format!("&name={}", encode(name)))
+                    .unwrap_or_default();
+                format!(
+                    "nostrconnect://{}?{}&secret={}{}",
+                    client_pubkey,
+                    relays(urls),
+                    encode(secret),
+                    name
+                )
+            }
+        }
+    }
+}
+
+/// How the remote signer reaches its relays, provided by `RelayProvider`.
+#[derive(Clone, PartialEq)]
+pub struct Nip46Transport {
+    pub send_note_to: Callback<(SignedNote, Vec<String>)>,
+    pub subscribe_to: Callback<(NostrSubscription, Vec<String>)>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Nip46Request {
+    id: String,
+    method: String,
+    params: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Nip46Response {
+    id: String,
+    #[serde(default)]
+    result: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Nip46State {
+    remote_pubkey: Option<String>,
+    user_pubkey: Option<String>,
+    transport: Option<Nip46Transport>,
+    queued: Vec<SignedNote>,
+    pending: HashMap<String, oneshot::Sender<Result<String, String>>>,
+}
+
+/// A NIP-46 remote signer. Requests are kind 24133 notes signed by a
+/// throwaway client key and NIP-44 encrypted to the bunker, so the user's
+/// secret key never reaches the browser.
+#[derive(Clone)]
+pub struct Nip46Signer {
+    client_keys: UserKeys,
+    relays: Vec<String>,
+    secret: Option<String>,
+    state: Rc<RefCell<Nip46State>>,
+}
+impl PartialEq for Nip46Signer {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+impl Eq for Nip46Signer {}
+impl std::fmt::Debug for Nip46Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Nip46Signer")
+            .field("client_pubkey", &self.client_keys.get_public_key())
+            .field("remote_pubkey", &self.state.borrow().remote_pubkey)
+            .field("relays", &self.relays)
+            .finish()
+    }
+}
+impl Nip46Signer {
+    /// A signer for a `bunker://` URI, connect it with [`Nip46Signer::connect`].
+    pub fn from_bunker_uri(uri: &str) -> Result<Self, JsValue> {
+        match Nip46Uri::parse(uri)? {
+            Nip46Uri::Bunker {
+                remote_pubkey,
+                relays,
+                secret,
+            } => Ok(Self::new(Some(remote_pubkey), relays, secret)),
+            Nip46Uri::NostrConnect { .. } => Err(JsValue::from_str("Expected a bunker:// URI")),
+        }
+    }
+    /// A signer waiting for a bunker to answer the returned `nostrconnect://` URI.
+    pub fn nostrconnect(
+        relays: Vec<String>,
+        name: Option<String>,
+    ) -> Result<(Self, String), JsValue> {
+        let secret = hex::encode(BrowserCrypto::default().random_bytes::<16>()?);
+        let signer = Self::new(None, relays.clone(), Some(secret.clone()));
+        let uri = Nip46Uri::NostrConnect {
+            client_pubkey: signer.client_keys.get_public_key(),
+            relays,
+            secret,
+            name,
+        };
+        Ok((signer, uri.to_uri()))
+    }
+    fn new(remote_pubkey: Option<String>, relays: Vec<String>, secret: Option<String>) -> Self {
+        Self {
+            client_keys: UserKeys::generate_extractable(),
+            relays,
+            secret,
+            state: Rc::new(RefCell::new(Nip46State {
+                remote_pubkey,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn relays(&self) -> &[String] {
+        &self.relays
+    }
+    pub fn client_pubkey(&self) -> String {
+        self.client_keys.get_public_key()
+    }
+    pub fn remote_pubkey(&self) -> Option<String> {
+        self.state.borrow().remote_pubkey.clone()
+    }
+    /// The user's pubkey once connected, empty before that. The bunker's own
+    /// key is not the user's and is never reported as theirs.
+    pub fn pubkey(&self) -> String {
+        self.state.borrow().user_pubkey.clone().unwrap_or_default()
+    }
+
+    /// Hands the signer its relay connection and sends queued requests.
+    pub fn attach(&self, transport: Nip46Transport) {
+        let mut state = self.state.borrow_mut();
+        if state.transport.as_ref() == Some(&transport) {
+            return;
+        }
+        // Only responses addressed to this client, the bunker relay carries
+        // everyone's signer traffic.
+        let subscription = NostrSubscription::new(
+            NostrFilter::default()
+                .new_kind(NIP46_KIND)
+                .new_tag("p", vec![self.client_pubkey()])
+                .new_limit(0),
+        );
+        transport
+            .subscribe_to
+            .emit((subscription, self.relays.clone()));
+        for note in std::mem::take(&mut state.queued) {
+            transport.send_note_to.emit((note, self.relays.clone()));
+        }
+        state.transport = Some(transport);
+    }
+
+    /// Runs `connect` (or waits for the bunker's answer to a nostrconnect URI)
+    /// and fetches the user's pubkey.
+    pub async fn connect(&self) -> Result<String, JsValue> {
+        match self.remote_pubkey() {
+            Some(remote_pubkey) => {
+                let mut params = vec![remote_pubkey];
+                params.extend(self.secret.clone());
+                self.request("connect", params).await?;
+            }
+            None => {
+                let secret = self
+                    .secret
+                    .clone()
+                    .ok_or(JsValue::from_str("No secret to wait for"))?;
+                let (sender, receiver) = oneshot::channel();
+                self.state.borrow_mut().pending.insert(secret, sender);
+                Self::wait(receiver).await?;
+            }
+        }
+        let user_pubkey = self.request("get_public_key", vec![]).await?;
+        self.state.borrow_mut().user_pubkey = Some(user_pubkey.clone());
+        Ok(user_pubkey)
+    }
+    pub async fn get_public_key(&self) -> Result<String, JsValue> {
+        self.request("get_public_key", vec![]).await
+    }
+    /// Fails unless the remote signer signed exactly `note`, with a valid id
+    /// and signature.
+    pub async fn sign_event(&self, note: Note) -> Result<SignedNote, JsValue> {
+        let request =
+            serde_json::to_string(&note).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let signed_note = self.request("sign_event", vec![request]).await?;
+        let signed_note: SignedNote =
+            serde_json::from_str(&signed_note).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        verify_signed_request(&note, &signed_note).map_err(|e| JsValue::from_str(&e))?;
+        Ok(signed_note)
+    }
+    pub async fn nip04_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        self.request(
+            "nip04_encrypt",
+            vec![pubkey.to_string(), plaintext.to_string()],
+        )
+        .await
+    }
+    pub async fn nip04_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        self.request(
+            "nip04_decrypt",
+            vec![pubkey.to_string(), ciphertext.to_string()],
+        )
+        .await
+    }
+    pub async fn nip44_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        self.request(
+            "nip44_encrypt",
+            vec![pubkey.to_string(), plaintext.to_string()],
+        )
+        .await
+    }
+    pub async fn nip44_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        self.request(
+            "nip44_decrypt",
+            vec![pubkey.to_string(), ciphertext.to_string()],
+        )
+        .await
+    }
+
+    /// Feeds a kind 24133 note from the relays to the signer. Notes for other
+    /// clients are ignored.
+    pub fn handle_response(&self, note: &SignedNote) {
+        if let Err(e) = self.try_handle_response(note) {
+            gloo::console::error!("Error reading remote signer response: {:?}", e);
+        }
+    }
+    fn try_handle_response(&self, note: &SignedNote) -> Result<(), JsValue> {
+        let client_pubkey = self.client_keys.get_public_key();
+        let for_us = note_tags(note)
+            .iter()
+            .any(|tag| tag.len() >= 2 && tag[0] == "p" && tag[1] == client_pubkey);
+        if note.get_kind() != NIP46_KIND || !for_us {
+            return Ok(());
+        }
+        let author = note.get_pubkey().to_string();
+        let secret_key = self.client_keys.get_secret_key();
+        let content = note.get_content().to_string();
+        let content = match content.contains("?iv=") {
+            true => nip04_decrypt(&secret_key, &author, &content)?,
+            false => nip44_decrypt(&secret_key, &author, &content)?,
+        };
+        let response: Nip46Response =
+            serde_json::from_str(&content).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut state = self.state.borrow_mut();
+        match &state.remote_pubkey {
+            Some(remote_pubkey) if remote_pubkey != &author => return Ok(()),
+            Some(_) => {}
+            None => {
+                // A nostrconnect bunker introduces itself by echoing our secret.
+                let secret = self.secret.clone().unwrap_or_default();
+                if response.result.as_deref() != Some(secret.as_str()) {
+                    return Ok(());
+                }
+                state.remote_pubkey = Some(author);
+                if let Some(pending) = state.pending.remove(&secret) {
+                    let _ = pending.send(Ok(secret));
+                }
+                return Ok(());
+            }
+        }
+        if response.result.as_deref() == Some("auth_url") {
+            if let Some(url) = &response.error {
+                let _ = web_sys::window().map(|window| window.open_with_url(url));
+            }
+            return Ok(());
+        }
+        if let Some(pending) = state.pending.remove(&response.id) {
+            let result = match response.error {
+                Some(error) if !error.is_empty() => Err(error),
+                _ => Ok(response.result.unwrap_or_default()),
+            };
+            let _ = pending.send(result);
+        }
+        Ok(())
+    }
+
+    async fn request(&self, method: &str, params: Vec<String>) -> Result<String, JsValue> {
+        let remote_pubkey = self
+            .remote_pubkey()
+            .ok_or(JsValue::from_str("Remote signer is not connected"))?;
+        let request = Nip46Request {
+            id: hex::encode(BrowserCrypto::default().random_bytes::<16>()?),
+            method: method.to_string(),
+            params,
+        };
+        let content =
+            serde_json::to_string(&request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let content = nip44_encrypt(&self.client_keys.get_secret_key(), &remote_pubkey, &content)?;
+        let note = self.client_keys.sign_nostr_event(nip46_note(
+            &self.client_pubkey(),
+            &remote_pubkey,
+            content,
+        )?);
+
+        let (sender, receiver) = oneshot::channel();
+        let transport = {
+            let mut state = self.state.borrow_mut();
+            state.pending.insert(request.id.clone(), sender);
+            if state.transport.is_none() {
+                state.queued.push(note.clone());
+            }
+            state.transport.clone()
+        };
+        if let Some(transport) = transport {
+            transport.send_note_to.emit((note, self.relays.clone()));
+        }
+        let result = Self::wait(receiver).await;
+        self.state.borrow_mut().pending.remove(&request.id);
+        result
+    }
+    async fn wait(receiver: oneshot::Receiver<Result<String, String>>) -> Result<String, JsValue> {
+        tokio::select! {
+            response = receiver => match response {
+                Ok(Ok(result)) => Ok(result),
+                Ok(Err(error)) => Err(JsValue::from_str(&error)),
+                Err(_) => Err(JsValue::from_str("Remote signer request was dropped")),
+            },
+            _ = TimeoutFuture::new(REQUEST_TIMEOUT_MS) => {
+                Err(JsValue::from_str("Remote signer did not answer"))
+            }
+        }
+    }
+}
+
+/// An unsigned kind 24133 note for `recipient` carrying encrypted `content`.
+pub fn nip46_note(pubkey: &str, recipient: &str, content: String) -> Result<Note, JsValue> {
+    let mut note = serde_json::to_value(Note::new(pubkey, NIP46_KIND, &content))
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    note["tags"] = serde_json::json!([["p", recipient]]);
+    serde_json::from_value(note).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Answers requests the way a bunker holding `bunker_keys` would.
+    fn stub_bunker(signer: Nip46Signer, bunker_keys: UserKeys) -> Nip46Transport {
+        let send_note_to = Callback::from(move |(note, _): (SignedNote, Vec<String>)| {
+            let client = note.get_pubkey().to_string();
+            let secret_key = bunker_keys.get_secret_key();
+            let request = nip44_decrypt(&secret_key, &client, &note.get_content()).unwrap();
+            let request: Nip46Request = serde_json::from_str(&request).unwrap();
+            let result = match request.method.as_str() {
+                "connect" => "ack".to_string(),
+                "get_public_key" => bunker_keys.get_public_key(),
+                "sign_event" => {
+                    let note: Note = serde_json::from_str(&request.params[0]).unwrap();
+                    serde_json::to_string(&bunker_keys.sign_nostr_event(note)).unwrap()
+                }
+                _ => String::new(),
+            };
+            let response = Nip46Response {
+                id: request.id,
+                result: Some(result),
+                error: None,
+            };
+            let content = nip44_encrypt(
+                &secret_key,
+                &client,
+                &serde_json::to_string(&response).unwrap(),
+            )
+            .unwrap();
+            let response = bunker_keys.sign_nostr_event(
+                nip46_note(&bunker_keys.get_public_key(), &client, content).unwrap(),
+            );
+            let signer = signer.clone();
+            yew::platform::spawn_local(async move { signer.handle_response(&response) });
+        });
+        Nip46Transport {
+            send_note_to,
+            subscribe_to: Callback::noop(),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn _nip46_uri() {
+        let pubkey = "a".repeat(64);
+        let uri = format!(
+            "bunker://{}?relay=wss%3A%2F%2Frelay.example.com&relay=wss://two.example.com&secret=abc",
+            pubkey
+        );
+        assert_eq!(
+            Nip46Uri::parse(&uri).unwrap(),
+            Nip46Uri::Bunker {
+                remote_pubkey: pubkey.clone(),
+                relays: vec![
+                    "wss://relay.example.com".to_string(),
+                    "wss://two.example.com".to_string()
+                ],
+                secret: Some("abc".to_string()),
+            }
+        );
+        let (_, connect_uri) =
+            Nip46Signer::nostrconnect(vec!["wss://relay.example.com".to_string()], None).unwrap();
+        assert!(matches!(
+            Nip46Uri::parse(&connect_uri),
+            Ok(Nip46Uri::NostrConnect { .. })
+        ));
+        assert!(Nip46Uri::parse("bunker://nothex?relay=wss://relay.example.com").is_err());
+        assert!(Nip46Uri::parse(&format!("bunker://{}", pubkey)).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn _nip46_stub_signer() -> Result<(), JsValue> {
+        let bunker_keys = UserKeys::generate_extractable();
+        let uri = format!(
+            "bunker://{}?relay=wss://relay.example.com",
+            bunker_keys.get_public_key()
+        );
+        let signer = Nip46Signer::from_bunker_uri(&uri)?;
+        signer.attach(stub_bunker(signer.clone(), bunker_keys.clone()));
+        assert_eq!(signer.pubkey(), "");
+
+        assert_eq!(signer.connect().await?, bunker_keys.get_public_key());
+        assert_eq!(signer.pubkey(), bunker_keys.get_public_key());
+        let note = Note::new(&signer.pubkey(), 1, "Signed by the bunker");
+        let signed_note = signer.sign_event(note).await?;
+        assert_eq!(
+            signed_note.get_pubkey().to_string(),
+            bunker_keys.get_public_key()
+        );
+        assert_eq!(signed_note.get_content(), "Signed by the bunker");
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use unicode_normalization::UnicodeNormalization;
+use wasm_bindgen::JsValue;
+
+use super::nip19;
+
+const NCRYPTSEC_VERSION: u8 = 0x02;
+const NCRYPTSEC_LENGTH: usize = 91;
+/// Scrypt cost used when exporting, 2^16 rounds take about a second.
+pub const DEFAULT_LOG_N: u8 = 16;
+
+/// How the secret key was handled before it was encrypted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySecurity {
+    Insecure = 0x00,
+    Secure = 0x01,
+    Unknown = 0x02,
+}
+impl TryFrom<u8> for KeySecurity {
+    type Error = JsValue;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(KeySecurity::Insecure),
+            0x01 => Ok(KeySecurity::Secure),
+            0x02 => Ok(KeySecurity::Unknown),
+            _ => Err(JsValue::from_str("Unknown key security byte")),
+        }
+    }
+}
+
+/// Encrypts a 32 byte secret key as a NIP-49 `ncryptsec`.
+pub fn encrypt_secret_key(
+    secret_key: &[u8; 32],
+    password: &str,
+    log_n: u8,
+    salt: [u8; 16],
+    nonce: [u8; 24],
+    security: KeySecurity,
+) -> Result<String, JsValue> {
+    let key = password_key(password, &salt, log_n)?;
+    let aad = [security as u8];
+    let ciphertext = XChaCha20Poly1305::new(&key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: secret_key,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| JsValue::from_str("Error encrypting secret key"))?;
+    let mut data = Vec::with_capacity(NCRYPTSEC_LENGTH);
+    data.push(NCRYPTSEC_VERSION);
+    data.push(log_n);
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&aad);
+    data.extend_from_slice(&ciphertext);
+    nip19::encode("ncryptsec", &data)
+}
+
+/// Decrypts a NIP-49 `ncryptsec`, failing on a wrong password.
+pub fn decrypt_secret_key(
+    ncryptsec: &str,
+    password: &str,
+) -> Result<([u8; 32], KeySecurity), JsValue> {
+    let data = nip19::decode("ncryptsec", ncryptsec)?;
+    if data.len() != NCRYPTSEC_LENGTH || data[0] != NCRYPTSEC_VERSION {
+        return Err(JsValue::from_str("Unsupported ncryptsec"));
+    }
+    let log_n = data[1];
+    let salt = &data[2..18];
+    let nonce = &data[18..42];
+    let security = KeySecurity::try_from(data[42])?;
+    let key = password_key(password, salt, log_n)?;
+    let secret_key = XChaCha20Poly1305::new(&key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: &data[43..],
+                aad: &data[42..43],
+            },
+        )
+        .map_err(|_| JsValue::from_str("Wrong password"))?;
+    let secret_key = secret_key
+        .try_into()
+        .map_err(|_| JsValue::from_str("Invalid secret key length"))?;
+    Ok((secret_key, security))
+}
+
+/// Scrypt key of the NFKC normalized password.
+fn password_key(password: &str, salt: &[u8], log_n: u8) -> Result<[u8; 32], JsValue> {
+    let password: String = password.nfkc().collect();
+    let params =
+        scrypt::Params::new(log_n, 8, 1, 32).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _nip49_ncryptsec() {
+        // Vector from NIP-49.
+        let ncryptsec = "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfqtd67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6ksu8r89qk5z2zxfmu5gv8th8wclt0h4p";
+        let (secret_key, _) = decrypt_secret_key(ncryptsec, "nostr").unwrap();
+        assert_eq!(
+            hex::encode(secret_key),
+            "3501454135014541350145413501453fefb02227e449e57cf4d3a3ce05378683"
+        );
+        assert!(decrypt_secret_key(ncryptsec, "not nostr").is_err());
+
+        let encrypted = encrypt_secret_key(
+            &[9; 32],
+            "\u{212B}\u{2126}\u{1E9B}\u{0323}",
+            4,
+            [1; 16],
+            [2; 24],
+            KeySecurity::Secure,
+        )
+        .unwrap();
+        assert!(encrypted.starts_with("ncryptsec1"));
+        // The password is NFKC normalized, so other encodings of it decrypt too.
+        let (secret_key, security) =
+            decrypt_secret_key(&encrypted, "\u{00C5}\u{03A9}\u{1E69}").unwrap();
+        assert_eq!(secret_key, [9; 32]);
+        assert_eq!(security, KeySecurity::Secure);
+    }
+}
@@ -2,53 +2,255 @@ use nostro2::userkeys::UserKeys;
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::CryptoKey;
 
-use crate::browser_api::{BrowserCrypto, IdbStoreConfig, IdbStoreManager};
+use super::nip19::{npub_from_hex, nsec_from_hex, nsec_to_hex, short_npub};
+use super::nip49::{decrypt_secret_key, encrypt_secret_key, KeySecurity};
+use crate::browser_api::{
+    BrowserCrypto, IdbStoreConfig, IdbStoreManager, IdbWriteTransaction, WrappedSecret,
+};
 
+/// How the secret key is kept at rest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdentitySecret {
+    /// An extractable AES-GCM key holding the raw secret.
+    Plain(CryptoKey),
+    /// The secret encrypted under a passphrase, only usable after unlocking.
+    Encrypted(WrappedSecret),
+}
+
+/// Reads an `nsec`, a hex secret or a NIP-49 `ncryptsec` (which needs `password`).
+pub fn parse_secret_key(secret: &str, password: Option<&str>) -> Result<UserKeys, JsValue> {
+    let secret = secret.trim();
+    let hex_key = if secret.starts_with("ncryptsec1") {
+        let password = password.ok_or(JsValue::from_str("ncryptsec needs its password"))?;
+        hex::encode(decrypt_secret_key(secret, password)?.0)
+    } else if secret.starts_with("nsec1") {
+        nsec_to_hex(secret)?
+    } else {
+        secret.to_string()
+    };
+    UserKeys::new(&hex_key).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Key every identity was stored under before identities were keyed by pubkey.
+const LEGACY_IDENTITY_KEY: &str = "privateKey";
+
+/// One stored account, keyed by its hex pubkey.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UserIdentity {
     pubkey: String,
-    crypto_key: CryptoKey,
+    label: Option<String>,
+    secret: IdentitySecret,
 }
 
 impl UserIdentity {
-    pub async fn find_local_identity() -> Result<Self, JsValue>
-    where
-        Self: IdbStoreManager,
-    {
-        Self::retrieve_from_store::<Self>(&JsValue::from_str("privateKey")).await
+    /// The last active identity, or the first stored one.
+    pub async fn find_local_identity() -> Result<Self, JsValue> {
+        let identities = Self::find_all_identities().await?;
+        let active = ActiveIdentity::find().await;
+        identities
+            .iter()
+            .find(|identity| Some(identity.get_pubkey()) == active)
+            .or(identities.first())
+            .cloned()
+            .ok_or(JsValue::from_str("No stored identity"))
+    }
+    pub async fn find_identity(pubkey: &str) -> Result<Self, JsValue> {
+        Self::find_all_identities()
+            .await?
+            .into_iter()
+            .find(|identity| identity.pubkey == pubkey)
+            .ok_or(JsValue::from_str("Identity not found"))
+    }
+    /// Every stored identity. Plain identities saved under the legacy key are
+    /// moved to their pubkey, encrypted ones move once unlocked. A legacy
+    /// identity that fails to move is logged and left out.
+    pub async fn find_all_identities() -> Result<Vec<Self>, JsValue> {
+        let mut identities = vec![];
+        for identity in Self::retrieve_all_from_store().await? {
+            if !identity.is_legacy() || identity.is_encrypted() {
+                identities.push(identity);
+                continue;
+            }
+            match identity.migrate_plain_legacy().await {
+                Ok(migrated) => identities.push(migrated),
+                Err(e) => gloo::console::error!("Skipping legacy identity:", e),
+            }
+        }
+        Ok(identities)
+    }
+    async fn migrate_plain_legacy(self) -> Result<Self, JsValue> {
+        let pubkey = self.get_user_keys().await?.get_public_key();
+        self.migrate_legacy(pubkey).await
     }
     pub async fn new_local_identity() -> Result<Self, JsValue> {
-        let user_key = UserKeys::generate_extractable();
-        let crypto_key = BrowserCrypto::default()
-            .crypto_key_from_bytes(&user_key.get_secret_key())
-            .await?;
-        let new_identity = UserIdentity {
-            pubkey: "privateKey".to_string(),
-            crypto_key,
-        };
-        new_identity.clone().save_to_store().await?;
-        Ok(new_identity)
+        Self::from_new_keys(UserKeys::generate_extractable()).await
     }
     pub async fn from_new_keys(keys: UserKeys) -> Result<Self, JsValue> {
         let crypto_key: CryptoKey = BrowserCrypto::default()
             .crypto_key_from_bytes(&keys.get_secret_key())
             .await?;
         let user_identity = UserIdentity {
-            pubkey: "privateKey".to_string(),
-            crypto_key,
+            pubkey: keys.get_public_key(),
+            label: None,
+            secret: IdentitySecret::Plain(crypto_key),
         };
         user_identity.clone().save_to_store().await?;
         Ok(user_identity)
     }
-    pub async fn get_user_keys(&self) -> Result<UserKeys, JsValue> {
-        let key = BrowserCrypto::default()
-            .crypto_key_to_hex(self.crypto_key.clone())
+    pub async fn from_nsec(nsec: &str) -> Result<Self, JsValue> {
+        Self::from_new_keys(parse_secret_key(nsec, None)?).await
+    }
+    /// Imports a NIP-49 encrypted key, failing on a wrong password.
+    pub async fn from_ncryptsec(ncryptsec: &str, password: &str) -> Result<Self, JsValue> {
+        Self::from_new_keys(parse_secret_key(ncryptsec, Some(password))?).await
+    }
+    pub async fn to_nsec(&self) -> Result<String, JsValue> {
+        nsec_from_hex(&hex::encode(self.get_user_keys().await?.get_secret_key()))
+    }
+    pub async fn to_npub(&self) -> Result<String, JsValue> {
+        npub_from_hex(&self.get_user_keys().await?.get_public_key())
+    }
+    /// Exports the key as a NIP-49 `ncryptsec` with scrypt cost `2^log_n`.
+    pub async fn to_ncryptsec(&self, password: &str, log_n: u8) -> Result<String, JsValue> {
+        let keys = self.get_user_keys().await?;
+        let crypto = BrowserCrypto::default();
+        encrypt_secret_key(
+            &keys.get_secret_key(),
+            password,
+            log_n,
+            crypto.random_bytes()?,
+            crypto.random_bytes()?,
+            KeySecurity::Unknown,
+        )
+    }
+    /// Stores only the passphrase-wrapped secret, replacing any plain copy.
+    pub async fn from_keys_with_passphrase(
+        keys: UserKeys,
+        passphrase: &str,
+    ) -> Result<Self, JsValue> {
+        let wrapped = BrowserCrypto::default()
+            .wrap_secret(&keys.get_secret_key(), passphrase)
             .await?;
-        Ok(UserKeys::new(&key).map_err(|e| JsValue::from_str(&e.to_string()))?)
+        let user_identity = UserIdentity {
+            pubkey: keys.get_public_key(),
+            label: None,
+            secret: IdentitySecret::Encrypted(wrapped),
+        };
+        user_identity.clone().save_to_store().await?;
+        Ok(user_identity)
+    }
+    /// Encrypts a stored plain identity under `passphrase`.
+    pub async fn protect_with_passphrase(&self, passphrase: &str) -> Result<Self, JsValue> {
+        let keys = self.get_user_keys().await?;
+        let protected = Self::from_keys_with_passphrase(keys, passphrase).await?;
+        match &self.label {
+            Some(label) => protected.with_label(label).await,
+            None => Ok(protected),
+        }
+    }
+    /// Renames the account and saves it.
+    pub async fn with_label(&self, label: &str) -> Result<Self, JsValue> {
+        let labelled = UserIdentity {
+            label: Some(label.to_string()).filter(|label| !label.is_empty()),
+            ..self.clone()
+        };
+        labelled.clone().save_to_store().await?;
+        Ok(labelled)
+    }
+    /// Moves a record saved under the legacy key to `pubkey`.
+    pub(crate) async fn migrate_legacy(self, pubkey: String) -> Result<Self, JsValue> {
+        if !self.is_legacy() {
+            return Ok(self);
+        }
+        // One transaction, a failed save must not lose the legacy record.
+        let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+        transaction.delete(&self)?;
+        let migrated = UserIdentity { pubkey, ..self };
+        transaction.put(migrated.clone())?;
+        transaction.commit().await?;
+        Ok(migrated)
+    }
+    /// Replaces this account with a freshly generated key, keeping its label.
+    /// An encrypted account needs its `passphrase`, which also protects the new key.
+    pub async fn rotate(&self, passphrase: Option<&str>) -> Result<(Self, UserKeys), JsValue> {
+        let keys = UserKeys::generate_extractable();
+        let crypto = BrowserCrypto::default();
+        let secret = match (&self.secret, passphrase) {
+            (IdentitySecret::Plain(_), _) => {
+                IdentitySecret::Plain(crypto.crypto_key_from_bytes(&keys.get_secret_key()).await?)
+            }
+            (IdentitySecret::Encrypted(_), Some(passphrase)) => {
+                // A wrong passphrase must not lock the user out of the new key.
+                self.unlock(passphrase).await?;
+                IdentitySecret::Encrypted(
+                    crypto
+                        .wrap_secret(&keys.get_secret_key(), passphrase)
+                        .await?,
+                )
+            }
+            (IdentitySecret::Encrypted(_), None) => {
+                return Err(JsValue::from_str("Identity is locked"))
+            }
+        };
+        let rotated = UserIdentity {
+            pubkey: keys.get_public_key(),
+            label: self.label.clone(),
+            secret,
+        };
+        // One transaction, a failed save must not lose the current key.
+        let transaction = IdbWriteTransaction::for_store::<Self>().await?;
+        transaction.delete(self)?;
+        transaction.put(rotated.clone())?;
+        transaction.commit().await?;
+        Ok((rotated, keys))
+    }
+    pub(crate) fn is_legacy(&self) -> bool {
+        self.pubkey == LEGACY_IDENTITY_KEY
+    }
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self.secret, IdentitySecret::Encrypted(_))
+    }
+    /// Fails for encrypted identities, use [`UserIdentity::unlock`] instead.
+    pub async fn get_user_keys(&self) -> Result<UserKeys, JsValue> {
+        match &self.secret {
+            IdentitySecret::Plain(crypto_key) => {
+                let key = BrowserCrypto::default()
+                    .crypto_key_to_hex(crypto_key.clone())
+                    .await?;
+                Ok(UserKeys::new(&key).map_err(|e| JsValue::from_str(&e.to_string()))?)
+            }
+            IdentitySecret::Encrypted(_) => Err(JsValue::from_str("Identity is locked")),
+        }
+    }
+    pub async fn unlock(&self, passphrase: &str) -> Result<UserKeys, JsValue> {
+        match &self.secret {
+            IdentitySecret::Plain(_) => self.get_user_keys().await,
+            IdentitySecret::Encrypted(wrapped) => {
+                let secret = BrowserCrypto::default()
+                    .unwrap_secret(wrapped, passphrase)
+                    .await
+                    .map_err(|_| JsValue::from_str("Wrong passphrase"))?;
+                Ok(UserKeys::new(&hex::encode(secret))
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?)
+            }
+        }
     }
     pub fn get_pubkey(&self) -> String {
         self.pubkey.clone()
     }
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+    /// The label, or a shortened npub for unlabelled accounts.
+    pub fn display_name(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.clone();
+        }
+        match self.is_legacy() {
+            true => "Locked account".to_string(),
+            false => short_npub(&self.pubkey),
+        }
+    }
 }
 impl Into<JsValue> for UserIdentity {
     fn into(self) -> JsValue {
@@ -59,12 +261,24 @@ impl Into<JsValue> for UserIdentity {
             &JsValue::from_str(&self.pubkey),
         )
         .unwrap();
-        js_sys::Reflect::set(
-            &obj,
-            &JsValue::from_str("crypto_key"),
-            &self.crypto_key.into(),
-        )
-        .unwrap();
+        if let Some(label) = &self.label {
+            js_sys::Reflect::set(&obj, &JsValue::from_str("label"), &JsValue::from_str(label))
+                .unwrap();
+        }
+        match self.secret {
+            IdentitySecret::Plain(crypto_key) => {
+                js_sys::Reflect::set(&obj, &JsValue::from_str("crypto_key"), &crypto_key.into())
+                    .unwrap();
+            }
+            IdentitySecret::Encrypted(wrapped) => {
+                js_sys::Reflect::set(
+                    &obj,
+                    &JsValue::from_str("wrapped_secret"),
+                    &serde_wasm_bindgen::to_value(&wrapped).unwrap(),
+                )
+                .unwrap();
+            }
+        }
         obj.into()
     }
 }
@@ -75,9 +289,19 @@ impl TryFrom<JsValue> for UserIdentity {
         let pubkey = js_sys::Reflect::get(&obj, &JsValue::from_str("pubkey"))?
             .as_string()
             .ok_or(JsValue::from_str("id not found"))?;
-        let crypto_key = js_sys::Reflect::get(&obj, &JsValue::from_str("crypto_key"))?;
-        let crypto_key = crypto_key.dyn_into::<CryptoKey>()?;
-        Ok(UserIdentity { pubkey, crypto_key })
+        let label = js_sys::Reflect::get(&obj, &JsValue::from_str("label"))?.as_string();
+        let wrapped = js_sys::Reflect::get(&obj, &JsValue::from_str("wrapped_secret"))?;
+        let secret = if wrapped.is_undefined() {
+            let crypto_key = js_sys::Reflect::get(&obj, &JsValue::from_str("crypto_key"))?;
+            IdentitySecret::Plain(crypto_key.dyn_into::<CryptoKey>()?)
+        } else {
+            IdentitySecret::Encrypted(serde_wasm_bindgen::from_value(wrapped)?)
+        };
+        Ok(UserIdentity {
+            pubkey,
+            label,
+            secret,
+        })
     }
 }
 impl IdbStoreManager for UserIdentity {
@@ -87,6 +311,7 @@ impl IdbStoreManager for UserIdentity {
             db_name: "test_db_3",
             db_version: 1,
             document_key: "pubkey",
+            indexes: &[],
         }
     }
     fn key(&self) -> JsValue {
@@ -94,6 +319,68 @@ impl IdbStoreManager for UserIdentity {
     }
 }
 
+/// Remembers which stored identity was active between sessions.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct ActiveIdentity {
+    pub id: String,
+    pub pubkey: String,
+}
+impl ActiveIdentity {
+    const ID: &'static str = "active";
+
+    pub async fn find() -> Option<String> {
+        Self::retrieve_all_from_store()
+            .await
+            .ok()?
+            .into_iter()
+            .find(|active| active.id == Self::ID)
+            .map(|active| active.pubkey)
+    }
+    pub async fn set(pubkey: &str) -> Result<(), JsValue> {
+        ActiveIdentity {
+            id: Self::ID.to_string(),
+            pubkey: pubkey.to_string(),
+        }
+        .save_to_store()
+        .await?;
+        Ok(())
+    }
+    pub async fn clear() -> Result<(), JsValue> {
+        ActiveIdentity {
+            id: Self::ID.to_string(),
+            pubkey: String::new(),
+        }
+        .delete_from_store()
+        .await?;
+        Ok(())
+    }
+}
+impl TryFrom<JsValue> for ActiveIdentity {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for ActiveIdentity {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+impl IdbStoreManager for ActiveIdentity {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 1,
+            db_name: "minions_active_identity",
+            store_name: "active_identity",
+            document_key: "id",
+            indexes: &[],
+        }
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.id)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,9 +390,122 @@ mod tests {
     async fn _user_identity_fb() -> Result<(), JsValue> {
         let user_identity = UserIdentity::new_local_identity().await.unwrap();
         let user_keys = user_identity.get_user_keys().await.unwrap();
-        let user_identity = UserIdentity::find_local_identity().await.unwrap();
+        let user_identity = UserIdentity::find_identity(&user_keys.get_public_key())
+            .await
+            .unwrap();
         let user_keys2 = user_identity.get_user_keys().await.unwrap();
         assert_eq!(user_keys, user_keys2);
         Ok(())
     }
+    #[wasm_bindgen_test]
+    async fn _user_identity_bech32() -> Result<(), JsValue> {
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        let user_identity = UserIdentity::from_nsec(nsec).await?;
+        assert_eq!(user_identity.to_nsec().await?, nsec);
+        let npub = user_identity.to_npub().await?;
+        assert!(npub.starts_with("npub1"));
+
+        let ncryptsec = user_identity.to_ncryptsec("minion password", 4).await?;
+        assert!(UserIdentity::from_ncryptsec(&ncryptsec, "wrong password")
+            .await
+            .is_err());
+        let imported = UserIdentity::from_ncryptsec(&ncryptsec, "minion password").await?;
+        assert_eq!(imported.to_npub().await?, npub);
+        imported.delete_from_store().await?;
+        Ok(())
+    }
+    #[wasm_bindgen_test]
+    async fn _user_identity_passphrase() -> Result<(), JsValue> {
+        let user_keys = UserKeys::generate_extractable();
+        let user_identity =
+            UserIdentity::from_keys_with_passphrase(user_keys.clone(), "minion passphrase").await?;
+        let stored = UserIdentity::find_identity(&user_keys.get_public_key()).await?;
+        assert!(stored.is_encrypted());
+        assert!(stored.get_user_keys().await.is_err());
+        assert!(stored.unlock("wrong passphrase").await.is_err());
+        assert_eq!(stored.unlock("minion passphrase").await?, user_keys);
+        user_identity.delete_from_store().await?;
+        Ok(())
+    }
+    #[wasm_bindgen_test]
+    async fn _user_identity_accounts() -> Result<(), JsValue> {
+        let work = UserIdentity::new_local_identity()
+            .await?
+            .with_label("Work")
+            .await?;
+        let personal = UserIdentity::new_local_identity().await?;
+        let stored = UserIdentity::find_all_identities().await?;
+        assert!(stored.contains(&work));
+        assert!(stored.contains(&personal));
+        assert_eq!(work.display_name(), "Work");
+        assert!(personal.display_name().starts_with("npub1"));
+
+        ActiveIdentity::set(&personal.get_pubkey()).await?;
+        assert_eq!(UserIdentity::find_local_identity().await?, personal);
+        ActiveIdentity::set(&work.get_pubkey()).await?;
+        assert_eq!(UserIdentity::find_local_identity().await?, work);
+
+        work.delete_from_store().await?;
+        personal.delete_from_store().await?;
+        ActiveIdentity::clear().await?;
+        Ok(())
+    }
+    #[wasm_bindgen_test]
+    async fn _user_identity_rotate() -> Result<(), JsValue> {
+        let plain = UserIdentity::new_local_identity()
+            .await?
+            .with_label("Minion")
+            .await?;
+        let (rotated, keys) = plain.rotate(None).await?;
+        assert_ne!(rotated.get_pubkey(), plain.get_pubkey());
+        assert_eq!(rotated.label(), Some("Minion"));
+        assert_eq!(rotated.get_user_keys().await?, keys);
+        assert!(UserIdentity::find_identity(&plain.get_pubkey())
+            .await
+            .is_err());
+        assert_eq!(
+            UserIdentity::find_identity(&keys.get_public_key()).await?,
+            rotated
+        );
+
+        let encrypted = UserIdentity::from_keys_with_passphrase(
+            UserKeys::generate_extractable(),
+            "minion passphrase",
+        )
+        .await?;
+        assert!(encrypted.rotate(None).await.is_err());
+        assert!(encrypted.rotate(Some("wrong passphrase")).await.is_err());
+        assert!(UserIdentity::find_identity(&encrypted.get_pubkey())
+            .await
+            .is_ok());
+        let (rotated_encrypted, keys) = encrypted.rotate(Some("minion passphrase")).await?;
+        assert!(rotated_encrypted.is_encrypted());
+        assert_eq!(rotated_encrypted.unlock("minion passphrase").await?, keys);
+
+        rotated.delete_from_store().await?;
+        rotated_encrypted.delete_from_store().await?;
+        Ok(())
+    }
+    #[wasm_bindgen_test]
+    fn _parse_secret_key() {
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        let keys = parse_secret_key(nsec, None).unwrap();
+        let hex_key = hex::encode(keys.get_secret_key());
+        assert_eq!(parse_secret_key(&hex_key, None).unwrap(), keys);
+        let ncryptsec = encrypt_secret_key(
+            &keys.get_secret_key(),
+            "minion password",
+            4,
+            [7u8; 16],
+            [9u8; 24],
+            KeySecurity::Unknown,
+        )
+        .unwrap();
+        assert!(parse_secret_key(&ncryptsec, None).is_err());
+        assert_eq!(
+            parse_secret_key(&ncryptsec, Some("minion password")).unwrap(),
+            keys
+        );
+        assert!(parse_secret_key("not a key", None).is_err());
+    }
 }
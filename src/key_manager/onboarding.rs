@@ -0,0 +1,283 @@
+use wasm_bindgen::JsValue;
+use yew::{platform::spawn_local, prelude::*};
+
+use super::key_manager::{
+    connect_extension, generate_identity, import_identity, unlock_identity, NostrIdStore,
+};
+use super::nip19::{npub_from_hex, nsec_from_hex};
+use super::nip49::{encrypt_secret_key, KeySecurity, DEFAULT_LOG_N};
+use super::nostr_id::UserIdentity;
+use crate::browser_api::{BrowserCrypto, HtmlForm};
+
+/// Optional form input, empty strings count as not given.
+fn optional_input(form: &HtmlForm, name: &str) -> Option<String> {
+    form.input_value(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct IdentityFormProps {
+    /// Called with the identity once it is stored and active.
+    #[prop_or_default]
+    pub on_done: Callback<UserIdentity>,
+    #[prop_or_default]
+    pub class: Classes,
+}
+
+/// Creates a new key, optionally labelled and protected by a passphrase.
+#[function_component(GenerateIdentityForm)]
+pub fn generate_identity_form(props: &IdentityFormProps) -> Html {
+    let store = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    let error = use_state(|| None::<String>);
+    let error_setter = error.setter();
+    let on_done = props.on_done.clone();
+    let onsubmit = Callback::from(move |e: SubmitEvent| {
+        e.prevent_default();
+        let Ok(form) = HtmlForm::new(e) else {
+            return;
+        };
+        let label = optional_input(&form, "label");
+        let passphrase = optional_input(&form, "passphrase");
+        if passphrase != optional_input(&form, "confirm_passphrase") {
+            error_setter.set(Some("Passphrases do not match".to_string()));
+            return;
+        }
+        let store = store.clone();
+        let error_setter = error_setter.clone();
+        let on_done = on_done.clone();
+        spawn_local(async move {
+            match generate_identity(store, label, passphrase).await {
+                Ok(identity) => on_done.emit(identity),
+                Err(e) => error_setter.set(Some(format!("{:?}", e))),
+            }
+        });
+    });
+    html! {
+        <form class={classes!("flex", "flex-col", "gap-2", props.class.clone())} {onsubmit}>
+            <h3 class="text-lg font-bold">{"Create a new identity"}</h3>
+            <input name="label" type="text" placeholder="Account name (optional)" />
+            <input name="passphrase" type="password" placeholder="Passphrase (optional)" />
+            <input name="confirm_passphrase" type="password" placeholder="Repeat passphrase" />
+            if let Some(error) = error.as_ref() {
+                <p class="text-red-500">{error}</p>
+            }
+            <button type="submit">{"Generate key"}</button>
+        </form>
+    }
+}
+
+/// Signs in with an existing `nsec`, hex or `ncryptsec` key.
+#[function_component(ImportIdentityForm)]
+pub fn import_identity_form(props: &IdentityFormProps) -> Html {
+    let store = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    let error = use_state(|| None::<String>);
+    let error_setter = error.setter();
+    let on_done = props.on_done.clone();
+    let onsubmit = Callback::from(move |e: SubmitEvent| {
+        e.prevent_default();
+        let Ok(form) = HtmlForm::new(e) else {
+            return;
+        };
+        let Some(secret) = optional_input(&form, "secret") else {
+            error_setter.set(Some("Enter your secret key".to_string()));
+            return;
+        };
+        let label = optional_input(&form, "label");
+        let password = optional_input(&form, "password");
+        let store = store.clone();
+        let error_setter = error_setter.clone();
+        let on_done = on_done.clone();
+        spawn_local(async move {
+            match import_identity(store, &secret, password, label).await {
+                Ok(identity) => on_done.emit(identity),
+                Err(e) => error_setter.set(Some(format!("{:?}", e))),
+            }
+        });
+    });
+    html! {
+        <form class={classes!("flex", "flex-col", "gap-2", props.class.clone())} {onsubmit}>
+            <h3 class="text-lg font-bold">{"Import an identity"}</h3>
+            <input name="secret" type="password" placeholder="nsec1…, ncryptsec1… or hex" />
+            <input name="password" type="password" placeholder="Password (ncryptsec or to protect the key)" />
+            <input name="label" type="text" placeholder="Account name (optional)" />
+            if let Some(error) = error.as_ref() {
+                <p class="text-red-500">{error}</p>
+            }
+            <button type="submit">{"Import key"}</button>
+        </form>
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct UnlockIdentityFormProps {
+    #[prop_or_default]
+    pub class: Classes,
+}
+
+/// Asks for the passphrase of the active encrypted identity.
+#[function_component(UnlockIdentityForm)]
+pub fn unlock_identity_form(props: &UnlockIdentityFormProps) -> Html {
+    let store = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    let error = use_state(|| None::<String>);
+    let error_setter = error.setter();
+    let name = store
+        .get_identity()
+        .map(|identity| identity.display_name())
+        .unwrap_or_default();
+    let onsubmit = Callback::from(move |e: SubmitEvent| {
+        e.prevent_default();
+        let Ok(form) = HtmlForm::new(e) else {
+            return;
+        };
+        let passphrase = form.input_value("passphrase").unwrap_or_default();
+        let store = store.clone();
+        let error_setter = error_setter.clone();
+        spawn_local(async move {
+            if let Err(e) = unlock_identity(store, passphrase).await {
+                error_setter.set(Some(format!("{:?}", e)));
+            }
+        });
+    });
+    html! {
+        <form class={classes!("flex", "flex-col", "gap-2", props.class.clone())} {onsubmit}>
+            <h3 class="text-lg font-bold">{format!("Unlock {}", name)}</h3>
+            <input name="passphrase" type="password" placeholder="Passphrase" />
+            if let Some(error) = error.as_ref() {
+                <p class="text-red-500">{error}</p>
+            }
+            <button type="submit">{"Unlock"}</button>
+        </form>
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct BackupIdentityProps {
+    /// scrypt cost of the exported `ncryptsec`, as `2^log_n`.
+    #[prop_or(DEFAULT_LOG_N)]
+    pub log_n: u8,
+    #[prop_or_default]
+    pub class: Classes,
+}
+
+/// Shows the active key as npub, and on request as nsec or password
+/// protected ncryptsec.
+#[function_component(BackupIdentity)]
+pub fn backup_identity(props: &BackupIdentityProps) -> Html {
+    let store = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    let revealed = use_state(|| None::<String>);
+    let error = use_state(|| None::<String>);
+    let Some(keys) = store.get_nostr_key() else {
+        return html! {
+            <div class={props.class.clone()}>
+                <p>{"Unlock a stored account to back it up."}</p>
+            </div>
+        };
+    };
+    let npub = npub_from_hex(&keys.get_public_key()).unwrap_or_default();
+
+    let show_nsec = {
+        let keys = keys.clone();
+        let revealed = revealed.setter();
+        let error = error.setter();
+        Callback::from(
+            move |_| match nsec_from_hex(&hex::encode(keys.get_secret_key())) {
+                Ok(nsec) => revealed.set(Some(nsec)),
+                Err(e) => error.set(Some(format!("{:?}", e))),
+            },
+        )
+    };
+    let log_n = props.log_n;
+    let revealed_setter = revealed.setter();
+    let error_setter = error.setter();
+    let export_ncryptsec = Callback::from(move |e: SubmitEvent| {
+        e.prevent_default();
+        let Ok(form) = HtmlForm::new(e) else {
+            return;
+        };
+        let Some(password) = optional_input(&form, "password") else {
+            error_setter.set(Some("Enter a password for the backup".to_string()));
+            return;
+        };
+        let crypto = BrowserCrypto::default();
+        let ncryptsec = crypto
+            .random_bytes()
+            .map_err(JsValue::from)
+            .and_then(|salt| {
+                encrypt_secret_key(
+                    &keys.get_secret_key(),
+                    &password,
+                    log_n,
+                    salt,
+                    crypto.random_bytes()?,
+                    KeySecurity::Unknown,
+                )
+            });
+        match ncryptsec {
+            Ok(ncryptsec) => revealed_setter.set(Some(ncryptsec)),
+            Err(e) => error_setter.set(Some(format!("{:?}", e))),
+        }
+    });
+    html! {
+        <div class={classes!("flex", "flex-col", "gap-2", props.class.clone())}>
+            <h3 class="text-lg font-bold">{"Back up your key"}</h3>
+            <p class="break-all">{npub}</p>
+            <button onclick={show_nsec}>{"Show secret key"}</button>
+            <form class="flex flex-row gap-2" onsubmit={export_ncryptsec}>
+                <input name="password" type="password" placeholder="Backup password" />
+                <button type="submit">{"Export ncryptsec"}</button>
+            </form>
+            if let Some(secret) = revealed.as_ref() {
+                <p class="break-all font-mono">{secret}</p>
+                <p>{"Keep this somewhere safe, anyone holding it controls your identity."}</p>
+            }
+            if let Some(error) = error.as_ref() {
+                <p class="text-red-500">{error}</p>
+            }
+        </div>
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct NostrOnboardingProps {
+    pub children: Children,
+    #[prop_or_default]
+    pub class: Classes,
+}
+
+/// Renders its children once an identity can sign, and the onboarding forms
+/// (or the unlock form) until then.
+#[function_component(NostrOnboarding)]
+pub fn nostr_onboarding(props: &NostrOnboardingProps) -> Html {
+    let store = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    if !store.finished_loading() {
+        return html! { <div>{"Loading identity..."}</div> };
+    }
+    if store.get_signer().is_some() {
+        return html! { <>{props.children.clone()}</> };
+    }
+    if store.is_locked() {
+        return html! { <UnlockIdentityForm class={props.class.clone()} /> };
+    }
+    let use_extension = {
+        let store = store.clone();
+        Callback::from(move |_| {
+            let store = store.clone();
+            spawn_local(async move {
+                if let Err(e) = connect_extension(store).await {
+                    gloo::console::error!("Error connecting extension: {:?}", e);
+                }
+            });
+        })
+    };
+    html! {
+        <div class={classes!("flex", "flex-col", "gap-4", props.class.clone())}>
+            <GenerateIdentityForm />
+            <ImportIdentityForm />
+            if store.extension_available() {
+                <button onclick={use_extension}>{"Use browser extension"}</button>
+            }
+        </div>
+    }
+}
@@ -0,0 +1,248 @@
+use js_sys::{Array, Function, Promise, Reflect};
+use nostro2::{
+    notes::{Note, SignedNote},
+    userkeys::UserKeys,
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use super::nip04::{nip04_decrypt, nip04_encrypt};
+use super::nip44::{nip44_decrypt, nip44_encrypt};
+use super::nip46::Nip46Signer;
+use crate::relay_pool::verify_signed_request;
+
+/// A NIP-07 signer, the `window.nostr` object injected by extensions such as
+/// Alby or nos2x. The secret key never leaves the extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nip07Signer {
+    pubkey: String,
+}
+impl Nip07Signer {
+    pub fn is_available() -> bool {
+        Self::extension().is_ok()
+    }
+    /// Asks the extension for the user's public key, which may prompt them.
+    pub async fn connect() -> Result<Self, JsValue> {
+        let pubkey = Self::call(&["getPublicKey"], &[])
+            .await?
+            .as_string()
+            .ok_or(JsValue::from_str("Extension returned no public key"))?;
+        Ok(Self { pubkey })
+    }
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+    pub async fn get_public_key(&self) -> Result<String, JsValue> {
+        Self::call(&["getPublicKey"], &[])
+            .await?
+            .as_string()
+            .ok_or(JsValue::from_str("Extension returned no public key"))
+    }
+    /// Fails unless the extension signed exactly `note`, with a valid id
+    /// and signature.
+    pub async fn sign_event(&self, note: Note) -> Result<SignedNote, JsValue> {
+        let event = Self::call(&["signEvent"], &[serde_wasm_bindgen::to_value(&note)?]).await?;
+        let signed_note: SignedNote = serde_wasm_bindgen::from_value(event)?;
+        verify_signed_request(&note, &signed_note).map_err(|e| JsValue::from_str(&e))?;
+        Ok(signed_note)
+    }
+    pub async fn nip04_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        self.cipher(&["nip04", "encrypt"], pubkey, plaintext).await
+    }
+    pub async fn nip04_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        self.cipher(&["nip04", "decrypt"], pubkey, ciphertext).await
+    }
+    pub async fn nip44_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        self.cipher(&["nip44", "encrypt"], pubkey, plaintext).await
+    }
+    pub async fn nip44_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        self.cipher(&["nip44", "decrypt"], pubkey, ciphertext).await
+    }
+
+    async fn cipher(&self, path: &[&str], pubkey: &str, text: &str) -> Result<String, JsValue> {
+        Self::call(path, &[JsValue::from_str(pubkey), JsValue::from_str(text)])
+            .await?
+            .as_string()
+            .ok_or(JsValue::from_str("Extension returned no text"))
+    }
+    fn extension() -> Result<JsValue, JsValue> {
+        let window = web_sys::window().ok_or(JsValue::from_str("No window"))?;
+        let nostr = Reflect::get(&window, &JsValue::from_str("nostr"))?;
+        match nostr.is_object() {
+            true => Ok(nostr),
+            false => Err(JsValue::from_str("No NIP-07 extension found")),
+        }
+    }
+    /// Calls a method of `window.nostr` by path, awaiting the promise it returns.
+    async fn call(path: &[&str], args: &[JsValue]) -> Result<JsValue, JsValue> {
+        let (method, objects) = path.split_last().ok_or(JsValue::from_str("Empty path"))?;
+        let mut this = Self::extension()?;
+        for object in objects {
+            this = Reflect::get(&this, &JsValue::from_str(object))?;
+        }
+        let function: Function = Reflect::get(&this, &JsValue::from_str(method))?
+            .dyn_into()
+            .map_err(|_| JsValue::from_str(&format!("Extension does not support {}", method)))?;
+        let args: Array = args.iter().collect();
+        let result = function.apply(&this, &args)?;
+        match result.dyn_into::<Promise>() {
+            Ok(promise) => JsFuture::from(promise).await,
+            Err(value) => Ok(value),
+        }
+    }
+}
+
+/// Signs and encrypts on behalf of the current identity, either with keys we
+/// hold, through a browser extension or through a NIP-46 remote signer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NostrSigner {
+    Local(UserKeys),
+    Extension(Nip07Signer),
+    Remote(Nip46Signer),
+}
+impl NostrSigner {
+    pub fn pubkey(&self) -> String {
+        match self {
+            NostrSigner::Local(keys) => keys.get_public_key(),
+            NostrSigner::Extension(signer) => signer.pubkey().to_string(),
+            NostrSigner::Remote(signer) => signer.pubkey(),
+        }
+    }
+    pub async fn get_public_key(&self) -> Result<String, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => Ok(keys.get_public_key()),
+            NostrSigner::Extension(signer) => signer.get_public_key().await,
+            NostrSigner::Remote(signer) => signer.get_public_key().await,
+        }
+    }
+    pub async fn sign_event(&self, note: Note) -> Result<SignedNote, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => Ok(keys.sign_nostr_event(note)),
+            NostrSigner::Extension(signer) => signer.sign_event(note).await,
+            NostrSigner::Remote(signer) => signer.sign_event(note).await,
+        }
+    }
+    pub async fn nip04_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => nip04_encrypt(&keys.get_secret_key(), pubkey, plaintext),
+            NostrSigner::Extension(signer) => signer.nip04_encrypt(pubkey, plaintext).await,
+            NostrSigner::Remote(signer) => signer.nip04_encrypt(pubkey, plaintext).await,
+        }
+    }
+    pub async fn nip04_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => nip04_decrypt(&keys.get_secret_key(), pubkey, ciphertext),
+            NostrSigner::Extension(signer) => signer.nip04_decrypt(pubkey, ciphertext).await,
+            NostrSigner::Remote(signer) => signer.nip04_decrypt(pubkey, ciphertext).await,
+        }
+    }
+    pub async fn nip44_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => nip44_encrypt(&keys.get_secret_key(), pubkey, plaintext),
+            NostrSigner::Extension(signer) => signer.nip44_encrypt(pubkey, plaintext).await,
+            NostrSigner::Remote(signer) => signer.nip44_encrypt(pubkey, plaintext).await,
+        }
+    }
+    pub async fn nip44_decrypt(&self, pubkey: &str, ciphertext: &str) -> Result<String, JsValue> {
+        match self {
+            NostrSigner::Local(keys) => nip44_decrypt(&keys.get_secret_key(), pubkey, ciphertext),
+            NostrSigner::Extension(signer) => signer.nip44_decrypt(pubkey, ciphertext).await,
+            NostrSigner::Remote(signer) => signer.nip44_decrypt(pubkey, ciphertext).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// A stand-in for an extension holding `keys`. `signEvent` answers with
+    /// `signed_note` whatever it is asked to sign.
+    fn install_stub_extension(keys: &UserKeys, signed_note: &SignedNote) {
+        Function::new_no_args(&format!(
+            r#"window.nostr = {{
+                getPublicKey: async () => "{pubkey}",
+                signEvent: async (event) => ({signed_note}),
+                nip04: {{
+                    encrypt: async (pubkey, text) => "nip04:" + text,
+                    decrypt: async (pubkey, text) => text.replace("nip04:", ""),
+                }},
+                nip44: {{
+                    encrypt: async (pubkey, text) => "nip44:" + text,
+                    decrypt: async (pubkey, text) => text.replace("nip44:", ""),
+                }},
+            }};"#,
+            pubkey = keys.get_public_key(),
+            signed_note = serde_json::to_string(signed_note).unwrap(),
+        ))
+        .call0(&JsValue::NULL)
+        .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn _nip07_signer() -> Result<(), JsValue> {
+        let keys = UserKeys::generate();
+        let pubkey = keys.get_public_key();
+        let note = Note::new(&pubkey, 1, "Signed by the extension");
+        let expected = keys.sign_nostr_event(note.clone());
+        install_stub_extension(&keys, &expected);
+        assert!(Nip07Signer::is_available());
+        let signer = NostrSigner::Extension(Nip07Signer::connect().await?);
+        assert_eq!(signer.pubkey(), pubkey);
+        assert_eq!(signer.get_public_key().await?, pubkey);
+
+        let signed_note = signer.sign_event(note.clone()).await?;
+        assert_eq!(signed_note.get_content(), "Signed by the extension");
+        assert_eq!(signed_note.get_id(), expected.get_id());
+
+        let encrypted = signer.nip44_encrypt(&pubkey, "hello").await?;
+        assert_eq!(encrypted, "nip44:hello");
+        assert_eq!(signer.nip44_decrypt(&pubkey, &encrypted).await?, "hello");
+        let encrypted = signer.nip04_encrypt(&pubkey, "hello").await?;
+        assert_eq!(signer.nip04_decrypt(&pubkey, &encrypted).await?, "hello");
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _nip07_signer_rejects_bad_events() -> Result<(), JsValue> {
+        let keys = UserKeys::generate();
+        let note = Note::new(&keys.get_public_key(), 1, "Sign exactly this");
+
+        // A signature lifted from another note.
+        let other = keys.sign_nostr_event(Note::new(&keys.get_public_key(), 1, "Other"));
+        let mut forged = serde_json::to_value(keys.sign_nostr_event(note.clone())).unwrap();
+        forged["sig"] = serde_json::to_value(&other).unwrap()["sig"].clone();
+        let forged: SignedNote = serde_json::from_value(forged).unwrap();
+        install_stub_extension(&keys, &forged);
+        let signer = Nip07Signer::connect().await?;
+        assert!(signer.sign_event(note.clone()).await.is_err());
+
+        // Validly signed, but not the note that was asked for.
+        install_stub_extension(&keys, &other);
+        assert!(signer.sign_event(note).await.is_err());
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _local_signer() -> Result<(), JsValue> {
+        let alice = UserKeys::generate_extractable();
+        let bob = UserKeys::generate_extractable();
+        let alice_signer = NostrSigner::Local(alice.clone());
+        let bob_signer = NostrSigner::Local(bob.clone());
+        let encrypted = alice_signer
+            .nip44_encrypt(&bob.get_public_key(), "Minion secret")
+            .await?;
+        assert_eq!(
+            bob_signer
+                .nip44_decrypt(&alice.get_public_key(), &encrypted)
+                .await?,
+            "Minion secret"
+        );
+        let note = Note::new(&alice.get_public_key(), 1, "Signed locally");
+        let signed_note = alice_signer.sign_event(note).await?;
+        assert_eq!(signed_note.get_pubkey().to_string(), alice.get_public_key());
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 pub mod browser_api;
 pub mod key_manager;
+pub mod messaging;
 pub mod relay_pool;
 pub mod router;
 pub mod widgets;
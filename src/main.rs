@@ -1,7 +1,7 @@
 use html::ChildrenProps;
 use minions::{
     key_manager::NostrIdProvider,
-    relay_pool::{RelayPoolTest, RelayProvider, UserRelay},
+    relay_pool::{RelayPoolTest, RelayProvider, RelayToaster, UserRelay},
 };
 use yew::prelude::*;
 
@@ -39,11 +39,12 @@ fn app_context_providers(props: &ChildrenProps) -> Html {
             write: true,
         },
     ];
+    let on_notice = use_memo((), |_| RelayToaster::default().callback());
     html! {
-        <RelayProvider {relays} >
-            <NostrIdProvider>
+        <NostrIdProvider>
+            <RelayProvider {relays} offline_cache=true on_notice={(*on_notice).clone()}>
                 {props.children.clone()}
-            </NostrIdProvider>
-        </RelayProvider>
+            </RelayProvider>
+        </NostrIdProvider>
     }
 }
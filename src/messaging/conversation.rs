@@ -0,0 +1,254 @@
+use std::{collections::HashMap, rc::Rc};
+
+use wasm_bindgen::JsValue;
+use yew::prelude::*;
+
+use super::direct_message::{DirectMessage, MessageProtocol};
+use crate::browser_api::{IdbStoreConfig, IdbStoreManager};
+
+/// Every message exchanged with one counterparty, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Conversation {
+    counterparty: String,
+    messages: Vec<DirectMessage>,
+    last_read: u64,
+}
+impl Conversation {
+    pub fn new(counterparty: &str) -> Self {
+        Self {
+            counterparty: counterparty.to_string(),
+            ..Default::default()
+        }
+    }
+    pub fn counterparty(&self) -> &str {
+        &self.counterparty
+    }
+    pub fn messages(&self) -> &[DirectMessage] {
+        &self.messages
+    }
+    pub fn latest(&self) -> Option<&DirectMessage> {
+        self.messages.last()
+    }
+    /// Messages from the counterparty newer than the last time it was read.
+    pub fn unread_count(&self) -> usize {
+        self.messages
+            .iter()
+            .filter(|message| message.sender == self.counterparty)
+            .filter(|message| message.created_at > self.last_read)
+            .count()
+    }
+    /// NIP-17 unless the counterparty has only ever written to us over NIP-04.
+    pub fn protocol(&self) -> MessageProtocol {
+        let legacy_only = self
+            .messages
+            .iter()
+            .filter(|message| message.sender == self.counterparty)
+            .all(|message| message.protocol == MessageProtocol::Nip04);
+        match legacy_only && !self.messages.is_empty() {
+            true => MessageProtocol::Nip04,
+            false => MessageProtocol::Nip17,
+        }
+    }
+    fn insert(&mut self, message: DirectMessage) -> bool {
+        if self.messages.iter().any(|known| known.id == message.id) {
+            return false;
+        }
+        let position = self
+            .messages
+            .partition_point(|known| known.created_at <= message.created_at);
+        self.messages.insert(position, message);
+        true
+    }
+}
+
+/// Direct messages of the active account grouped by counterparty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Conversations {
+    owner: Option<String>,
+    conversations: HashMap<String, Conversation>,
+}
+impl Conversations {
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+    pub fn get(&self, counterparty: &str) -> Option<&Conversation> {
+        self.conversations.get(counterparty)
+    }
+    /// Conversations with the most recent message first.
+    pub fn by_recency(&self) -> Vec<&Conversation> {
+        let mut conversations: Vec<&Conversation> = self.conversations.values().collect();
+        conversations.sort_by_key(|conversation| {
+            std::cmp::Reverse(conversation.latest().map(|message| message.created_at))
+        });
+        conversations
+    }
+    pub fn unread_count(&self, counterparty: &str) -> usize {
+        self.get(counterparty)
+            .map(Conversation::unread_count)
+            .unwrap_or_default()
+    }
+    pub fn total_unread(&self) -> usize {
+        self.conversations
+            .values()
+            .map(Conversation::unread_count)
+            .sum()
+    }
+}
+
+pub enum ConversationAction {
+    /// Forgets everything and starts over for another account.
+    Reset(Option<String>),
+    Receive(DirectMessage),
+    /// Marks a conversation read up to a timestamp.
+    MarkRead(String, u64),
+    LoadReadState(Vec<ConversationReadState>),
+}
+impl Reducible for Conversations {
+    type Action = ConversationAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        match action {
+            ConversationAction::Reset(owner) => Rc::new(Conversations {
+                owner,
+                ..Default::default()
+            }),
+            ConversationAction::Receive(message) => {
+                let Some(owner) = self.owner.as_deref() else {
+                    return self;
+                };
+                if !message.involves(owner) {
+                    return self;
+                }
+                let counterparty = message.counterparty(owner).to_string();
+                let mut conversations = (*self).clone();
+                let conversation = conversations
+                    .conversations
+                    .entry(counterparty.clone())
+                    .or_insert_with(|| Conversation::new(&counterparty));
+                match conversation.insert(message) {
+                    true => Rc::new(conversations),
+                    false => self,
+                }
+            }
+            ConversationAction::MarkRead(counterparty, read_at) => {
+                let mut conversations = (*self).clone();
+                match conversations.conversations.get_mut(&counterparty) {
+                    Some(conversation) if conversation.last_read < read_at => {
+                        conversation.last_read = read_at;
+                        Rc::new(conversations)
+                    }
+                    _ => self,
+                }
+            }
+            ConversationAction::LoadReadState(states) => {
+                let mut conversations = (*self).clone();
+                for state in states {
+                    if Some(&state.owner) != self.owner.as_ref() {
+                        continue;
+                    }
+                    let conversation = conversations
+                        .conversations
+                        .entry(state.counterparty.clone())
+                        .or_insert_with(|| Conversation::new(&state.counterparty));
+                    conversation.last_read = conversation.last_read.max(state.last_read);
+                }
+                Rc::new(conversations)
+            }
+        }
+    }
+}
+pub type ConversationsHandle = UseReducerHandle<Conversations>;
+
+/// How far `owner` has read a conversation, kept across sessions.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct ConversationReadState {
+    pub id: String,
+    pub owner: String,
+    pub counterparty: String,
+    pub last_read: u64,
+}
+impl ConversationReadState {
+    pub fn new(owner: &str, counterparty: &str, last_read: u64) -> Self {
+        Self {
+            id: format!("{}:{}", owner, counterparty),
+            owner: owner.to_string(),
+            counterparty: counterparty.to_string(),
+            last_read,
+        }
+    }
+}
+impl TryFrom<JsValue> for ConversationReadState {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for ConversationReadState {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+impl IdbStoreManager for ConversationReadState {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 1,
+            db_name: "minions_conversations",
+            store_name: "read_state",
+            document_key: "id",
+            indexes: &[],
+        }
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const ME: &str = "me";
+
+    fn message(id: &str, sender: &str, recipient: &str, created_at: u64) -> DirectMessage {
+        DirectMessage {
+            id: id.to_string(),
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            content: format!("message {}", id),
+            created_at,
+            protocol: MessageProtocol::Nip17,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn _conversations() {
+        let state = Rc::new(Conversations::default())
+            .reduce(ConversationAction::Reset(Some(ME.to_string())))
+            .reduce(ConversationAction::Receive(message("1", "bob", ME, 10)))
+            .reduce(ConversationAction::Receive(message("3", "bob", ME, 30)))
+            .reduce(ConversationAction::Receive(message("2", ME, "bob", 20)))
+            .reduce(ConversationAction::Receive(message("1", "bob", ME, 10)))
+            .reduce(ConversationAction::Receive(message("4", "carol", ME, 5)))
+            .reduce(ConversationAction::Receive(message(
+                "5", "carol", "bob", 50,
+            )));
+
+        let bob = state.get("bob").expect("No conversation with bob");
+        let ids: Vec<&str> = bob.messages().iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        assert_eq!(bob.unread_count(), 2);
+        assert_eq!(state.total_unread(), 3);
+        assert_eq!(state.by_recency()[0].counterparty(), "bob");
+
+        let state = state.reduce(ConversationAction::MarkRead("bob".to_string(), 20));
+        assert_eq!(state.unread_count("bob"), 1);
+        let state = state.reduce(ConversationAction::LoadReadState(vec![
+            ConversationReadState::new(ME, "carol", 5),
+            ConversationReadState::new("someone else", "bob", 100),
+        ]));
+        assert_eq!(state.unread_count("carol"), 0);
+        assert_eq!(state.total_unread(), 1);
+    }
+}
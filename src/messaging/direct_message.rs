@@ -0,0 +1,305 @@
+use nostro2::{
+    notes::{Note, SignedNote},
+    userkeys::UserKeys,
+};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::JsValue;
+
+use crate::browser_api::BrowserCrypto;
+use crate::key_manager::{nip44_encrypt, NostrSigner};
+use crate::relay_pool::{note_tags, verify_note};
+
+pub const LEGACY_DM_KIND: u32 = 4;
+pub const SEAL_KIND: u32 = 13;
+pub const CHAT_KIND: u32 = 14;
+pub const GIFT_WRAP_KIND: u32 = 1059;
+/// Seals and gift wraps are backdated by up to two days to hide when a
+/// message was really sent.
+const MAX_TIMESTAMP_TWEAK: u64 = 2 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum MessageProtocol {
+    /// Kind 4, NIP-04 encrypted. Leaks who talks to whom, kept for old clients.
+    Nip04,
+    /// Kind 14 sealed and gift wrapped, NIP-44 encrypted.
+    Nip17,
+}
+
+/// An unsigned event. A rumor that leaks cannot be proven to come from its author.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Rumor {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+}
+impl Rumor {
+    /// A kind 14 chat message from `pubkey` to `recipient`.
+    pub fn chat(pubkey: &str, recipient: &str, content: &str) -> Self {
+        let mut rumor = Self {
+            id: String::new(),
+            pubkey: pubkey.to_string(),
+            created_at: nostro2::utils::get_unix_timestamp(),
+            kind: CHAT_KIND,
+            tags: vec![vec!["p".to_string(), recipient.to_string()]],
+            content: content.to_string(),
+        };
+        rumor.id = rumor.compute_id();
+        rumor
+    }
+    /// NIP-01 id, the sha256 of the serialized event.
+    pub fn compute_id(&self) -> String {
+        let serialized = serde_json::json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        hex::encode(Sha256::digest(serialized.to_string().as_bytes()))
+    }
+    pub fn recipients(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|tag| tag.len() >= 2 && tag[0] == "p")
+            .map(|tag| tag[1].clone())
+            .collect()
+    }
+}
+
+/// A decrypted message, whichever protocol carried it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct DirectMessage {
+    pub id: String,
+    pub sender: String,
+    pub recipient: String,
+    pub content: String,
+    pub created_at: u64,
+    pub protocol: MessageProtocol,
+}
+impl DirectMessage {
+    /// Who `owner` is talking to in this message.
+    pub fn counterparty(&self, owner: &str) -> &str {
+        match self.sender == owner {
+            true => &self.recipient,
+            false => &self.sender,
+        }
+    }
+    pub fn involves(&self, owner: &str) -> bool {
+        self.sender == owner || self.recipient == owner
+    }
+    /// Reads a kind 14 rumor as a message to its first `p` tag.
+    pub fn from_rumor(rumor: Rumor) -> Result<Self, JsValue> {
+        let recipient = rumor
+            .recipients()
+            .into_iter()
+            .next()
+            .ok_or(JsValue::from_str("Message has no recipient"))?;
+        Ok(Self {
+            id: rumor.id,
+            sender: rumor.pubkey,
+            recipient,
+            content: rumor.content,
+            created_at: rumor.created_at,
+            protocol: MessageProtocol::Nip17,
+        })
+    }
+}
+
+/// NIP-44 unless the payload is in the NIP-04 `?iv=` form.
+pub async fn decrypt_payload(
+    signer: &NostrSigner,
+    pubkey: &str,
+    payload: &str,
+) -> Result<String, JsValue> {
+    match payload.contains("?iv=") {
+        true => signer.nip04_decrypt(pubkey, payload).await,
+        false => signer.nip44_decrypt(pubkey, payload).await,
+    }
+}
+
+/// Reads a kind 4 or kind 1059 note `owner` sent or received.
+pub async fn open_message(
+    signer: &NostrSigner,
+    owner: &str,
+    note: &SignedNote,
+) -> Result<DirectMessage, JsValue> {
+    match note.get_kind() {
+        GIFT_WRAP_KIND => DirectMessage::from_rumor(unwrap_gift(signer, note).await?),
+        LEGACY_DM_KIND => {
+            let sender = note.get_pubkey().to_string();
+            let recipient = note_tags(note)
+                .into_iter()
+                .find(|tag| tag.len() >= 2 && tag[0] == "p")
+                .map(|tag| tag[1].clone())
+                .ok_or(JsValue::from_str("Message has no recipient"))?;
+            let counterparty = match sender == owner {
+                true => &recipient,
+                false => &sender,
+            };
+            let content = decrypt_payload(signer, counterparty, &note.get_content()).await?;
+            Ok(DirectMessage {
+                id: note.get_id().to_string(),
+                sender,
+                recipient,
+                content,
+                created_at: note.get_created_at(),
+                protocol: MessageProtocol::Nip04,
+            })
+        }
+        _ => Err(JsValue::from_str("Not a direct message")),
+    }
+}
+
+/// A signed kind 4 note for peers that only read NIP-04.
+pub async fn legacy_message(
+    signer: &NostrSigner,
+    recipient: &str,
+    content: &str,
+) -> Result<SignedNote, JsValue> {
+    let encrypted = signer.nip04_encrypt(recipient, content).await?;
+    let note = note_with(
+        &signer.pubkey(),
+        LEGACY_DM_KIND,
+        &encrypted,
+        serde_json::json!([["p", recipient]]),
+        None,
+    )?;
+    signer.sign_event(note).await
+}
+
+/// Seals `rumor` with the sender's signer and gift wraps it for `recipient`
+/// under a throwaway key. Send one wrap per recipient and one to yourself.
+pub async fn gift_wrap(
+    signer: &NostrSigner,
+    rumor: &Rumor,
+    recipient: &str,
+) -> Result<SignedNote, JsValue> {
+    let rumor_json = serde_json::to_string(rumor).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let sealed = signer.nip44_encrypt(recipient, &rumor_json).await?;
+    let seal = note_with(
+        &signer.pubkey(),
+        SEAL_KIND,
+        &sealed,
+        serde_json::json!([]),
+        Some(tweaked_timestamp()?),
+    )?;
+    let seal = signer.sign_event(seal).await?;
+
+    let ephemeral = UserKeys::generate_extractable();
+    let seal_json = serde_json::to_string(&seal).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let wrapped = nip44_encrypt(&ephemeral.get_secret_key(), recipient, &seal_json)?;
+    let wrap = note_with(
+        &ephemeral.get_public_key(),
+        GIFT_WRAP_KIND,
+        &wrapped,
+        serde_json::json!([["p", recipient]]),
+        Some(tweaked_timestamp()?),
+    )?;
+    Ok(ephemeral.sign_nostr_event(wrap))
+}
+
+/// Opens a gift wrap addressed to the signer. Fails if the wrap or the seal
+/// is not validly signed, or if the seal was signed by someone other than the
+/// rumor's author.
+pub async fn unwrap_gift(signer: &NostrSigner, wrap: &SignedNote) -> Result<Rumor, JsValue> {
+    if wrap.get_kind() != GIFT_WRAP_KIND {
+        return Err(JsValue::from_str("Not a gift wrap"));
+    }
+    verify_note(wrap).map_err(|e| JsValue::from_str(&e))?;
+    let seal_json = signer
+        .nip44_decrypt(&wrap.get_pubkey().to_string(), &wrap.get_content())
+        .await?;
+    let seal: SignedNote =
+        serde_json::from_str(&seal_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    if seal.get_kind() != SEAL_KIND {
+        return Err(JsValue::from_str("Gift wrap does not hold a seal"));
+    }
+    // Only a valid signature makes the seal's pubkey the sender.
+    verify_note(&seal).map_err(|e| JsValue::from_str(&e))?;
+    let author = seal.get_pubkey().to_string();
+    let rumor_json = signer.nip44_decrypt(&author, &seal.get_content()).await?;
+    let rumor: Rumor =
+        serde_json::from_str(&rumor_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    if rumor.pubkey != author {
+        return Err(JsValue::from_str("Seal and message authors differ"));
+    }
+    if rumor.kind != CHAT_KIND || rumor.id != rumor.compute_id() {
+        return Err(JsValue::from_str("Invalid sealed message"));
+    }
+    Ok(rumor)
+}
+
+fn tweaked_timestamp() -> Result<u64, JsValue> {
+    let random = u32::from_be_bytes(BrowserCrypto::default().random_bytes::<4>()?);
+    Ok(nostro2::utils::get_unix_timestamp() - random as u64 % MAX_TIMESTAMP_TWEAK)
+}
+
+fn note_with(
+    pubkey: &str,
+    kind: u32,
+    content: &str,
+    tags: serde_json::Value,
+    created_at: Option<u64>,
+) -> Result<Note, JsValue> {
+    let mut note = serde_json::to_value(Note::new(pubkey, kind, content))
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    note["tags"] = tags;
+    if let Some(created_at) = created_at {
+        note["created_at"] = serde_json::json!(created_at);
+    }
+    serde_json::from_value(note).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn _gift_wrap_roundtrip() -> Result<(), JsValue> {
+        let alice = NostrSigner::Local(UserKeys::generate_extractable());
+        let bob = NostrSigner::Local(UserKeys::generate_extractable());
+        let eve = NostrSigner::Local(UserKeys::generate_extractable());
+
+        let rumor = Rumor::chat(&alice.pubkey(), &bob.pubkey(), "Minion meeting at noon");
+        let wrap = gift_wrap(&alice, &rumor, &bob.pubkey()).await?;
+        assert_eq!(wrap.get_kind(), GIFT_WRAP_KIND);
+        assert_ne!(wrap.get_pubkey().to_string(), alice.pubkey());
+        assert!(wrap.get_created_at() <= rumor.created_at);
+
+        let message = open_message(&bob, &bob.pubkey(), &wrap).await?;
+        assert_eq!(message.sender, alice.pubkey());
+        assert_eq!(message.counterparty(&bob.pubkey()), alice.pubkey());
+        assert_eq!(message.content, "Minion meeting at noon");
+        assert_eq!(message.protocol, MessageProtocol::Nip17);
+        assert!(unwrap_gift(&eve, &wrap).await.is_err());
+
+        // A wrap whose signature does not match is not opened.
+        let other_wrap = gift_wrap(&alice, &rumor, &bob.pubkey()).await?;
+        let mut forged = serde_json::to_value(&wrap).unwrap();
+        forged["sig"] = serde_json::to_value(&other_wrap).unwrap()["sig"].clone();
+        let forged: SignedNote = serde_json::from_value(forged).unwrap();
+        assert!(unwrap_gift(&bob, &forged).await.is_err());
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _legacy_message() -> Result<(), JsValue> {
+        let alice = NostrSigner::Local(UserKeys::generate_extractable());
+        let bob = NostrSigner::Local(UserKeys::generate_extractable());
+        let note = legacy_message(&alice, &bob.pubkey(), "Old school").await?;
+        assert!(note.get_content().contains("?iv="));
+
+        let received = open_message(&bob, &bob.pubkey(), &note).await?;
+        let sent = open_message(&alice, &alice.pubkey(), &note).await?;
+        assert_eq!(received, sent);
+        assert_eq!(received.content, "Old school");
+        assert_eq!(sent.counterparty(&alice.pubkey()), bob.pubkey());
+        Ok(())
+    }
+}
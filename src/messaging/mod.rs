@@ -0,0 +1,6 @@
+pub mod conversation;
+pub mod direct_message;
+pub mod provider;
+pub use conversation::*;
+pub use direct_message::*;
+pub use provider::*;
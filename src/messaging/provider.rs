@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+
+use nostro2::relays::NostrFilter;
+use wasm_bindgen::JsValue;
+use yew::{platform::spawn_local, prelude::*};
+
+use super::conversation::{
+    Conversation, ConversationAction, ConversationReadState, Conversations, ConversationsHandle,
+};
+use super::direct_message::{
+    gift_wrap, legacy_message, open_message, DirectMessage, MessageProtocol, Rumor, GIFT_WRAP_KIND,
+    LEGACY_DM_KIND,
+};
+use crate::browser_api::IdbStoreManager;
+use crate::key_manager::{NostrIdStore, NostrSigner};
+use crate::relay_pool::{use_account_subscription, NostrProps};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    pub to: String,
+    pub content: String,
+    pub protocol: MessageProtocol,
+}
+
+/// Conversations of the active account and the callbacks to act on them.
+#[derive(Clone, PartialEq)]
+pub struct MessagingContext {
+    pub conversations: ConversationsHandle,
+    pub send_message: Callback<OutgoingMessage>,
+    /// Marks the conversation with a counterparty as read.
+    pub mark_read: Callback<String>,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct MessagingProviderProps {
+    pub children: Children,
+}
+
+/// Decrypts the active account's direct messages as they arrive. Must sit
+/// inside both `NostrIdProvider` and `RelayProvider`.
+#[function_component(MessagingProvider)]
+pub fn messaging_provider(props: &MessagingProviderProps) -> Html {
+    let identity = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    let relay_ctx = use_context::<NostrProps>().expect("No relay context found");
+    let conversations = use_reducer(Conversations::default);
+    let processed = use_mut_ref(HashSet::<String>::new);
+    // The account messages are decrypted for, checked again once decrypting
+    // is done so a switch meanwhile drops the result.
+    let current_owner = use_mut_ref(|| None::<String>);
+    let owner = identity.active_pubkey();
+    let signer = identity.get_signer();
+
+    let dispatcher = conversations.dispatcher();
+    let reset_processed = processed.clone();
+    let reset_owner = current_owner.clone();
+    use_effect_with(owner.clone(), move |owner| {
+        reset_processed.borrow_mut().clear();
+        *reset_owner.borrow_mut() = owner.clone();
+        dispatcher.dispatch(ConversationAction::Reset(owner.clone()));
+        if owner.is_some() {
+            spawn_local(async move {
+                match ConversationReadState::retrieve_all_from_store().await {
+                    Ok(states) => dispatcher.dispatch(ConversationAction::LoadReadState(states)),
+                    Err(e) => gloo::console::error!("Error loading read state: {:?}", e),
+                }
+            });
+        }
+        || {}
+    });
+
+    let inbox = use_account_subscription(|pubkey| {
+        NostrFilter::default()
+            .new_kinds(vec![LEGACY_DM_KIND, GIFT_WRAP_KIND])
+            .new_tag("p", vec![pubkey.to_string()])
+    });
+    // Gift wraps we send are also wrapped to ourselves, kind 4 notes are not.
+    let outbox = use_account_subscription(|pubkey| {
+        NostrFilter::default()
+            .new_kind(LEGACY_DM_KIND)
+            .new_authors(vec![pubkey.to_string()])
+    });
+
+    let dispatcher = conversations.dispatcher();
+    let sent_owner = current_owner.clone();
+    use_effect_with(
+        (inbox, outbox, signer.clone()),
+        move |(inbox, outbox, signer)| {
+            // Notes wait for a signer, a locked account reads them once unlocked.
+            if let Some(signer) = signer {
+                let owner = signer.pubkey();
+                for note in inbox.notes().iter().chain(outbox.notes().iter()) {
+                    if !processed.borrow_mut().insert(note.get_id().to_string()) {
+                        continue;
+                    }
+                    let note = note.clone();
+                    let signer = signer.clone();
+                    let owner = owner.clone();
+                    let current_owner = current_owner.clone();
+                    let dispatcher = dispatcher.clone();
+                    spawn_local(async move {
+                        match open_message(&signer, &owner, &note).await {
+                            Ok(_) if current_owner.borrow().as_ref() != Some(&owner) => {}
+                            Ok(message) => {
+                                dispatcher.dispatch(ConversationAction::Receive(message))
+                            }
+                            Err(e) => gloo::console::error!("Error opening message: {:?}", e),
+                        }
+                    });
+                }
+            }
+            || {}
+        },
+    );
+
+    let send_note = relay_ctx.send_note.clone();
+    let dispatcher = conversations.dispatcher();
+    let send_signer = signer.clone();
+    let send_message = Callback::from(move |message: OutgoingMessage| {
+        let Some(signer) = send_signer.clone() else {
+            gloo::console::error!("No signer available to send messages");
+            return;
+        };
+        let send_note = send_note.clone();
+        let dispatcher = dispatcher.clone();
+        let current_owner = sent_owner.clone();
+        spawn_local(async move {
+            let owner = signer.pubkey();
+            match send_direct_message(&signer, &send_note, message).await {
+                Ok(_) if current_owner.borrow().as_ref() != Some(&owner) => {}
+                Ok(sent) => dispatcher.dispatch(ConversationAction::Receive(sent)),
+                Err(e) => gloo::console::error!("Error sending message: {:?}", e),
+            }
+        });
+    });
+
+    let dispatcher = conversations.dispatcher();
+    let mark_read = Callback::from(move |counterparty: String| {
+        let Some(owner) = owner.clone() else {
+            return;
+        };
+        let read_at = nostro2::utils::get_unix_timestamp();
+        dispatcher.dispatch(ConversationAction::MarkRead(counterparty.clone(), read_at));
+        spawn_local(async move {
+            let state = ConversationReadState::new(&owner, &counterparty, read_at);
+            if let Err(e) = state.save_to_store().await {
+                gloo::console::error!("Error saving read state: {:?}", e);
+            }
+        });
+    });
+
+    let context = MessagingContext {
+        conversations,
+        send_message,
+        mark_read,
+    };
+    html! {
+        <ContextProvider<MessagingContext> {context}>
+            {props.children.clone()}
+        </ContextProvider<MessagingContext>>
+    }
+}
+
+/// Encrypts and sends one message, returning it as we will see it.
+async fn send_direct_message(
+    signer: &NostrSigner,
+    send_note: &Callback<nostro2::notes::SignedNote>,
+    message: OutgoingMessage,
+) -> Result<DirectMessage, JsValue> {
+    let sender = signer.pubkey();
+    match message.protocol {
+        MessageProtocol::Nip17 => {
+            let rumor = Rumor::chat(&sender, &message.to, &message.content);
+            send_note.emit(gift_wrap(signer, &rumor, &message.to).await?);
+            send_note.emit(gift_wrap(signer, &rumor, &sender).await?);
+            DirectMessage::from_rumor(rumor)
+        }
+        MessageProtocol::Nip04 => {
+            let note = legacy_message(signer, &message.to, &message.content).await?;
+            send_note.emit(note.clone());
+            Ok(DirectMessage {
+                id: note.get_id().to_string(),
+                sender,
+                recipient: message.to,
+                content: message.content,
+                created_at: note.get_created_at(),
+                protocol: MessageProtocol::Nip04,
+            })
+        }
+    }
+}
+
+#[hook]
+pub fn use_messaging() -> MessagingContext {
+    use_context::<MessagingContext>().expect("No MessagingProvider found")
+}
+
+/// One conversation and a callback that sends to it over the protocol the
+/// counterparty is known to read.
+#[derive(Clone, PartialEq)]
+pub struct ConversationHandle {
+    pub conversation: Conversation,
+    pub send: Callback<String>,
+    pub mark_read: Callback<()>,
+}
+
+#[hook]
+pub fn use_conversation(counterparty: String) -> ConversationHandle {
+    let messaging = use_messaging();
+    let conversation = messaging
+        .conversations
+        .get(&counterparty)
+        .cloned()
+        .unwrap_or_else(|| Conversation::new(&counterparty));
+    let protocol = conversation.protocol();
+    let to = counterparty.clone();
+    let send_message = messaging.send_message.clone();
+    let send = Callback::from(move |content: String| {
+        send_message.emit(OutgoingMessage {
+            to: to.clone(),
+            content,
+            protocol,
+        })
+    });
+    let mark_read = messaging.mark_read.reform(move |_| counterparty.clone());
+    ConversationHandle {
+        conversation,
+        send,
+        mark_read,
+    }
+}
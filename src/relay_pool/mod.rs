@@ -1,46 +1,46 @@
 pub mod nostr_relay;
+pub mod note_cache;
+pub mod note_store;
+pub mod note_verify;
+pub mod profile;
+pub mod publish;
+pub mod relay_auth;
+pub mod relay_connection;
+pub mod relay_events;
+pub mod relay_list;
+pub mod relay_notice;
 pub mod relay_pool;
+pub mod subscription;
 pub use nostr_relay::*;
+pub use note_cache::*;
+pub use note_store::*;
+pub use note_verify::*;
+pub use profile::*;
+pub use publish::*;
+pub use relay_auth::*;
+pub use relay_connection::*;
+pub use relay_events::*;
+pub use relay_list::*;
+pub use relay_notice::*;
 pub use relay_pool::*;
+pub use subscription::*;
 
 #[yew::function_component(RelayPoolTest)]
 pub fn relay_pool_test() -> yew::Html {
     let relay_ctx = yew::use_context::<relay_pool::NostrProps>().expect("No relay context found");
-    let subscription_id = yew::use_state(|| None);
-    let latest_note = yew::use_state(|| None);
-
-    let subscriber = relay_ctx.subscribe.clone();
-    let id_handle = subscription_id.clone();
-    yew::use_effect_with((), move |_| {
-        let nostr_sub = nostro2::relays::NostrFilter::default()
-            .new_kind(20001)
-            .subscribe();
-        let kind_one_filter = nostro2::relays::NostrFilter::default()
+    let minion_notes = subscription::use_nostr_subscription(
+        nostro2::relays::NostrFilter::default().new_kind(20001),
+    );
+    let kind_one_notes = subscription::use_nostr_subscription(
+        nostro2::relays::NostrFilter::default()
             .new_kind(1)
-            .new_limit(20)
-            .subscribe();
-        id_handle.set(Some(nostr_sub.id()));
-        subscriber.emit(nostr_sub);
-        subscriber.emit(kind_one_filter);
-        || {}
-    });
-    let note_handle = latest_note.clone();
-    let note_counter = yew::use_state(|| 0);
+            .new_limit(20),
+    );
 
-    let counter_handle = note_counter.clone();
-    yew::use_effect_with(relay_ctx.unique_notes.clone(), move |notes| {
-        if let Some(note) = notes.last() {
-            if note.get_kind() == 20001 {
-                note_handle.set(Some(note.clone()));
-            }
-            if note.get_kind() == 1 {
-                counter_handle.set(*counter_handle + 1);
-            }
-        }
-        || {}
-    });
+    let publish_status = yew::use_state(|| None::<publish::PublishStatus>);
 
-    let note_sender = relay_ctx.send_note.clone();
+    let publisher = relay_ctx.publish.clone();
+    let status_setter = publish_status.setter();
     let send_note_onclick = yew::Callback::from(move |_| {
         let new_keys = nostro2::userkeys::UserKeys::generate();
         let timestamp = nostro2::utils::get_unix_timestamp();
@@ -50,15 +50,19 @@ pub fn relay_pool_test() -> yew::Html {
             &format!("Minion Note at {}", timestamp),
         );
         let signed_note = new_keys.sign_nostr_event(new_note);
-        note_sender.emit(signed_note);
+        let status_setter = status_setter.clone();
+        publisher.emit(publish::PublishRequest {
+            note: signed_note,
+            relays: None,
+            on_status: yew::Callback::from(move |status| status_setter.set(Some(status))),
+        });
     });
 
-    match subscription_id.as_ref() {
+    match minion_notes.id() {
         Some(id) => {
             let unsubscriber = relay_ctx.unsubscribe.clone();
-            let sub_id = id.clone();
             let unsubscribe_onclick = yew::Callback::from(move |_| {
-                unsubscriber.emit(sub_id.clone());
+                unsubscriber.emit(id.clone());
             });
             yew::html! {
                 <div class="flex flex-col gap-4">
@@ -71,12 +75,18 @@ pub fn relay_pool_test() -> yew::Html {
                             { "Unsubscribe" }
                         </button>
                     </div>
+                    if let Some(status) = publish_status.as_ref() {
+                        <p>{format!("Delivered to {}/{} relays", status.accepted(), status.total())}</p>
+                    }
                     <div>
                         <h3>{"Kind 1 Count"}</h3>
-                        <p>{*note_counter}</p>
+                        <p>{kind_one_notes.notes().len()}</p>
+                        if !kind_one_notes.finished_loading() {
+                            <p>{"Loading stored notes..."}</p>
+                        }
                     </div>
                     {{
-                        match latest_note.as_ref() {
+                        match minion_notes.latest() {
                             Some(note) => yew::html! {
                                 <div>
                                     <h3>{"My Latest Note"}</h3>
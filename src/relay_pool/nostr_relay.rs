@@ -1,6 +1,9 @@
 use wasm_bindgen::JsValue;
 
-use crate::browser_api::IdbStoreManager;
+use super::relay_auth::RelayAuthState;
+use crate::browser_api::{
+    IdbSchema, IdbStoreConfig, IdbStoreManager, IdbWriteTransaction, MigrationStep, MinionsError,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct UserRelay {
@@ -8,6 +11,89 @@ pub struct UserRelay {
     pub read: bool,
     pub write: bool,
 }
+
+/// Which relays a command is sent to.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum RelayRoute {
+    All,
+    Read,
+    Write,
+    Relays(Vec<String>),
+}
+impl RelayRoute {
+    pub fn accepts(&self, relay: &UserRelay) -> bool {
+        match self {
+            RelayRoute::All => true,
+            RelayRoute::Read => relay.read,
+            RelayRoute::Write => relay.write,
+            RelayRoute::Relays(urls) => urls.iter().any(|url| url == &relay.url),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelayStatus {
+    Connecting,
+    Open,
+    Closed,
+    Errored,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayConnectionState {
+    pub relay: UserRelay,
+    pub status: RelayStatus,
+    pub last_error: Option<String>,
+    pub retry_count: u32,
+    pub auth: RelayAuthState,
+}
+impl RelayConnectionState {
+    const BASE_RETRY_DELAY_MS: u32 = 1_000;
+    const MAX_RETRY_DELAY_MS: u32 = 60_000;
+
+    pub fn new(relay: UserRelay) -> Self {
+        Self {
+            relay,
+            status: RelayStatus::Connecting,
+            last_error: None,
+            retry_count: 0,
+            auth: RelayAuthState::NotRequested,
+        }
+    }
+    pub fn is_open(&self) -> bool {
+        self.status == RelayStatus::Open
+    }
+    /// Exponential backoff for the next reconnect attempt, capped at one minute.
+    pub fn retry_delay_ms(&self) -> u32 {
+        let exponent = self.retry_count.saturating_sub(1).min(16);
+        Self::BASE_RETRY_DELAY_MS
+            .saturating_mul(1 << exponent)
+            .min(Self::MAX_RETRY_DELAY_MS)
+    }
+}
+impl UserRelay {
+    /// The relays to run at startup: every configured relay plus the ones the
+    /// user added. The stored read and write flags win for a relay in both,
+    /// and a configured relay the user removed stays removed.
+    pub fn merge_stored(
+        configured: &[UserRelay],
+        stored: Vec<UserRelay>,
+        removed: &[RemovedRelay],
+    ) -> Vec<UserRelay> {
+        let mut merged: Vec<UserRelay> = configured
+            .iter()
+            .filter(|relay| !removed.iter().any(|removed| removed.url == relay.url))
+            .cloned()
+            .collect();
+        for relay in stored {
+            match merged.iter_mut().find(|known| known.url == relay.url) {
+                Some(known) => *known = relay,
+                None => merged.push(relay),
+            }
+        }
+        merged
+    }
+}
 impl TryFrom<JsValue> for UserRelay {
     type Error = JsValue;
     fn try_from(value: JsValue) -> Result<Self, Self::Error> {
@@ -20,19 +106,82 @@ impl Into<JsValue> for UserRelay {
     }
 }
 impl IdbStoreManager for UserRelay {
-    fn config() -> crate::browser_api::IdbStoreConfig {
-        crate::browser_api::IdbStoreConfig {
-            db_version: 1,
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 2,
             db_name: "test_db_relays",
             store_name: "user_relays",
             document_key: "url",
+            indexes: &[],
+        }
+    }
+    fn schema() -> IdbSchema {
+        relay_schema()
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.url)
+    }
+}
+
+/// A relay the user removed, so a configured relay does not come back on the
+/// next load. Adding the relay again clears it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct RemovedRelay {
+    pub url: String,
+}
+impl TryFrom<JsValue> for RemovedRelay {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for RemovedRelay {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+impl IdbStoreManager for RemovedRelay {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 2,
+            db_name: "test_db_relays",
+            store_name: "removed_relays",
+            document_key: "url",
+            indexes: &[],
         }
     }
+    fn schema() -> IdbSchema {
+        relay_schema()
+    }
     fn key(&self) -> JsValue {
         JsValue::from_str(&self.url)
     }
 }
 
+/// A transaction over the user's relays and the ones they removed.
+pub async fn relay_transaction() -> Result<IdbWriteTransaction, MinionsError> {
+    IdbWriteTransaction::open(relay_schema(), &["user_relays", "removed_relays"]).await
+}
+/// The user's relays and the ones they removed, in one database so both
+/// change in the same transaction.
+pub fn relay_schema() -> IdbSchema {
+    IdbSchema::new("test_db_relays")
+        .migration(
+            1,
+            vec![MigrationStep::CreateStore {
+                store: "user_relays",
+                key_path: "url",
+            }],
+        )
+        .migration(
+            2,
+            vec![MigrationStep::CreateStore {
+                store: "removed_relays",
+                key_path: "url",
+            }],
+        )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,4 +207,73 @@ mod tests {
         assert_eq!(retrieved.url, "wss://example.com");
         Ok(())
     }
+
+    #[wasm_bindgen_test]
+    fn _relay_route() {
+        let archive = UserRelay {
+            url: "wss://archive.example.com".to_string(),
+            read: false,
+            write: true,
+        };
+        assert!(!RelayRoute::Read.accepts(&archive));
+        assert!(RelayRoute::Write.accepts(&archive));
+        assert!(RelayRoute::All.accepts(&archive));
+        assert!(RelayRoute::Relays(vec![archive.url.clone()]).accepts(&archive));
+        assert!(!RelayRoute::Relays(vec!["wss://example.com".to_string()]).accepts(&archive));
+    }
+
+    #[wasm_bindgen_test]
+    fn _relay_merge_stored() {
+        let relay = |url: &str, read: bool, write: bool| UserRelay {
+            url: url.to_string(),
+            read,
+            write,
+        };
+        let configured = vec![
+            relay("wss://a.example.com", true, true),
+            relay("wss://b.example.com", true, true),
+        ];
+        let stored = vec![
+            relay("wss://b.example.com", true, false),
+            relay("wss://c.example.com", false, true),
+        ];
+        assert_eq!(
+            UserRelay::merge_stored(&configured, stored.clone(), &[]),
+            vec![
+                relay("wss://a.example.com", true, true),
+                relay("wss://b.example.com", true, false),
+                relay("wss://c.example.com", false, true),
+            ]
+        );
+        assert_eq!(
+            UserRelay::merge_stored(&configured, vec![], &[]),
+            configured
+        );
+        // A configured relay the user removed stays gone across reloads.
+        let removed = [RemovedRelay {
+            url: "wss://a.example.com".to_string(),
+        }];
+        assert_eq!(
+            UserRelay::merge_stored(&configured, stored, &removed),
+            vec![
+                relay("wss://b.example.com", true, false),
+                relay("wss://c.example.com", false, true),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn _relay_retry_backoff() {
+        let mut state = RelayConnectionState::new(UserRelay {
+            url: "wss://example.com".to_string(),
+            read: true,
+            write: true,
+        });
+        state.retry_count = 1;
+        assert_eq!(state.retry_delay_ms(), 1_000);
+        state.retry_count = 3;
+        assert_eq!(state.retry_delay_ms(), 4_000);
+        state.retry_count = 30;
+        assert_eq!(state.retry_delay_ms(), 60_000);
+    }
 }
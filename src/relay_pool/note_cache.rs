@@ -0,0 +1,324 @@
+use nostro2::notes::SignedNote;
+use wasm_bindgen::JsValue;
+
+use super::nostr_relay::RelayRoute;
+use crate::browser_api::{
+    IdbIndexConfig, IdbQuery, IdbSchema, IdbStoreConfig, IdbStoreManager, IdbWriteTransaction,
+    MigrationStep, MinionsError,
+};
+
+/// A note kept in IndexedDB so it can be shown before any relay connects.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CachedNote {
+    pub id: String,
+    pub note: SignedNote,
+}
+impl From<SignedNote> for CachedNote {
+    fn from(note: SignedNote) -> Self {
+        Self {
+            id: note.get_id().to_string(),
+            note,
+        }
+    }
+}
+impl TryFrom<JsValue> for CachedNote {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for CachedNote {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+
+/// Cached notes by `[kind, created_at]`.
+pub const NOTES_BY_KIND_INDEX: &str = "by_kind_created_at";
+const NOTES_BY_KIND: IdbIndexConfig = IdbIndexConfig {
+    name: NOTES_BY_KIND_INDEX,
+    key_path: &["note.kind", "note.created_at"],
+    unique: false,
+    multi_entry: false,
+};
+
+impl CachedNote {
+    /// Cached notes of `kind` created between `since` and `until`, newest first.
+    pub async fn by_kind(
+        kind: u32,
+        since: u64,
+        until: u64,
+        limit: u32,
+    ) -> Result<Vec<Self>, MinionsError> {
+        let query = IdbQuery::index(NOTES_BY_KIND_INDEX)
+            .bound(kind_key(kind, since), kind_key(kind, until))
+            .reverse()
+            .limit(limit);
+        Self::query_from_store(query).await
+    }
+    /// At most `limit` cached notes, `prune` first to keep the newest ones.
+    pub async fn load(limit: u32) -> Result<Vec<Self>, MinionsError> {
+        Self::query_from_store(IdbQuery::all().limit(limit)).await
+    }
+    /// Deletes the oldest notes until at most `capacity` are left. Kinds with
+    /// few notes keep all of them, the rest share what is left evenly, so a
+    /// busy feed can not push out profiles or relay lists.
+    pub async fn prune(capacity: u32) -> Result<(), MinionsError> {
+        let mut kinds = vec![];
+        let mut next_kind = Some(0u32);
+        while let Some(kind) = next_kind {
+            let first = IdbQuery::index(NOTES_BY_KIND_INDEX)
+                .lower(kind_key(kind, 0))
+                .limit(1);
+            let Some(found) = Self::query_from_store(first).await?.pop() else {
+                break;
+            };
+            let kind = found.note.get_kind();
+            let count = Self::count_from_store(kind_range(kind)).await?;
+            kinds.push((kind, count));
+            next_kind = kind.checked_add(1);
+        }
+        kinds.sort_by_key(|(_, count)| *count);
+        let mut remaining = capacity;
+        let total = kinds.len() as u32;
+        for (position, (kind, count)) in kinds.into_iter().enumerate() {
+            let keep = count.min(remaining / (total - position as u32));
+            remaining -= keep;
+            if count > keep {
+                let mut oldest = Self::open_cursor(kind_range(kind).reverse().offset(keep)).await?;
+                while let Some(note) = oldest.next().await {
+                    if let Err(e) = note {
+                        gloo::console::error!("Pruning unreadable cached note:", e.to_string());
+                    }
+                    oldest.delete().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+fn kind_key(kind: u32, created_at: u64) -> JsValue {
+    js_sys::Array::of2(&kind.into(), &(created_at as f64).into()).into()
+}
+/// Every cached note of `kind`, through `NOTES_BY_KIND_INDEX`.
+fn kind_range(kind: u32) -> IdbQuery {
+    IdbQuery::index(NOTES_BY_KIND_INDEX).bound(kind_key(kind, 0), kind_key(kind, u64::MAX))
+}
+impl IdbStoreManager for CachedNote {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 3,
+            db_name: "minions_note_cache",
+            store_name: "notes",
+            document_key: "id",
+            indexes: &[NOTES_BY_KIND],
+        }
+    }
+    fn schema() -> IdbSchema {
+        note_schema()
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.id)
+    }
+}
+
+/// A note created while none of its relays was reachable, sent once one is.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PendingNote {
+    pub id: String,
+    pub note: SignedNote,
+    /// Notes queued before routes were stored go to the write relays.
+    #[serde(default = "default_pending_route")]
+    pub route: RelayRoute,
+}
+impl PendingNote {
+    pub fn new(note: SignedNote, route: RelayRoute) -> Self {
+        Self {
+            id: note.get_id().to_string(),
+            note,
+            route,
+        }
+    }
+    /// Every queued note. Notes still in the outbox's old database are moved
+    /// over first, they are only deleted there once saved here.
+    pub async fn load() -> Result<Vec<Self>, MinionsError> {
+        let legacy = LegacyPendingNote::retrieve_all_from_store().await?;
+        if !legacy.is_empty() {
+            let notes: Vec<Self> = legacy.iter().map(|legacy| legacy.0.clone()).collect();
+            Self::save_many_to_store(notes).await?;
+            let transaction = IdbWriteTransaction::for_store::<LegacyPendingNote>().await?;
+            transaction.delete_many(&legacy)?;
+            transaction.commit().await?;
+        }
+        Self::retrieve_all_from_store().await
+    }
+}
+fn default_pending_route() -> RelayRoute {
+    RelayRoute::Write
+}
+impl TryFrom<JsValue> for PendingNote {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for PendingNote {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+impl IdbStoreManager for PendingNote {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 3,
+            db_name: "minions_note_cache",
+            store_name: "pending_notes",
+            document_key: "id",
+            indexes: &[],
+        }
+    }
+    fn schema() -> IdbSchema {
+        note_schema()
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.id)
+    }
+}
+
+/// A pending note in the database the outbox had to itself before.
+#[derive(Clone, Debug, PartialEq)]
+struct LegacyPendingNote(PendingNote);
+impl TryFrom<JsValue> for LegacyPendingNote {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        PendingNote::try_from(value).map(Self)
+    }
+}
+impl Into<JsValue> for LegacyPendingNote {
+    fn into(self) -> JsValue {
+        self.0.into()
+    }
+}
+impl IdbStoreManager for LegacyPendingNote {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 1,
+            db_name: "minions_note_outbox",
+            store_name: "pending_notes",
+            document_key: "id",
+            indexes: &[],
+        }
+    }
+    fn key(&self) -> JsValue {
+        self.0.key()
+    }
+}
+
+/// The note cache and the outbox, in one database.
+pub fn note_schema() -> IdbSchema {
+    IdbSchema::new("minions_note_cache")
+        .migration(
+            1,
+            vec![MigrationStep::CreateStore {
+                store: "notes",
+                key_path: "id",
+            }],
+        )
+        .migration(
+            2,
+            vec![MigrationStep::CreateIndex {
+                store: "notes",
+                index: NOTES_BY_KIND,
+            }],
+        )
+        .migration(
+            3,
+            vec![MigrationStep::CreateStore {
+                store: "pending_notes",
+                key_path: "id",
+            }],
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn _note_cache_idb_manager() -> Result<(), JsValue> {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Cached minion note");
+        let cached = CachedNote::from(keys.sign_nostr_event(note));
+        cached.clone().save_to_store().await?;
+        let retrieved: CachedNote =
+            CachedNote::retrieve_from_store(&JsValue::from_str(&cached.id)).await?;
+        assert_eq!(retrieved, cached);
+        let created_at = cached.note.get_created_at();
+        let by_kind = CachedNote::by_kind(1, created_at, created_at, 10).await?;
+        assert!(by_kind.contains(&cached));
+        assert!(CachedNote::by_kind(0, 0, u32::MAX as u64, 10)
+            .await?
+            .iter()
+            .all(|note| note.note.get_kind() == 0));
+        retrieved.delete_from_store().await?;
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _note_cache_prune() -> Result<(), JsValue> {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        for content in ["First", "Second", "Third"] {
+            let note = nostro2::notes::Note::new(&keys.get_public_key(), 7, content);
+            CachedNote::from(keys.sign_nostr_event(note))
+                .save_to_store()
+                .await?;
+        }
+        CachedNote::prune(2).await?;
+        assert!(CachedNote::count_from_store(IdbQuery::all()).await? <= 2);
+        assert!(CachedNote::load(10).await?.len() <= 2);
+        CachedNote::prune(0).await?;
+        assert_eq!(CachedNote::count_from_store(IdbQuery::all()).await?, 0);
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _pending_note_route() -> Result<(), JsValue> {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Pending minion note");
+        let route = RelayRoute::Relays(vec!["wss://relay.example.com".to_string()]);
+        let pending = PendingNote::new(keys.sign_nostr_event(note), route.clone());
+        pending.clone().save_to_store().await?;
+        let retrieved: PendingNote = PendingNote::retrieve_from_store(&pending.key()).await?;
+        assert_eq!(retrieved.route, route);
+        retrieved.delete_from_store().await?;
+
+        let mut legacy = serde_json::to_value(&pending).unwrap();
+        legacy.as_object_mut().unwrap().remove("route");
+        let legacy: PendingNote = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.route, RelayRoute::Write);
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn _note_cache_shared_database() -> Result<(), JsValue> {
+        let db = CachedNote::request_db_open().await?;
+        assert_eq!(db, PendingNote::request_db_open().await?);
+        assert!(db.object_store_names().contains("notes"));
+        assert!(db.object_store_names().contains("pending_notes"));
+
+        // Notes queued in the old outbox database are moved, not lost.
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Queued minion note");
+        let pending = PendingNote::new(keys.sign_nostr_event(note), RelayRoute::Write);
+        LegacyPendingNote(pending.clone()).save_to_store().await?;
+        assert!(PendingNote::load().await?.contains(&pending));
+        assert!(LegacyPendingNote::retrieve_all_from_store()
+            .await?
+            .is_empty());
+        pending.delete_from_store().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    cell::{Ref, RefCell},
+    collections::{BTreeSet, HashMap},
+    rc::Rc,
+};
+
+use nostro2::notes::SignedNote;
+use yew::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Drop the note with the oldest `created_at` first.
+    OldestCreated,
+    /// Drop the note that arrived first, regardless of its timestamp.
+    FirstReceived,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteStoreConfig {
+    pub capacity: usize,
+    pub eviction: EvictionPolicy,
+}
+impl Default for NoteStoreConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            eviction: EvictionPolicy::OldestCreated,
+        }
+    }
+}
+
+type NoteKey = (u64, String);
+
+struct StoredNote {
+    note: Rc<SignedNote>,
+    received: u64,
+    tags: Vec<(String, String)>,
+}
+
+/// In-memory notes deduplicated by id and indexed by kind, author and tag.
+/// Every index is ordered by `created_at`, so queries come back newest first.
+pub struct NoteStore {
+    config: NoteStoreConfig,
+    notes: HashMap<String, StoredNote>,
+    by_created_at: BTreeSet<NoteKey>,
+    by_received: BTreeSet<(u64, String)>,
+    by_kind: HashMap<u32, BTreeSet<NoteKey>>,
+    by_author: HashMap<String, BTreeSet<NoteKey>>,
+    by_tag: HashMap<(String, String), BTreeSet<NoteKey>>,
+    received_count: u64,
+}
+impl NoteStore {
+    pub fn new(config: NoteStoreConfig) -> Self {
+        Self {
+            config,
+            notes: HashMap::new(),
+            by_created_at: BTreeSet::new(),
+            by_received: BTreeSet::new(),
+            by_kind: HashMap::new(),
+            by_author: HashMap::new(),
+            by_tag: HashMap::new(),
+            received_count: 0,
+        }
+    }
+    /// Returns false if the note was already stored, or if the store is full
+    /// and the note is older than anything it would evict.
+    pub fn insert(&mut self, note: SignedNote) -> bool {
+        let id = note.get_id().to_string();
+        if self.config.capacity == 0 || self.notes.contains_key(&id) {
+            return false;
+        }
+        let key = (note.get_created_at(), id.clone());
+        if self.is_next_eviction(&key) {
+            return false;
+        }
+        while self.notes.len() >= self.config.capacity {
+            self.evict();
+        }
+        let tags = Self::indexed_tags(&note);
+        self.by_created_at.insert(key.clone());
+        self.by_received.insert((self.received_count, id.clone()));
+        self.by_kind
+            .entry(note.get_kind())
+            .or_default()
+            .insert(key.clone());
+        self.by_author
+            .entry(note.get_pubkey().to_string())
+            .or_default()
+            .insert(key.clone());
+        for tag in &tags {
+            self.by_tag
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        self.notes.insert(
+            id,
+            StoredNote {
+                note: Rc::new(note),
+                received: self.received_count,
+                tags,
+            },
+        );
+        self.received_count += 1;
+        true
+    }
+    pub fn remove(&mut self, id: &str) -> Option<Rc<SignedNote>> {
+        let stored = self.notes.remove(id)?;
+        let note = stored.note;
+        let key = (note.get_created_at(), id.to_string());
+        self.by_created_at.remove(&key);
+        self.by_received.remove(&(stored.received, id.to_string()));
+        Self::remove_from_index(&mut self.by_kind, &note.get_kind(), &key);
+        Self::remove_from_index(&mut self.by_author, &note.get_pubkey().to_string(), &key);
+        for tag in stored.tags {
+            Self::remove_from_index(&mut self.by_tag, &tag, &key);
+        }
+        Some(note)
+    }
+    pub fn get(&self, id: &str) -> Option<Rc<SignedNote>> {
+        self.notes.get(id).map(|stored| stored.note.clone())
+    }
+    pub fn contains(&self, id: &str) -> bool {
+        self.notes.contains_key(id)
+    }
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+    pub fn latest(&self, limit: usize) -> Vec<Rc<SignedNote>> {
+        self.collect(self.by_created_at.iter(), limit)
+    }
+    pub fn by_kind(&self, kind: u32, limit: usize) -> Vec<Rc<SignedNote>> {
+        self.by_kind
+            .get(&kind)
+            .map(|keys| self.collect(keys.iter(), limit))
+            .unwrap_or_default()
+    }
+    pub fn by_author(&self, pubkey: &str, limit: usize) -> Vec<Rc<SignedNote>> {
+        self.by_author
+            .get(pubkey)
+            .map(|keys| self.collect(keys.iter(), limit))
+            .unwrap_or_default()
+    }
+    /// Notes carrying a `[name, value, ..]` tag, e.g. `("e", event_id)`.
+    pub fn by_tag(&self, name: &str, value: &str, limit: usize) -> Vec<Rc<SignedNote>> {
+        self.by_tag
+            .get(&(name.to_string(), value.to_string()))
+            .map(|keys| self.collect(keys.iter(), limit))
+            .unwrap_or_default()
+    }
+
+    fn collect<'a, I>(&self, keys: I, limit: usize) -> Vec<Rc<SignedNote>>
+    where
+        I: DoubleEndedIterator<Item = &'a NoteKey>,
+    {
+        keys.rev()
+            .take(limit)
+            .filter_map(|(_, id)| self.get(id))
+            .collect()
+    }
+    /// A note that the store, once full, would evict first anyway.
+    fn is_next_eviction(&self, key: &NoteKey) -> bool {
+        let full = self.notes.len() >= self.config.capacity;
+        let oldest = self.by_created_at.first();
+        full && self.config.eviction == EvictionPolicy::OldestCreated
+            && oldest.is_some_and(|oldest| key < oldest)
+    }
+    fn evict(&mut self) {
+        let id = match self.config.eviction {
+            EvictionPolicy::OldestCreated => self.by_created_at.first().map(|(_, id)| id.clone()),
+            EvictionPolicy::FirstReceived => self.by_received.first().map(|(_, id)| id.clone()),
+        };
+        if let Some(id) = id {
+            self.remove(&id);
+        }
+    }
+    fn remove_from_index<K>(index: &mut HashMap<K, BTreeSet<NoteKey>>, key: &K, note_key: &NoteKey)
+    where
+        K: std::hash::Hash + Eq,
+    {
+        if let Some(keys) = index.get_mut(key) {
+            keys.remove(note_key);
+            if keys.is_empty() {
+                index.remove(key);
+            }
+        }
+    }
+    /// Single letter tags with a value, the ones relays index as well.
+    fn indexed_tags(note: &SignedNote) -> Vec<(String, String)> {
+        note_tags(note)
+            .into_iter()
+            .filter(|tag| tag.len() >= 2 && tag[0].len() == 1)
+            .map(|tag| (tag[0].clone(), tag[1].clone()))
+            .collect()
+    }
+}
+
+/// The raw `tags` array of a note, read from its wire form.
+pub fn note_tags(note: &SignedNote) -> Vec<Vec<String>> {
+    serde_json::to_value(note)
+        .ok()
+        .and_then(|value| serde_json::from_value(value["tags"].clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Cheap shared view of the provider's note store. Equality follows the store
+/// version, so context consumers re-render only when notes change.
+#[derive(Clone)]
+pub struct NoteStoreHandle {
+    store: Rc<RefCell<NoteStore>>,
+    version: u64,
+}
+impl PartialEq for NoteStoreHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.store, &other.store) && self.version == other.version
+    }
+}
+impl NoteStoreHandle {
+    pub fn new(config: NoteStoreConfig) -> Self {
+        Self {
+            store: Rc::new(RefCell::new(NoteStore::new(config))),
+            version: 0,
+        }
+    }
+    pub fn notes(&self) -> Ref<'_, NoteStore> {
+        self.store.borrow()
+    }
+    pub fn insert(&mut self, note: SignedNote) -> bool {
+        let inserted = self.store.borrow_mut().insert(note);
+        if inserted {
+            self.version += 1;
+        }
+        inserted
+    }
+}
+
+/// The provider's note store, re-rendering the component whenever a note is
+/// added to it.
+#[hook]
+pub fn use_note_store() -> NoteStoreHandle {
+    use_context::<NoteStoreHandle>().expect("No relay context found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn signed_note(keys: &nostro2::userkeys::UserKeys, kind: u32, content: &str) -> SignedNote {
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), kind, content);
+        keys.sign_nostr_event(note)
+    }
+
+    #[wasm_bindgen_test]
+    fn _note_store_indexes() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let mut store = NoteStore::new(NoteStoreConfig::default());
+        let text_note = signed_note(&keys, 1, "hello");
+        assert!(store.insert(text_note.clone()));
+        assert!(!store.insert(text_note.clone()));
+        assert!(store.insert(signed_note(&keys, 31924, "event")));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.by_kind(1, 10).len(), 1);
+        assert_eq!(store.by_author(&keys.get_public_key(), 10).len(), 2);
+        assert!(store.by_kind(7, 10).is_empty());
+        let removed = store.remove(&text_note.get_id().to_string());
+        assert!(removed.is_some());
+        assert!(store.by_kind(1, 10).is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn _note_store_eviction() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let mut store = NoteStore::new(NoteStoreConfig {
+            capacity: 2,
+            eviction: EvictionPolicy::FirstReceived,
+        });
+        let first = signed_note(&keys, 1, "first");
+        store.insert(first.clone());
+        store.insert(signed_note(&keys, 1, "second"));
+        store.insert(signed_note(&keys, 1, "third"));
+        assert_eq!(store.len(), 2);
+        assert!(!store.contains(&first.get_id().to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn _note_store_rejects_oldest_when_full() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let created_note = |created_at: u64, content: &str| {
+            let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, content);
+            let mut note = serde_json::to_value(note).unwrap();
+            note["created_at"] = created_at.into();
+            keys.sign_nostr_event(serde_json::from_value(note).unwrap())
+        };
+        let mut store = NoteStore::new(NoteStoreConfig {
+            capacity: 2,
+            eviction: EvictionPolicy::OldestCreated,
+        });
+        let older = created_note(100, "older");
+        let newer = created_note(200, "newer");
+        assert!(store.insert(older.clone()));
+        assert!(store.insert(newer.clone()));
+        // The backfilled note would be evicted right away, nothing changes.
+        let backfill = created_note(50, "backfill");
+        assert!(!store.insert(backfill.clone()));
+        assert_eq!(store.len(), 2);
+        assert!(store.contains(&older.get_id().to_string()));
+        assert!(!store.contains(&backfill.get_id().to_string()));
+        // A newer note still pushes out the oldest.
+        assert!(store.insert(created_note(300, "newest")));
+        assert!(!store.contains(&older.get_id().to_string()));
+        assert!(store.contains(&newer.get_id().to_string()));
+    }
+}
@@ -0,0 +1,141 @@
+use nostro2::notes::{Note, SignedNote};
+use secp256k1::{schnorr::Signature, Secp256k1, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+
+/// The fields of a signed note as they travel on the wire.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct NoteFields {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+impl NoteFields {
+    pub fn of(note: &SignedNote) -> Result<Self, String> {
+        serde_json::to_value(note)
+            .and_then(serde_json::from_value)
+            .map_err(|e| e.to_string())
+    }
+    /// NIP-01 id, the sha256 of the serialized event.
+    pub fn compute_id(&self) -> String {
+        let serialized = serde_json::json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        hex::encode(Sha256::digest(serialized.to_string().as_bytes()))
+    }
+}
+
+/// Checks that the id of a note hashes its fields and that its author signed
+/// it. Notes from relays, remote signers and gift wraps are untrusted until
+/// they pass.
+pub fn verify_note(note: &SignedNote) -> Result<NoteFields, String> {
+    let fields = NoteFields::of(note)?;
+    if fields.compute_id() != fields.id {
+        return Err(format!("Note {} does not match its id", fields.id));
+    }
+    let mut id = [0u8; 32];
+    hex::decode_to_slice(&fields.id, &mut id).map_err(|e| e.to_string())?;
+    let pubkey = hex::decode(&fields.pubkey)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).map_err(|e| e.to_string()))?;
+    let signature = hex::decode(&fields.sig)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Signature::from_slice(&bytes).map_err(|e| e.to_string()))?;
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &id, &pubkey)
+        .map_err(|_| format!("Note {} has a bad signature", fields.id))?;
+    Ok(fields)
+}
+
+/// The fields a signer is asked to sign.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
+struct RequestedFields {
+    pubkey: String,
+    created_at: u64,
+    kind: u32,
+    tags: Vec<Vec<String>>,
+    content: String,
+}
+
+/// Checks a note that came back from an extension or a remote signer. It
+/// must verify and carry exactly the fields of the note sent to be signed.
+pub fn verify_signed_request(request: &Note, signed: &SignedNote) -> Result<NoteFields, String> {
+    let fields = verify_note(signed)?;
+    let requested: RequestedFields = serde_json::to_value(request)
+        .and_then(serde_json::from_value)
+        .map_err(|e| e.to_string())?;
+    let returned = RequestedFields {
+        pubkey: fields.pubkey.clone(),
+        created_at: fields.created_at,
+        kind: fields.kind,
+        tags: fields.tags.clone(),
+        content: fields.content.clone(),
+    };
+    if returned != requested {
+        return Err(format!("Signer returned a different note {}", fields.id));
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _verify_note() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Minion test note");
+        let signed_note = keys.sign_nostr_event(note);
+        let fields = verify_note(&signed_note).expect("Signed note does not verify");
+        assert_eq!(fields.pubkey, keys.get_public_key());
+
+        let forge = |fields: &[(&str, serde_json::Value)]| -> SignedNote {
+            let mut json = serde_json::to_value(&signed_note).unwrap();
+            for (field, value) in fields {
+                json[*field] = value.clone();
+            }
+            serde_json::from_value(json).unwrap()
+        };
+        assert!(verify_note(&forge(&[("content", "Forged".into())])).is_err());
+        let other = nostro2::userkeys::UserKeys::generate().get_public_key();
+        assert!(verify_note(&forge(&[("pubkey", other.into())])).is_err());
+        // A matching id does not help without the author's signature.
+        let mut forged = NoteFields::of(&signed_note).unwrap();
+        forged.content = "Forged".to_string();
+        let forged_id = forged.compute_id();
+        assert!(verify_note(&forge(&[
+            ("content", "Forged".into()),
+            ("id", forged_id.into())
+        ]))
+        .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn _verify_signed_request() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let request = Note::new(&keys.get_public_key(), 1, "Please sign this");
+        let signed_note = keys.sign_nostr_event(request.clone());
+        assert!(verify_signed_request(&request, &signed_note).is_ok());
+
+        // Validly signed, but not what was asked for.
+        let swapped = keys.sign_nostr_event(Note::new(&keys.get_public_key(), 1, "Swapped"));
+        assert!(verify_signed_request(&request, &swapped).is_err());
+        let other_keys = nostro2::userkeys::UserKeys::generate();
+        let other_author = other_keys.sign_nostr_event(Note::new(
+            &other_keys.get_public_key(),
+            1,
+            "Please sign this",
+        ));
+        assert!(verify_signed_request(&request, &other_author).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use nostro2::notes::SignedNote;
+use wasm_bindgen::JsValue;
+use yew::prelude::*;
+
+use crate::browser_api::{IdbStoreConfig, IdbStoreManager};
+use crate::key_manager::short_npub;
+
+pub const METADATA_KIND: u32 = 0;
+
+/// The fields of a kind 0 note apps usually show.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct NostrProfile {
+    pub pubkey: String,
+    pub created_at: u64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub about: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+    #[serde(default)]
+    pub banner: Option<String>,
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(default)]
+    pub nip05: Option<String>,
+    #[serde(default)]
+    pub lud16: Option<String>,
+}
+impl NostrProfile {
+    pub fn from_note(note: &SignedNote) -> Option<Self> {
+        if note.get_kind() != METADATA_KIND {
+            return None;
+        }
+        let content: serde_json::Value = serde_json::from_str(&note.get_content()).ok()?;
+        // Clients disagree on types, anything that is not a string is skipped.
+        let field = |name: &str| {
+            content[name]
+                .as_str()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Some(Self {
+            pubkey: note.get_pubkey().to_string(),
+            created_at: note.get_created_at(),
+            name: field("name"),
+            display_name: field("display_name").or(field("displayName")),
+            about: field("about"),
+            picture: field("picture"),
+            banner: field("banner"),
+            website: field("website"),
+            nip05: field("nip05"),
+            lud16: field("lud16"),
+        })
+    }
+    /// The name to show for `pubkey`, falling back to a shortened npub.
+    pub fn display_label(&self) -> String {
+        self.display_name
+            .clone()
+            .or(self.name.clone())
+            .unwrap_or_else(|| short_npub(&self.pubkey))
+    }
+}
+
+impl TryFrom<JsValue> for NostrProfile {
+    type Error = JsValue;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Ok(serde_wasm_bindgen::from_value(value)?)
+    }
+}
+impl Into<JsValue> for NostrProfile {
+    fn into(self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+}
+impl IdbStoreManager for NostrProfile {
+    fn config() -> IdbStoreConfig {
+        IdbStoreConfig {
+            db_version: 1,
+            db_name: "minions_profiles",
+            store_name: "profiles",
+            document_key: "pubkey",
+            indexes: &[],
+        }
+    }
+    fn key(&self) -> JsValue {
+        JsValue::from_str(&self.pubkey)
+    }
+}
+
+/// Profiles known to the `RelayProvider`, shared as context. Missing profiles
+/// are requested from the relays in batches through `request`.
+#[derive(Clone)]
+pub struct ProfileCache {
+    profiles: Rc<RefCell<HashMap<String, NostrProfile>>>,
+    version: u64,
+    request: Callback<Vec<String>>,
+}
+impl PartialEq for ProfileCache {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.profiles, &other.profiles) && self.version == other.version
+    }
+}
+impl ProfileCache {
+    pub fn new(request: Callback<Vec<String>>) -> Self {
+        Self {
+            profiles: Rc::new(RefCell::new(HashMap::new())),
+            version: 0,
+            request,
+        }
+    }
+    pub fn get(&self, pubkey: &str) -> Option<NostrProfile> {
+        self.profiles.borrow().get(pubkey).cloned()
+    }
+    /// Returns true if the profile was new or replaced an older one.
+    pub fn insert(&mut self, profile: NostrProfile) -> bool {
+        let mut profiles = self.profiles.borrow_mut();
+        match profiles.get(&profile.pubkey) {
+            Some(known) if known.created_at >= profile.created_at => false,
+            _ => {
+                profiles.insert(profile.pubkey.clone(), profile);
+                self.version += 1;
+                true
+            }
+        }
+    }
+    /// Asks the relays for the profiles of `pubkeys` that are not cached.
+    pub fn request(&self, pubkeys: &[String]) {
+        let profiles = self.profiles.borrow();
+        let missing: Vec<String> = pubkeys
+            .iter()
+            .filter(|pubkey| !profiles.contains_key(*pubkey))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            self.request.emit(missing);
+        }
+    }
+}
+
+/// Profiles for `pubkeys`, fetched once and re-rendered as they arrive.
+#[hook]
+pub fn use_profiles(pubkeys: Vec<String>) -> HashMap<String, NostrProfile> {
+    let cache = use_context::<ProfileCache>().expect("No relay context found");
+    let mut pubkeys = pubkeys;
+    pubkeys.sort();
+    pubkeys.dedup();
+    let request_cache = cache.clone();
+    use_effect_with(pubkeys.clone(), move |pubkeys| {
+        request_cache.request(pubkeys);
+        || {}
+    });
+    pubkeys
+        .into_iter()
+        .filter_map(|pubkey| cache.get(&pubkey).map(|profile| (pubkey, profile)))
+        .collect()
+}
+
+/// The profile of a single pubkey, `None` until it is found.
+#[hook]
+pub fn use_profile(pubkey: String) -> Option<NostrProfile> {
+    use_profiles(vec![pubkey.clone()]).remove(&pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn metadata_note(created_at: u64, content: serde_json::Value) -> SignedNote {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("{:064x}", created_at),
+            "pubkey": "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "created_at": created_at,
+            "kind": METADATA_KIND,
+            "tags": [],
+            "content": content.to_string(),
+            "sig": "c".repeat(128),
+        }))
+        .expect("Invalid note")
+    }
+
+    #[wasm_bindgen_test]
+    fn _profile_cache() {
+        let older = NostrProfile::from_note(&metadata_note(
+            1,
+            serde_json::json!({"name": "minion", "picture": "", "lud16": 42}),
+        ))
+        .expect("Not a profile");
+        assert_eq!(older.display_label(), "minion");
+        assert_eq!(older.picture, None);
+        assert_eq!(older.lud16, None);
+
+        let newer = NostrProfile::from_note(&metadata_note(
+            2,
+            serde_json::json!({"display_name": "Minion", "nip05": "minion@example.com"}),
+        ))
+        .expect("Not a profile");
+        let mut cache = ProfileCache::new(Callback::noop());
+        assert!(cache.insert(newer.clone()));
+        assert!(!cache.insert(older));
+        assert_eq!(cache.get(&newer.pubkey), Some(newer.clone()));
+
+        let unnamed = NostrProfile {
+            pubkey: newer.pubkey.clone(),
+            ..Default::default()
+        };
+        assert!(unnamed.display_label().starts_with("npub1"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use nostro2::notes::SignedNote;
+use yew::Callback;
+
+/// How long a relay has to answer an EVENT with OK.
+pub const PUBLISH_TIMEOUT_MS: u32 = 10_000;
+pub const MAX_PUBLISH_RETRIES: u32 = 3;
+const RETRY_DELAY_MS: u32 = 2_000;
+
+/// A NIP-20 `["OK", <event id>, <accepted>, <message>]` from one relay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayOk {
+    pub relay_url: String,
+    pub note_id: String,
+    pub accepted: bool,
+    pub message: String,
+}
+impl RelayOk {
+    pub fn is_rate_limited(&self) -> bool {
+        !self.accepted && self.message.starts_with("rate-limited:")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelayAck {
+    Pending,
+    Accepted(String),
+    Rejected(String),
+    TimedOut,
+}
+
+/// Per relay outcome of publishing one note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishStatus {
+    pub note_id: String,
+    pub relays: Vec<(String, RelayAck)>,
+}
+impl PublishStatus {
+    pub fn new(note_id: String, relay_urls: Vec<String>) -> Self {
+        Self {
+            note_id,
+            relays: relay_urls
+                .into_iter()
+                .map(|url| (url, RelayAck::Pending))
+                .collect(),
+        }
+    }
+    pub fn ack(&self, relay_url: &str) -> Option<&RelayAck> {
+        self.relays
+            .iter()
+            .find(|(url, _)| url == relay_url)
+            .map(|(_, ack)| ack)
+    }
+    pub fn accepted(&self) -> usize {
+        self.relays
+            .iter()
+            .filter(|(_, ack)| matches!(ack, RelayAck::Accepted(_)))
+            .count()
+    }
+    pub fn total(&self) -> usize {
+        self.relays.len()
+    }
+    /// True once no relay is left pending.
+    pub fn is_settled(&self) -> bool {
+        self.relays.iter().all(|(_, ack)| *ack != RelayAck::Pending)
+    }
+    fn set(&mut self, relay_url: &str, ack: RelayAck) {
+        if let Some((_, known)) = self.relays.iter_mut().find(|(url, _)| url == relay_url) {
+            *known = ack;
+        }
+    }
+}
+
+/// A note to publish, with an optional explicit relay set and a callback that
+/// receives every change to its [`PublishStatus`].
+#[derive(Clone, PartialEq)]
+pub struct PublishRequest {
+    pub note: SignedNote,
+    pub relays: Option<Vec<String>>,
+    pub on_status: Callback<PublishStatus>,
+}
+
+pub enum PublishUpdate {
+    Updated,
+    Retry(u32),
+    Ignored,
+}
+
+pub struct PublishTracker {
+    pub note: SignedNote,
+    pub status: PublishStatus,
+    attempts: HashMap<String, u32>,
+    on_status: Option<Callback<PublishStatus>>,
+}
+impl PublishTracker {
+    pub fn new(
+        note: SignedNote,
+        relay_urls: Vec<String>,
+        on_status: Option<Callback<PublishStatus>>,
+    ) -> Self {
+        Self {
+            status: PublishStatus::new(note.get_id().to_string(), relay_urls),
+            note,
+            attempts: HashMap::new(),
+            on_status,
+        }
+    }
+    pub fn attempt(&self, relay_url: &str) -> u32 {
+        self.attempts.get(relay_url).copied().unwrap_or(1)
+    }
+    /// Rate-limit rejections are retried with a growing delay, any other
+    /// answer settles the relay.
+    pub fn record_ok(&mut self, ok: &RelayOk) -> PublishUpdate {
+        if self.status.ack(&ok.relay_url) != Some(&RelayAck::Pending) {
+            return PublishUpdate::Ignored;
+        }
+        let attempt = self.attempt(&ok.relay_url);
+        if ok.is_rate_limited() && attempt <= MAX_PUBLISH_RETRIES {
+            self.attempts.insert(ok.relay_url.clone(), attempt + 1);
+            return PublishUpdate::Retry(RETRY_DELAY_MS << (attempt - 1));
+        }
+        let ack = match ok.accepted {
+            true => RelayAck::Accepted(ok.message.clone()),
+            false => RelayAck::Rejected(ok.message.clone()),
+        };
+        self.status.set(&ok.relay_url, ack);
+        PublishUpdate::Updated
+    }
+    /// Timeouts from earlier attempts are ignored after a retry.
+    pub fn time_out(&mut self, relay_url: &str, attempt: u32) -> PublishUpdate {
+        if self.status.ack(relay_url) != Some(&RelayAck::Pending)
+            || self.attempt(relay_url) != attempt
+        {
+            return PublishUpdate::Ignored;
+        }
+        self.status.set(relay_url, RelayAck::TimedOut);
+        PublishUpdate::Updated
+    }
+    pub fn notify(&self) {
+        if let Some(on_status) = &self.on_status {
+            on_status.emit(self.status.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn ok(relay_url: &str, accepted: bool, message: &str) -> RelayOk {
+        RelayOk {
+            relay_url: relay_url.to_string(),
+            note_id: "note".to_string(),
+            accepted,
+            message: message.to_string(),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn _publish_tracker() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = keys.sign_nostr_event(nostro2::notes::Note::new(
+            &keys.get_public_key(),
+            1,
+            "Tracked minion note",
+        ));
+        let relays = vec![
+            "wss://a".to_string(),
+            "wss://b".to_string(),
+            "wss://c".to_string(),
+        ];
+        let mut tracker = PublishTracker::new(note, relays, None);
+
+        assert!(matches!(
+            tracker.record_ok(&ok("wss://a", true, "")),
+            PublishUpdate::Updated
+        ));
+        assert!(matches!(
+            tracker.record_ok(&ok("wss://b", false, "rate-limited: slow down")),
+            PublishUpdate::Retry(_)
+        ));
+        assert_eq!(tracker.attempt("wss://b"), 2);
+        assert!(matches!(
+            tracker.time_out("wss://b", 1),
+            PublishUpdate::Ignored
+        ));
+        assert!(matches!(
+            tracker.record_ok(&ok("wss://b", false, "blocked: no thanks")),
+            PublishUpdate::Updated
+        ));
+        assert!(!tracker.status.is_settled());
+        assert!(matches!(
+            tracker.time_out("wss://c", 1),
+            PublishUpdate::Updated
+        ));
+        assert!(tracker.status.is_settled());
+        assert_eq!(tracker.status.accepted(), 1);
+        assert_eq!(tracker.status.total(), 3);
+        assert_eq!(tracker.status.ack("wss://c"), Some(&RelayAck::TimedOut));
+    }
+}
@@ -0,0 +1,79 @@
+use nostro2::notes::Note;
+
+pub const AUTH_KIND: u32 = 22242;
+
+/// NIP-42 authentication state of one relay connection. Every new socket
+/// starts unauthenticated, challenges are only valid for the socket they came on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RelayAuthState {
+    #[default]
+    NotRequested,
+    /// The relay sent a challenge and is waiting for a signed AUTH event.
+    Challenged(String),
+    /// Id of the AUTH event sent, waiting for the relay's OK.
+    Authenticating(String),
+    Authenticated,
+    Failed(String),
+}
+impl RelayAuthState {
+    pub fn challenge(&self) -> Option<&str> {
+        match self {
+            RelayAuthState::Challenged(challenge) => Some(challenge),
+            _ => None,
+        }
+    }
+    pub fn is_authenticated(&self) -> bool {
+        *self == RelayAuthState::Authenticated
+    }
+}
+
+/// True if a relay refused a REQ or EVENT until the client authenticates.
+pub fn is_auth_required(message: &str) -> bool {
+    message.starts_with("auth-required:")
+}
+
+/// The unsigned kind 22242 event answering `challenge` from `relay_url`.
+pub fn auth_note(
+    pubkey: &str,
+    relay_url: &str,
+    challenge: &str,
+) -> Result<Note, serde_json::Error> {
+    let mut note = serde_json::to_value(Note::new(pubkey, AUTH_KIND, ""))?;
+    note["tags"] = serde_json::json!([["relay", relay_url], ["challenge", challenge]]);
+    serde_json::from_value(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay_pool::note_tags;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _relay_auth_note() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = auth_note(
+            &keys.get_public_key(),
+            "wss://auth.example.com",
+            "minion-challenge",
+        )
+        .expect("Error building auth note");
+        let signed_note = keys.sign_nostr_event(note);
+        assert_eq!(signed_note.get_kind(), AUTH_KIND);
+        assert_eq!(signed_note.get_pubkey().to_string(), keys.get_public_key());
+        assert_eq!(
+            note_tags(&signed_note),
+            vec![
+                vec!["relay".to_string(), "wss://auth.example.com".to_string()],
+                vec!["challenge".to_string(), "minion-challenge".to_string()],
+            ]
+        );
+        assert!(is_auth_required("auth-required: sign in first"));
+        assert!(!is_auth_required("blocked: no thanks"));
+        assert_eq!(
+            RelayAuthState::Challenged("minion-challenge".to_string()).challenge(),
+            Some("minion-challenge")
+        );
+    }
+}
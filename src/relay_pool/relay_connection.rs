@@ -0,0 +1,644 @@
+use std::collections::{HashMap, HashSet};
+
+use async_channel::{unbounded, Receiver, Sender};
+use gloo_timers::future::TimeoutFuture;
+use nostro2::{
+    notes::SignedNote,
+    relays::{NostrSubscription, RelayEvents},
+};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{CloseEvent, MessageEvent, WebSocket};
+use yew::{platform::spawn_local, Callback};
+
+use super::nostr_relay::{RelayConnectionState, RelayStatus, UserRelay};
+use super::note_verify::verify_note;
+use super::publish::RelayOk;
+use super::relay_auth::{is_auth_required, RelayAuthState};
+use super::relay_notice::RelayNotice;
+
+pub enum SocketEvent {
+    Open,
+    Message(String),
+    Error,
+    Closed(String),
+}
+
+/// A single websocket to a relay. Handlers stay alive for as long as the
+/// connection does and are detached when it is dropped.
+pub struct RelayConnection {
+    ws: WebSocket,
+    events: Receiver<SocketEvent>,
+    _on_open: Closure<dyn FnMut(web_sys::Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(web_sys::Event)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+impl RelayConnection {
+    pub async fn open(url: &str) -> Result<Self, JsValue> {
+        let ws = WebSocket::new(url)?;
+        let (events_tx, events) = unbounded::<SocketEvent>();
+
+        let open_tx = events_tx.clone();
+        let on_open = Closure::<dyn FnMut(web_sys::Event)>::new(move |_| {
+            let _ = open_tx.try_send(SocketEvent::Open);
+        });
+        let message_tx = events_tx.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            if let Some(text) = event.data().as_string() {
+                let _ = message_tx.try_send(SocketEvent::Message(text));
+            }
+        });
+        let error_tx = events_tx.clone();
+        let on_error = Closure::<dyn FnMut(web_sys::Event)>::new(move |_| {
+            let _ = error_tx.try_send(SocketEvent::Error);
+        });
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            let reason = format!("Socket closed ({}) {}", event.code(), event.reason());
+            let _ = events_tx.try_send(SocketEvent::Closed(reason));
+            events_tx.close();
+        });
+        ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let connection = Self {
+            ws,
+            events,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_error: on_error,
+            _on_close: on_close,
+        };
+        match connection.events.recv().await {
+            Ok(SocketEvent::Open) => Ok(connection),
+            Ok(SocketEvent::Closed(reason)) => Err(JsValue::from_str(&reason)),
+            _ => Err(JsValue::from_str(&format!("Could not connect to {}", url))),
+        }
+    }
+    pub async fn next_event(&self) -> Option<SocketEvent> {
+        self.events.recv().await.ok()
+    }
+    pub fn send(&self, message: &str) -> Result<(), JsValue> {
+        self.ws.send_with_str(message)
+    }
+    pub fn close(&self) -> Result<(), JsValue> {
+        self.ws.close()
+    }
+}
+impl Drop for RelayConnection {
+    fn drop(&mut self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+    }
+}
+
+/// The parts of a relay message the pool acts on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelayMessage {
+    Event(String, SignedNote),
+    EndOfStoredEvents(String),
+    /// Event id, accepted and the relay's message.
+    Ok(String, bool, String),
+    /// Subscription id and the relay's reason for closing it.
+    Closed(String, String),
+    /// NIP-42 challenge.
+    Auth(String),
+    Other,
+}
+impl RelayMessage {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let frame: Vec<serde_json::Value> =
+            serde_json::from_str(text).map_err(|e| e.to_string())?;
+        Self::from_frame(&frame)
+    }
+    /// Reads a frame already split into its fields. Notes with a wrong id or
+    /// signature are rejected, relays are not trusted to check them.
+    pub fn from_frame(message: &[serde_json::Value]) -> Result<Self, String> {
+        let field = |index: usize| {
+            message
+                .get(index)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+                .ok_or(format!("Missing field {} in relay message", index))
+        };
+        match message.first().and_then(|tag| tag.as_str()) {
+            Some("EVENT") => {
+                let note = message
+                    .get(2)
+                    .cloned()
+                    .ok_or("Missing note in relay message".to_string())?;
+                let note: SignedNote = serde_json::from_value(note).map_err(|e| e.to_string())?;
+                verify_note(&note)?;
+                Ok(RelayMessage::Event(field(1)?, note))
+            }
+            Some("EOSE") => Ok(RelayMessage::EndOfStoredEvents(field(1)?)),
+            Some("OK") => {
+                let accepted = message
+                    .get(2)
+                    .and_then(|value| value.as_bool())
+                    .ok_or("Missing status in OK message".to_string())?;
+                Ok(RelayMessage::Ok(
+                    field(1)?,
+                    accepted,
+                    field(3).unwrap_or_default(),
+                ))
+            }
+            Some("CLOSED") => Ok(RelayMessage::Closed(
+                field(1)?,
+                field(2).unwrap_or_default(),
+            )),
+            Some("AUTH") => Ok(RelayMessage::Auth(field(1)?)),
+            _ => Ok(RelayMessage::Other),
+        }
+    }
+}
+
+/// Where workers report what their relay sends back.
+#[derive(Clone)]
+pub struct RelayCallbacks {
+    pub event: Callback<RelayEvents>,
+    /// Subscription id and note.
+    pub note: Callback<(String, SignedNote)>,
+    /// Relay url and subscription id.
+    pub end_of_stored_events: Callback<(String, String)>,
+    pub ok: Callback<RelayOk>,
+    pub state: Callback<RelayConnectionState>,
+    pub notice: Callback<RelayNotice>,
+}
+
+pub enum RelayCommand {
+    Subscribe(NostrSubscription),
+    /// Subscribes for notes newer than the timestamp only, e.g. the newest
+    /// cached note matching the filter.
+    SubscribeSince(NostrSubscription, u64),
+    Unsubscribe(String),
+    SendNote(SignedNote),
+    /// A challenge and the signed kind 22242 event answering it.
+    Authenticate(String, SignedNote),
+    Close,
+}
+
+enum ConnectionEnd {
+    Closed,
+    Dropped(String),
+}
+
+/// Handle kept by the provider to drive one relay worker.
+#[derive(Clone)]
+pub struct RelayHandle {
+    pub relay: UserRelay,
+    commands: Sender<RelayCommand>,
+}
+impl RelayHandle {
+    pub fn send(&self, command: RelayCommand) -> Result<(), JsValue> {
+        self.commands
+            .try_send(command)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Seconds of overlap kept when catching up after a dropped socket, to cover
+/// clock skew between us and the relay.
+const RECONNECT_SYNC_WINDOW_SECS: u64 = 60;
+
+/// Owns the connection to one relay. Dropped sockets are reopened with
+/// exponential backoff and every active subscription is sent again. The ones
+/// already sent before only ask for notes newer than the moment the socket
+/// dropped, the ones added meanwhile still get their stored notes.
+pub struct RelayWorker {
+    relay: UserRelay,
+    commands: Receiver<RelayCommand>,
+    subscriptions: HashMap<String, NostrSubscription>,
+    /// Subscriptions sent on some connection, they have their stored notes.
+    sent_subscriptions: HashSet<String>,
+    /// The `since` a subscription was opened with, kept for every replay.
+    subscription_since: HashMap<String, u64>,
+    awaiting_auth: HashSet<String>,
+    pending_notes: Vec<SignedNote>,
+    disconnected_at: Option<u64>,
+    state: RelayConnectionState,
+    callbacks: RelayCallbacks,
+}
+impl RelayWorker {
+    pub fn spawn(relay: UserRelay, callbacks: RelayCallbacks) -> RelayHandle {
+        let (commands_tx, commands) = unbounded::<RelayCommand>();
+        let worker = Self {
+            relay: relay.clone(),
+            commands,
+            subscriptions: HashMap::new(),
+            sent_subscriptions: HashSet::new(),
+            subscription_since: HashMap::new(),
+            awaiting_auth: HashSet::new(),
+            pending_notes: Vec::new(),
+            disconnected_at: None,
+            state: RelayConnectionState::new(relay.clone()),
+            callbacks,
+        };
+        spawn_local(worker.run());
+        RelayHandle {
+            relay,
+            commands: commands_tx,
+        }
+    }
+
+    async fn run(mut self) {
+        loop {
+            self.set_status(RelayStatus::Connecting, None);
+            match RelayConnection::open(&self.relay.url).await {
+                Ok(connection) => {
+                    self.state.retry_count = 0;
+                    self.state.auth = RelayAuthState::NotRequested;
+                    self.awaiting_auth.clear();
+                    self.set_status(RelayStatus::Open, None);
+                    self.notify(RelayNotice::Connected(self.relay.url.clone()));
+                    let since = self
+                        .disconnected_at
+                        .take()
+                        .map(|timestamp| timestamp.saturating_sub(RECONNECT_SYNC_WINDOW_SECS));
+                    for subscription in self.subscriptions.values() {
+                        let since = self.replay_since(&subscription.id(), since);
+                        if let Err(e) = Self::send_subscription(&connection, subscription, since) {
+                            gloo::console::error!("Error replaying subscription: {:?}", e);
+                        }
+                    }
+                    self.sent_subscriptions = self.subscriptions.keys().cloned().collect();
+                    for note in std::mem::take(&mut self.pending_notes) {
+                        if let Err(e) =
+                            connection.send(&serde_json::json!(["EVENT", note]).to_string())
+                        {
+                            gloo::console::error!("Error sending pending note: {:?}", e);
+                        }
+                    }
+                    match self.serve(&connection).await {
+                        ConnectionEnd::Closed => {
+                            self.set_status(RelayStatus::Closed, None);
+                            return;
+                        }
+                        ConnectionEnd::Dropped(reason) => {
+                            self.disconnected_at = Some((js_sys::Date::now() / 1000.0) as u64);
+                            self.notify(RelayNotice::Disconnected(
+                                self.relay.url.clone(),
+                                reason.clone(),
+                            ));
+                            self.set_status(RelayStatus::Closed, Some(reason));
+                        }
+                    }
+                }
+                Err(e) => {
+                    let reason = e
+                        .as_string()
+                        .unwrap_or_else(|| format!("Could not connect to {}", self.relay.url));
+                    self.notify(RelayNotice::ConnectionFailed(
+                        self.relay.url.clone(),
+                        reason.clone(),
+                    ));
+                    self.set_status(RelayStatus::Errored, Some(reason));
+                }
+            }
+            self.state.retry_count += 1;
+            if let ConnectionEnd::Closed = self.wait_for_retry().await {
+                self.set_status(RelayStatus::Closed, None);
+                return;
+            }
+        }
+    }
+
+    async fn serve(&mut self, connection: &RelayConnection) -> ConnectionEnd {
+        loop {
+            tokio::select! {
+                event = connection.next_event() => match event {
+                    Some(SocketEvent::Message(text)) => self.handle_message(connection, &text),
+                    Some(SocketEvent::Open) | Some(SocketEvent::Error) => {}
+                    Some(SocketEvent::Closed(reason)) => return ConnectionEnd::Dropped(reason),
+                    None => return ConnectionEnd::Dropped("Socket closed".to_string()),
+                },
+                command = self.commands.recv() => match command {
+                    Ok(RelayCommand::Close) | Err(_) => {
+                        let _ = connection.close();
+                        return ConnectionEnd::Closed;
+                    }
+                    Ok(command) => {
+                        if let Err(e) = self.handle_command(connection, command) {
+                            self.notify(RelayNotice::CommandFailed(
+                                self.relay.url.clone(),
+                                format!("{:?}", e),
+                            ));
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Sleeps for the backoff delay while still tracking subscription changes,
+    /// so they are replayed once the socket is back.
+    async fn wait_for_retry(&mut self) -> ConnectionEnd {
+        let mut delay = TimeoutFuture::new(self.state.retry_delay_ms());
+        loop {
+            tokio::select! {
+                _ = &mut delay => return ConnectionEnd::Dropped(String::new()),
+                command = self.commands.recv() => match command {
+                    Ok(RelayCommand::Close) | Err(_) => return ConnectionEnd::Closed,
+                    Ok(command) => self.queue_command(command),
+                },
+            }
+        }
+    }
+
+    /// Keeps a command that arrived while disconnected for the next socket.
+    fn queue_command(&mut self, command: RelayCommand) {
+        match command {
+            RelayCommand::Subscribe(subscription) => self.queue_subscription(subscription, None),
+            RelayCommand::SubscribeSince(subscription, since) => {
+                self.queue_subscription(subscription, Some(since))
+            }
+            RelayCommand::Unsubscribe(id) => {
+                self.subscriptions.remove(&id);
+                self.sent_subscriptions.remove(&id);
+                self.subscription_since.remove(&id);
+            }
+            RelayCommand::SendNote(note) => self.pending_notes.push(note),
+            RelayCommand::Authenticate(_, _) | RelayCommand::Close => {}
+        }
+    }
+    fn queue_subscription(&mut self, subscription: NostrSubscription, since: Option<u64>) {
+        self.sent_subscriptions.remove(&subscription.id());
+        self.set_subscription_since(&subscription.id(), since);
+        self.subscriptions.insert(subscription.id(), subscription);
+    }
+    fn set_subscription_since(&mut self, subscription_id: &str, since: Option<u64>) {
+        match since {
+            Some(since) => self
+                .subscription_since
+                .insert(subscription_id.to_string(), since),
+            None => self.subscription_since.remove(subscription_id),
+        };
+    }
+    /// The `since` for a replayed subscription. The catch-up window only
+    /// applies to the ones that already received their stored notes.
+    fn replay_since(&self, subscription_id: &str, since: Option<u64>) -> Option<u64> {
+        since
+            .filter(|_| self.sent_subscriptions.contains(subscription_id))
+            .max(self.subscription_since.get(subscription_id).copied())
+    }
+
+    fn handle_command(
+        &mut self,
+        connection: &RelayConnection,
+        command: RelayCommand,
+    ) -> Result<(), JsValue> {
+        match command {
+            RelayCommand::Subscribe(subscription) => {
+                self.open_subscription(connection, subscription, None)?
+            }
+            RelayCommand::SubscribeSince(subscription, since) => {
+                self.open_subscription(connection, subscription, Some(since))?
+            }
+            RelayCommand::Unsubscribe(id) => {
+                self.sent_subscriptions.remove(&id);
+                self.subscription_since.remove(&id);
+                if self.subscriptions.remove(&id).is_some() {
+                    connection.send(&serde_json::json!(["CLOSE", id]).to_string())?;
+                }
+            }
+            RelayCommand::SendNote(note) => {
+                connection.send(&serde_json::json!(["EVENT", note]).to_string())?;
+            }
+            RelayCommand::Authenticate(challenge, note) => {
+                // Answers to an older challenge, or repeats, are dropped.
+                if self.state.auth.challenge() != Some(challenge.as_str()) {
+                    return Ok(());
+                }
+                connection.send(&serde_json::json!(["AUTH", note]).to_string())?;
+                self.state.auth = RelayAuthState::Authenticating(note.get_id().to_string());
+                self.callbacks.state.emit(self.state.clone());
+            }
+            RelayCommand::Close => connection.close()?,
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, connection: &RelayConnection, text: &str) {
+        let frame: Vec<serde_json::Value> = match serde_json::from_str(text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                gloo::console::error!("Unreadable relay message:", e.to_string());
+                return;
+            }
+        };
+        match RelayMessage::from_frame(&frame) {
+            Ok(RelayMessage::Event(subscription_id, note)) => {
+                self.callbacks.note.emit((subscription_id, note));
+            }
+            Ok(RelayMessage::EndOfStoredEvents(subscription_id)) => {
+                self.callbacks
+                    .end_of_stored_events
+                    .emit((self.relay.url.clone(), subscription_id));
+            }
+            Ok(RelayMessage::Ok(note_id, accepted, message))
+                if self.state.auth == RelayAuthState::Authenticating(note_id.clone()) =>
+            {
+                self.authenticated(connection, accepted, message);
+            }
+            Ok(RelayMessage::Ok(note_id, accepted, message)) => {
+                self.callbacks.ok.emit(RelayOk {
+                    relay_url: self.relay.url.clone(),
+                    note_id,
+                    accepted,
+                    message,
+                });
+            }
+            Ok(RelayMessage::Closed(subscription_id, message)) => {
+                if is_auth_required(&message) && self.subscriptions.contains_key(&subscription_id) {
+                    self.awaiting_auth.insert(subscription_id);
+                }
+            }
+            Ok(RelayMessage::Auth(challenge)) => {
+                self.state.auth = RelayAuthState::Challenged(challenge);
+                self.callbacks.state.emit(self.state.clone());
+            }
+            Ok(RelayMessage::Other) => {}
+            Err(e) => {
+                gloo::console::error!("Dropped relay message:", e);
+                return;
+            }
+        }
+        if let Ok(event) = serde_json::from_value::<RelayEvents>(serde_json::Value::Array(frame)) {
+            self.callbacks.event.emit(event);
+        }
+    }
+
+    /// Subscriptions the relay closed while we were unauthenticated are sent
+    /// again once it accepts our AUTH event.
+    fn authenticated(&mut self, connection: &RelayConnection, accepted: bool, message: String) {
+        if !accepted {
+            self.notify(RelayNotice::AuthenticationFailed(
+                self.relay.url.clone(),
+                message.clone(),
+            ));
+            self.state.auth = RelayAuthState::Failed(message);
+            self.callbacks.state.emit(self.state.clone());
+            return;
+        }
+        self.notify(RelayNotice::Authenticated(self.relay.url.clone()));
+        self.state.auth = RelayAuthState::Authenticated;
+        self.callbacks.state.emit(self.state.clone());
+        for subscription_id in std::mem::take(&mut self.awaiting_auth) {
+            if let Some(subscription) = self.subscriptions.get(&subscription_id) {
+                if let Err(e) = Self::send_subscription(connection, subscription, None) {
+                    gloo::console::error!("Error retrying subscription: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn open_subscription(
+        &mut self,
+        connection: &RelayConnection,
+        subscription: NostrSubscription,
+        since: Option<u64>,
+    ) -> Result<(), JsValue> {
+        Self::send_subscription(connection, &subscription, since)?;
+        self.set_subscription_since(&subscription.id(), since);
+        self.sent_subscriptions.insert(subscription.id());
+        self.subscriptions.insert(subscription.id(), subscription);
+        Ok(())
+    }
+
+    fn send_subscription(
+        connection: &RelayConnection,
+        subscription: &NostrSubscription,
+        since: Option<u64>,
+    ) -> Result<(), JsValue> {
+        let mut message =
+            serde_json::to_value(subscription).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        if let (Some(since), Some(filter)) = (
+            since,
+            message.get_mut(2).and_then(|filter| filter.as_object_mut()),
+        ) {
+            let current = filter
+                .get("since")
+                .and_then(|value| value.as_u64())
+                .unwrap_or_default();
+            filter.insert("since".to_string(), since.max(current).into());
+        }
+        connection.send(&message.to_string())
+    }
+
+    fn notify(&self, notice: RelayNotice) {
+        self.callbacks.notice.emit(notice);
+    }
+
+    fn set_status(&mut self, status: RelayStatus, last_error: Option<String>) {
+        self.state.status = status;
+        if last_error.is_some() || status == RelayStatus::Open {
+            self.state.last_error = last_error;
+        }
+        self.callbacks.state.emit(self.state.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostro2::relays::NostrFilter;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _relay_reconnect_since() {
+        let relay = UserRelay {
+            url: "wss://relay.example.com".to_string(),
+            read: true,
+            write: true,
+        };
+        let (_commands, receiver) = unbounded::<RelayCommand>();
+        let mut worker = RelayWorker {
+            relay: relay.clone(),
+            commands: receiver,
+            subscriptions: HashMap::new(),
+            sent_subscriptions: HashSet::new(),
+            subscription_since: HashMap::new(),
+            awaiting_auth: HashSet::new(),
+            pending_notes: Vec::new(),
+            disconnected_at: None,
+            state: RelayConnectionState::new(relay),
+            callbacks: RelayCallbacks {
+                event: Callback::noop(),
+                note: Callback::noop(),
+                end_of_stored_events: Callback::noop(),
+                ok: Callback::noop(),
+                state: Callback::noop(),
+                notice: Callback::noop(),
+            },
+        };
+        let before = NostrSubscription::new(NostrFilter::default().new_kind(1));
+        worker.sent_subscriptions.insert(before.id());
+        worker.subscriptions.insert(before.id(), before.clone());
+        // Added during backoff, it never got its stored notes.
+        let during = NostrSubscription::new(NostrFilter::default().new_kind(7));
+        worker.queue_command(RelayCommand::Subscribe(during.clone()));
+        assert_eq!(worker.replay_since(&before.id(), Some(100)), Some(100));
+        assert_eq!(worker.replay_since(&during.id(), Some(100)), None);
+        worker.queue_command(RelayCommand::Unsubscribe(before.id()));
+        assert_eq!(worker.replay_since(&before.id(), Some(100)), None);
+        // Opened from the cache, its own since always applies.
+        let cached = NostrSubscription::new(NostrFilter::default().new_kind(30023));
+        worker.queue_command(RelayCommand::SubscribeSince(cached.clone(), 50));
+        assert_eq!(worker.replay_since(&cached.id(), None), Some(50));
+        assert_eq!(worker.replay_since(&cached.id(), Some(100)), Some(50));
+        worker.sent_subscriptions.insert(cached.id());
+        assert_eq!(worker.replay_since(&cached.id(), Some(100)), Some(100));
+        worker.queue_command(RelayCommand::Unsubscribe(cached.id()));
+        assert_eq!(worker.replay_since(&cached.id(), None), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn _relay_message_parse() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Minion test note");
+        let signed_note = keys.sign_nostr_event(note);
+        let event = serde_json::json!(["EVENT", "sub_1", signed_note]).to_string();
+        assert_eq!(
+            RelayMessage::parse(&event),
+            Ok(RelayMessage::Event("sub_1".to_string(), signed_note))
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["EOSE","sub_1"]"#),
+            Ok(RelayMessage::EndOfStoredEvents("sub_1".to_string()))
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["OK","abc",false,"rate-limited: slow down"]"#),
+            Ok(RelayMessage::Ok(
+                "abc".to_string(),
+                false,
+                "rate-limited: slow down".to_string()
+            ))
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["CLOSED","sub_1","auth-required: sign in"]"#),
+            Ok(RelayMessage::Closed(
+                "sub_1".to_string(),
+                "auth-required: sign in".to_string()
+            ))
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["AUTH","challenge"]"#),
+            Ok(RelayMessage::Auth("challenge".to_string()))
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["NOTICE","slow down"]"#),
+            Ok(RelayMessage::Other)
+        );
+        assert!(RelayMessage::parse("not json").is_err());
+
+        let mut forged = serde_json::to_value(&signed_note).unwrap();
+        forged["content"] = "Forged by the relay".into();
+        let forged = serde_json::json!(["EVENT", "sub_1", forged]).to_string();
+        assert!(RelayMessage::parse(&forged).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
+
+use nostro2::relays::RelayEvents;
+use yew::prelude::*;
+
+/// Most raw relay messages a `use_relay_events` consumer keeps.
+pub const RELAY_EVENT_HISTORY: usize = 256;
+
+/// The latest raw relay messages, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct RelayEventHistory {
+    events: VecDeque<RelayEvents>,
+}
+impl RelayEventHistory {
+    pub fn events(&self) -> &VecDeque<RelayEvents> {
+        &self.events
+    }
+    pub fn latest(&self) -> Option<&RelayEvents> {
+        self.events.back()
+    }
+}
+impl Reducible for RelayEventHistory {
+    type Action = RelayEvents;
+
+    fn reduce(self: Rc<Self>, event: Self::Action) -> Rc<Self> {
+        let mut history = (*self).clone();
+        if history.events.len() >= RELAY_EVENT_HISTORY {
+            history.events.pop_front();
+        }
+        history.events.push_back(event);
+        Rc::new(history)
+    }
+}
+pub type RelayEventsHandle = UseReducerHandle<RelayEventHistory>;
+
+/// Hands raw relay messages to the components that asked for them. The
+/// context value never changes, so nothing else re-renders for them.
+#[derive(Clone, Default)]
+pub struct RelayEventLog {
+    listeners: Rc<RefCell<HashMap<u32, UseReducerDispatcher<RelayEventHistory>>>>,
+    next_listener: Rc<Cell<u32>>,
+}
+impl PartialEq for RelayEventLog {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.listeners, &other.listeners)
+    }
+}
+impl RelayEventLog {
+    pub fn emit(&self, event: RelayEvents) {
+        let listeners: Vec<_> = self.listeners.borrow().values().cloned().collect();
+        for listener in listeners {
+            listener.dispatch(event.clone());
+        }
+    }
+    fn listen(&self, dispatcher: UseReducerDispatcher<RelayEventHistory>) -> u32 {
+        let id = self.next_listener.get();
+        self.next_listener.set(id.wrapping_add(1));
+        self.listeners.borrow_mut().insert(id, dispatcher);
+        id
+    }
+    fn forget(&self, id: u32) {
+        self.listeners.borrow_mut().remove(&id);
+    }
+}
+
+/// Raw relay messages received while the component is mounted.
+#[hook]
+pub fn use_relay_events() -> RelayEventsHandle {
+    let log = use_context::<RelayEventLog>().expect("No relay context found");
+    let history = use_reducer(RelayEventHistory::default);
+    let dispatcher = history.dispatcher();
+    use_effect_with((), move |_| {
+        let id = log.listen(dispatcher);
+        move || log.forget(id)
+    });
+    history
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use nostro2::{notes::SignedNote, relays::NostrSubscription};
+
+use super::nostr_relay::UserRelay;
+use super::note_store::note_tags;
+
+pub const RELAY_LIST_KIND: u32 = 10002;
+
+/// An author's NIP-65 relay list, taken from their latest kind 10002 note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthorRelayList {
+    pub pubkey: String,
+    pub created_at: u64,
+    pub relays: Vec<UserRelay>,
+}
+impl AuthorRelayList {
+    pub fn from_note(note: &SignedNote) -> Option<Self> {
+        if note.get_kind() != RELAY_LIST_KIND {
+            return None;
+        }
+        let relays = note_tags(note)
+            .into_iter()
+            .filter(|tag| tag.len() >= 2 && tag[0] == "r")
+            .map(|tag| {
+                let marker = tag.get(2).map(String::as_str);
+                UserRelay {
+                    url: tag[1].clone(),
+                    read: marker != Some("write"),
+                    write: marker != Some("read"),
+                }
+            })
+            .collect();
+        Some(Self {
+            pubkey: note.get_pubkey().to_string(),
+            created_at: note.get_created_at(),
+            relays,
+        })
+    }
+    pub fn write_relays(&self) -> impl Iterator<Item = &UserRelay> {
+        self.relays.iter().filter(|relay| relay.write)
+    }
+}
+
+/// Known relay lists by pubkey. Only the newest list per author is kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelayListDirectory {
+    lists: HashMap<String, AuthorRelayList>,
+}
+impl RelayListDirectory {
+    /// Returns true if the list replaced an older one or was new.
+    pub fn insert(&mut self, list: AuthorRelayList) -> bool {
+        match self.lists.get(&list.pubkey) {
+            Some(known) if known.created_at >= list.created_at => false,
+            _ => {
+                self.lists.insert(list.pubkey.clone(), list);
+                true
+            }
+        }
+    }
+    pub fn get(&self, pubkey: &str) -> Option<&AuthorRelayList> {
+        self.lists.get(pubkey)
+    }
+    /// Up to `per_author` write relays for each author, without duplicates.
+    pub fn write_relays_for(&self, authors: &[String], per_author: usize) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for list in authors.iter().filter_map(|author| self.get(author)) {
+            for relay in list.write_relays().take(per_author) {
+                if !urls.contains(&relay.url) {
+                    urls.push(relay.url.clone());
+                }
+            }
+        }
+        urls
+    }
+}
+
+/// The `authors` a subscription filters on, read from its wire form.
+pub fn subscription_authors(subscription: &NostrSubscription) -> Vec<String> {
+    serde_json::to_value(subscription)
+        .ok()
+        .and_then(|value| serde_json::from_value(value[2]["authors"].clone()).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _relay_list_directory() {
+        let pubkey = "a".repeat(64);
+        let signed_note: SignedNote = serde_json::from_value(serde_json::json!({
+            "id": "b".repeat(64),
+            "pubkey": pubkey,
+            "created_at": 1_700_000_000,
+            "kind": RELAY_LIST_KIND,
+            "tags": [
+                ["r", "wss://both.example.com"],
+                ["r", "wss://inbox.example.com", "read"],
+                ["r", "wss://outbox.example.com", "write"]
+            ],
+            "content": "",
+            "sig": "c".repeat(128),
+        }))
+        .expect("Invalid note");
+        let list = AuthorRelayList::from_note(&signed_note).expect("Not a relay list");
+        assert!(list.relays[1].read && !list.relays[1].write);
+        assert!(!list.relays[2].read && list.relays[2].write);
+
+        let mut directory = RelayListDirectory::default();
+        assert!(directory.insert(list.clone()));
+        assert!(!directory.insert(list));
+        let authors = vec![pubkey];
+        assert_eq!(
+            directory.write_relays_for(&authors, 3),
+            vec![
+                "wss://both.example.com".to_string(),
+                "wss://outbox.example.com".to_string()
+            ]
+        );
+        assert_eq!(directory.write_relays_for(&authors, 1).len(), 1);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use gloo_timers::future::TimeoutFuture;
+use nostro2::notes::SignedNote;
+use yew::{platform::spawn_local, Callback};
+
+use super::publish::PublishStatus;
+use crate::widgets::toastify::ToastifyOptions;
+
+/// Relay lifecycle events reported through `RelayProvider`'s `on_notice` prop.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelayNotice {
+    Connected(String),
+    /// Relay url and the reason the socket closed.
+    Disconnected(String, String),
+    /// Relay url and why the socket could not be opened.
+    ConnectionFailed(String, String),
+    /// Relay url and the error sending it a command.
+    CommandFailed(String, String),
+    Authenticated(String),
+    /// Relay url and the relay's reason for rejecting our AUTH event.
+    AuthenticationFailed(String, String),
+    /// A note not seen before on any relay.
+    NoteReceived(SignedNote),
+    /// Every relay a published note went to has answered or timed out.
+    Published(PublishStatus),
+}
+
+/// How [`RelayToaster`] turns notices into toasts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToastPolicy {
+    /// Notes arriving within this window are shown as a single toast.
+    pub note_window_ms: u32,
+    /// Minimum time between two toasts of the same kind for the same relay.
+    pub relay_interval_ms: f64,
+}
+impl Default for ToastPolicy {
+    fn default() -> Self {
+        Self {
+            note_window_ms: 2_000,
+            relay_interval_ms: 30_000.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ToastThrottle {
+    pending_notes: usize,
+    last_shown: HashMap<(&'static str, String), f64>,
+}
+impl ToastThrottle {
+    /// Returns true for the first note of a window, which schedules the toast.
+    fn add_note(&mut self) -> bool {
+        self.pending_notes += 1;
+        self.pending_notes == 1
+    }
+    fn take_notes(&mut self) -> usize {
+        std::mem::take(&mut self.pending_notes)
+    }
+    fn allow(&mut self, kind: &'static str, relay_url: &str, now: f64, interval: f64) -> bool {
+        let key = (kind, relay_url.to_string());
+        match self.last_shown.get(&key) {
+            Some(last) if now - last < interval => false,
+            _ => {
+                self.last_shown.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+/// Default toast notifications for relay notices, with bursts of notes
+/// aggregated and repeated relay toasts throttled.
+#[derive(Clone, Default)]
+pub struct RelayToaster {
+    policy: ToastPolicy,
+    throttle: Rc<RefCell<ToastThrottle>>,
+}
+impl RelayToaster {
+    pub fn new(policy: ToastPolicy) -> Self {
+        Self {
+            policy,
+            throttle: Rc::new(RefCell::new(ToastThrottle::default())),
+        }
+    }
+    pub fn callback(&self) -> Callback<RelayNotice> {
+        let toaster = self.clone();
+        Callback::from(move |notice| toaster.notice(notice))
+    }
+    pub fn notice(&self, notice: RelayNotice) {
+        match notice {
+            RelayNotice::NoteReceived(_) => {
+                if !self.throttle.borrow_mut().add_note() {
+                    return;
+                }
+                let throttle = self.throttle.clone();
+                let window = self.policy.note_window_ms;
+                spawn_local(async move {
+                    TimeoutFuture::new(window).await;
+                    let count = throttle.borrow_mut().take_notes();
+                    ToastifyOptions::new_notes_received(count).show();
+                });
+            }
+            RelayNotice::Connected(url) => {
+                if self.allow("connected", &url) {
+                    ToastifyOptions::new_relay_connected(&url).show();
+                }
+            }
+            RelayNotice::Disconnected(url, _) => {
+                if self.allow("disconnected", &url) {
+                    ToastifyOptions::new_relay_disconnected(&url).show();
+                }
+            }
+            RelayNotice::ConnectionFailed(url, error)
+            | RelayNotice::CommandFailed(url, error)
+            | RelayNotice::AuthenticationFailed(url, error) => {
+                if self.allow("error", &url) {
+                    ToastifyOptions::new_relay_error(&error).show();
+                }
+            }
+            RelayNotice::Authenticated(_) | RelayNotice::Published(_) => {}
+        }
+    }
+    fn allow(&self, kind: &'static str, relay_url: &str) -> bool {
+        self.throttle.borrow_mut().allow(
+            kind,
+            relay_url,
+            js_sys::Date::now(),
+            self.policy.relay_interval_ms,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _toast_throttle() {
+        let mut throttle = ToastThrottle::default();
+        assert!(throttle.add_note());
+        assert!(!throttle.add_note());
+        assert!(!throttle.add_note());
+        assert_eq!(throttle.take_notes(), 3);
+        assert!(throttle.add_note());
+
+        let url = "wss://example.com";
+        assert!(throttle.allow("error", url, 0.0, 1_000.0));
+        assert!(!throttle.allow("error", url, 500.0, 1_000.0));
+        assert!(throttle.allow("connected", url, 500.0, 1_000.0));
+        assert!(throttle.allow("error", url, 1_500.0, 1_000.0));
+    }
+}
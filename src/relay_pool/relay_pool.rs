@@ -1,52 +1,142 @@
-use crate::widgets::toastify::ToastifyOptions;
-use std::collections::HashMap;
+use crate::browser_api::{IdbStoreManager, MinionsError};
+use crate::key_manager::{Nip46Transport, NostrIdAction, NostrIdStore, NostrSigner, NIP46_KIND};
+use std::collections::{HashMap, HashSet};
 
-use async_channel::{unbounded, Sender};
 use nostro2::{
     notes::SignedNote,
-    relays::{NostrSubscription, RelayEvents},
+    relays::{NostrFilter, NostrSubscription, RelayEvents},
 };
 
+use gloo_timers::future::TimeoutFuture;
 use wasm_bindgen::JsValue;
+use yew::context::ContextHandle;
 use yew::platform::spawn_local;
 use yew::{prelude::*, props};
 
-use super::nostr_relay::UserRelay;
+use super::nostr_relay::{
+    relay_transaction, RelayConnectionState, RelayRoute, RemovedRelay, UserRelay,
+};
+use super::note_cache::{CachedNote, PendingNote};
+use super::note_store::{NoteStoreConfig, NoteStoreHandle};
+use super::profile::{NostrProfile, ProfileCache, METADATA_KIND};
+use super::publish::{
+    PublishRequest, PublishStatus, PublishTracker, PublishUpdate, RelayOk, PUBLISH_TIMEOUT_MS,
+};
+use super::relay_auth::{auth_note, RelayAuthState};
+use super::relay_connection::{RelayCallbacks, RelayCommand, RelayHandle, RelayWorker};
+use super::relay_events::RelayEventLog;
+use super::relay_list::{
+    subscription_authors, AuthorRelayList, RelayListDirectory, RELAY_LIST_KIND,
+};
+use super::relay_notice::RelayNotice;
+use super::subscription::{subscription_matches, NostrSubscriptions};
+
+/// How many of an author's write relays an author filter is sent to.
+const MAX_RELAYS_PER_AUTHOR: usize = 3;
 
 #[derive(Clone, Debug, Properties, PartialEq)]
 pub struct RelayContextProps {
     pub children: Children,
+    /// Relays the user added, changed or removed are kept in IndexedDB and
+    /// merged in at startup, see `UserRelay::merge_stored`. New props replace
+    /// the list.
     pub relays: Vec<UserRelay>,
+    #[prop_or_default]
+    pub note_store: NoteStoreConfig,
+    /// Keep received notes and unsent notes in IndexedDB between sessions.
+    #[prop_or_default]
+    pub offline_cache: bool,
+    /// Receives relay lifecycle events, see `RelayToaster` for default toasts.
+    #[prop_or_default]
+    pub on_notice: Callback<RelayNotice>,
 }
 
 pub enum RelayAction {
     Event(RelayEvents),
-    UniqueNote(SignedNote),
+    UniqueNote(String, SignedNote),
+    EndOfStoredEvents(String, String),
     SendNote(SignedNote),
+    SendNoteTo(SignedNote, Vec<String>),
+    Publish(PublishRequest),
+    RelayOk(RelayOk),
+    RetryPublish(String, String),
+    PublishTimeout(String, String, u32),
     Subscribe(NostrSubscription),
+    SubscribeTo(NostrSubscription, Vec<String>),
     Unsubscribe(String),
+    RelayState(RelayConnectionState),
+    IdentityChanged(NostrIdStore),
+    Notice(RelayNotice),
+    AddRelay(UserRelay),
+    RemoveRelay(String),
+    UpdateRelay(UserRelay),
+    LoadStoredRelays(Vec<UserRelay>, Vec<RemovedRelay>),
+    LoadCachedNotes(Vec<SignedNote>),
+    LoadPendingNotes(Vec<(SignedNote, RelayRoute)>),
+    RequestProfiles(Vec<String>),
+    LoadProfiles(Vec<NostrProfile>),
     Close,
 }
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct NostrProps {
-    pub relay_events: Vec<RelayEvents>,
-    pub unique_notes: Vec<SignedNote>,
+    /// Connection and NIP-42 auth state of every user relay.
+    pub relay_states: Vec<RelayConnectionState>,
     pub send_note: Callback<SignedNote>,
+    pub send_note_to: Callback<(SignedNote, Vec<String>)>,
+    pub publish: Callback<PublishRequest>,
     pub subscribe: Callback<NostrSubscription>,
+    /// Subscribes on the given relays only, connecting any that are not running.
+    pub subscribe_to: Callback<(NostrSubscription, Vec<String>)>,
     pub unsubscribe: Callback<String>,
+    pub add_relay: Callback<UserRelay>,
+    pub remove_relay: Callback<String>,
+    pub update_relay: Callback<UserRelay>,
     pub close: Callback<()>,
 }
+impl NostrProps {
+    pub fn relay_auth(&self, relay_url: &str) -> Option<&RelayAuthState> {
+        self.relay_states
+            .iter()
+            .find(|state| state.relay.url == relay_url)
+            .map(|state| &state.auth)
+    }
+}
 pub struct RelayProvider {
-    relay_events: Vec<RelayEvents>,
-    unique_notes: Vec<SignedNote>,
-    sender_channel: Sender<SignedNote>,
-    filter_channel: Sender<NostrSubscription>,
-    unsubscribe_channel: Sender<String>,
-    close_channel: Sender<()>,
+    relay_events: RelayEventLog,
+    note_store: NoteStoreHandle,
+    offline_cache: bool,
+    outbox: Vec<(SignedNote, RelayRoute)>,
+    auth_signer: Option<NostrSigner>,
+    auth_challenges: HashMap<String, String>,
+    identity: Option<NostrIdStore>,
+    active_pubkey: Option<String>,
+    _identity_handle: Option<ContextHandle<NostrIdStore>>,
+    profiles: ProfileCache,
+    requested_profiles: HashSet<String>,
+    profile_subscriptions: HashSet<String>,
+    publishes: HashMap<String, PublishTracker>,
+    relay_states: Vec<RelayConnectionState>,
+    relay_handles: Vec<RelayHandle>,
+    subscriptions: HashMap<String, NostrSubscription>,
+    /// Subscriptions opened while the offline cache loads. They are sent once
+    /// its notes are in, asking only for newer ones.
+    held_subscriptions: Option<Vec<NostrSubscription>>,
+    relay_lists: RelayListDirectory,
+    requested_relay_lists: HashSet<String>,
+    relay_list_subscriptions: HashSet<String>,
+    discovered_relays: HashSet<String>,
+    subscription_streams: NostrSubscriptions,
+    end_of_stored_events: HashMap<String, HashSet<String>>,
     send_note_callback: Callback<SignedNote>,
+    send_note_to_callback: Callback<(SignedNote, Vec<String>)>,
+    publish_callback: Callback<PublishRequest>,
     subscribe_callback: Callback<NostrSubscription>,
+    subscribe_to_callback: Callback<(NostrSubscription, Vec<String>)>,
     unsubscribe_callback: Callback<String>,
+    add_relay_callback: Callback<UserRelay>,
+    remove_relay_callback: Callback<String>,
+    update_relay_callback: Callback<UserRelay>,
     close_callback: Callback<()>,
     children: Children,
 }
@@ -60,7 +150,15 @@ impl Component for RelayProvider {
         html! {
             <>
                 <ContextProvider<NostrProps> context={props}>
-                    {self.children.clone()}
+                    <ContextProvider<NostrSubscriptions> context={self.subscription_streams.clone()}>
+                        <ContextProvider<ProfileCache> context={self.profiles.clone()}>
+                            <ContextProvider<NoteStoreHandle> context={self.note_store.clone()}>
+                                <ContextProvider<RelayEventLog> context={self.relay_events.clone()}>
+                                    {self.children.clone()}
+                                </ContextProvider<RelayEventLog>>
+                            </ContextProvider<NoteStoreHandle>>
+                        </ContextProvider<ProfileCache>>
+                    </ContextProvider<NostrSubscriptions>>
                 </ContextProvider<NostrProps>>
             </>
         }
@@ -68,51 +166,218 @@ impl Component for RelayProvider {
 
     fn create(ctx: &Context<Self>) -> Self {
         let relays = ctx.props().relays.clone();
-        let (sender_channel, filter_channel, unsubscribe_channel, close_channel) =
-            Self::read_relays(
-                ctx.link().callback(RelayAction::Event),
-                ctx.link().callback(RelayAction::UniqueNote),
-                relays,
-            );
+        let relay_states = relays
+            .iter()
+            .cloned()
+            .map(RelayConnectionState::new)
+            .collect();
+        let relay_handles = relays
+            .into_iter()
+            .map(|relay| Self::spawn_relay(ctx, relay))
+            .collect();
+        let stored_relays_callback = ctx
+            .link()
+            .callback(|(stored, removed)| RelayAction::LoadStoredRelays(stored, removed));
+        spawn_local(async move {
+            let stored = async {
+                let relays = UserRelay::retrieve_all_from_store().await?;
+                let removed = RemovedRelay::retrieve_all_from_store().await?;
+                Ok::<_, MinionsError>((relays, removed))
+            };
+            match stored.await {
+                Ok(stored) => stored_relays_callback.emit(stored),
+                Err(e) => gloo::console::error!("Error loading stored relays: {:?}", e),
+            }
+        });
         let send_note_callback = ctx.link().callback(RelayAction::SendNote);
+        let send_note_to_callback = ctx
+            .link()
+            .callback(|(note, relays)| RelayAction::SendNoteTo(note, relays));
+        let publish_callback = ctx.link().callback(RelayAction::Publish);
         let close_callback = ctx.link().callback(move |_| RelayAction::Close);
         let subscribe_callback = ctx.link().callback(RelayAction::Subscribe);
+        let subscribe_to_callback = ctx
+            .link()
+            .callback(|(subscription, relays)| RelayAction::SubscribeTo(subscription, relays));
         let unsubscribe_callback = ctx.link().callback(RelayAction::Unsubscribe);
+        let add_relay_callback = ctx.link().callback(RelayAction::AddRelay);
+        let remove_relay_callback = ctx.link().callback(RelayAction::RemoveRelay);
+        let update_relay_callback = ctx.link().callback(RelayAction::UpdateRelay);
+        let subscription_streams =
+            NostrSubscriptions::new(subscribe_callback.clone(), unsubscribe_callback.clone());
         let children = ctx.props().children.clone();
-        let relay_events = Vec::new();
-        let unique_notes = Vec::new();
+        let relay_events = RelayEventLog::default();
+        let note_store = NoteStoreHandle::new(ctx.props().note_store);
+        let offline_cache = ctx.props().offline_cache;
+        if offline_cache {
+            Self::load_offline_cache(ctx);
+        }
+        // Relay AUTH is signed by whichever signer the NostrIdProvider above us
+        // holds, a NIP-46 signer also sends its requests through us.
+        let (identity, identity_handle) = match ctx
+            .link()
+            .context::<NostrIdStore>(ctx.link().callback(RelayAction::IdentityChanged))
+        {
+            Some((identity, handle)) => (Some(identity), Some(handle)),
+            None => (None, None),
+        };
+        let auth_signer = identity.as_ref().and_then(|identity| identity.get_signer());
+        let active_pubkey = identity
+            .as_ref()
+            .and_then(|identity| identity.active_pubkey());
+        let profiles = ProfileCache::new(ctx.link().callback(RelayAction::RequestProfiles));
+        let stored_profiles_callback = ctx.link().callback(RelayAction::LoadProfiles);
+        spawn_local(async move {
+            match NostrProfile::retrieve_all_from_store().await {
+                Ok(profiles) => stored_profiles_callback.emit(profiles),
+                Err(e) => gloo::console::error!("Error loading stored profiles: {:?}", e),
+            }
+        });
 
-        Self {
+        let mut provider = Self {
             relay_events,
-            unique_notes,
-            sender_channel,
-            close_channel,
-            filter_channel,
-            unsubscribe_channel,
+            note_store,
+            offline_cache,
+            outbox: Vec::new(),
+            auth_signer,
+            auth_challenges: HashMap::new(),
+            identity,
+            active_pubkey: None,
+            _identity_handle: identity_handle,
+            profiles,
+            requested_profiles: HashSet::new(),
+            profile_subscriptions: HashSet::new(),
+            publishes: HashMap::new(),
+            relay_states,
+            relay_handles,
+            subscriptions: HashMap::new(),
+            held_subscriptions: offline_cache.then(Vec::new),
+            relay_lists: RelayListDirectory::default(),
+            requested_relay_lists: HashSet::new(),
+            relay_list_subscriptions: HashSet::new(),
+            discovered_relays: HashSet::new(),
+            subscription_streams,
+            end_of_stored_events: HashMap::new(),
             send_note_callback,
+            send_note_to_callback,
+            publish_callback,
             close_callback,
             subscribe_callback,
+            subscribe_to_callback,
             unsubscribe_callback,
+            add_relay_callback,
+            remove_relay_callback,
+            update_relay_callback,
             children,
+        };
+        provider.attach_remote_signer();
+        provider.follow_active_profile(active_pubkey);
+        provider
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>, old_props: &Self::Properties) -> bool {
+        self.children = ctx.props().children.clone();
+        if ctx.props().relays != old_props.relays {
+            self.sync_relays(ctx, ctx.props().relays.clone(), true);
         }
+        true
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            RelayAction::SendNote(note) => match self.send_nostr_note(note) {
-                Ok(_) => true,
-                Err(e) => {
-                    gloo::console::error!("Error sending note: {:?}", e);
-                    false
+            RelayAction::SendNote(note) => {
+                match self.send_nostr_note(ctx, note, RelayRoute::Write, None) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        gloo::console::error!("Error sending note: {:?}", e);
+                        false
+                    }
                 }
-            },
-            RelayAction::Subscribe(filter) => match self.subscribe(filter) {
+            }
+            RelayAction::SendNoteTo(note, relays) => {
+                for url in &relays {
+                    self.discover_relay(ctx, url);
+                }
+                match self.send_nostr_note(ctx, note, RelayRoute::Relays(relays), None) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        gloo::console::error!("Error sending note: {:?}", e);
+                        false
+                    }
+                }
+            }
+            RelayAction::Publish(request) => {
+                let route = match request.relays {
+                    Some(relays) => RelayRoute::Relays(relays),
+                    None => RelayRoute::Write,
+                };
+                match self.send_nostr_note(ctx, request.note, route, Some(request.on_status)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        gloo::console::error!("Error publishing note: {:?}", e);
+                        false
+                    }
+                }
+            }
+            RelayAction::RelayOk(ok) => {
+                let note_id = ok.note_id.clone();
+                let update = match self.publishes.get_mut(&note_id) {
+                    Some(tracker) => tracker.record_ok(&ok),
+                    None => return false,
+                };
+                if let PublishUpdate::Retry(delay) = update {
+                    let retry = ctx.link().callback(|(note_id, relay_url)| {
+                        RelayAction::RetryPublish(note_id, relay_url)
+                    });
+                    spawn_local(async move {
+                        TimeoutFuture::new(delay).await;
+                        retry.emit((note_id, ok.relay_url));
+                    });
+                    return false;
+                }
+                self.publish_updated(ctx, &note_id, update);
+                false
+            }
+            RelayAction::RetryPublish(note_id, relay_url) => {
+                let Some(tracker) = self.publishes.get(&note_id) else {
+                    return false;
+                };
+                let note = tracker.note.clone();
+                if let Err(e) = self.dispatch_note(ctx, note, &RelayRoute::Relays(vec![relay_url]))
+                {
+                    gloo::console::error!("Error retrying note: {:?}", e);
+                }
+                false
+            }
+            RelayAction::PublishTimeout(note_id, relay_url, attempt) => {
+                let update = match self.publishes.get_mut(&note_id) {
+                    Some(tracker) => tracker.time_out(&relay_url, attempt),
+                    None => return false,
+                };
+                self.publish_updated(ctx, &note_id, update);
+                false
+            }
+            RelayAction::Subscribe(filter) => match self.subscribe(ctx, filter) {
                 Ok(_) => true,
                 Err(e) => {
                     gloo::console::error!("Error subscribing: {:?}", e);
                     false
                 }
             },
+            RelayAction::SubscribeTo(subscription, relays) => {
+                for url in &relays {
+                    self.discover_relay(ctx, url);
+                }
+                match self.send_to_relays(&RelayRoute::Relays(relays), || {
+                    RelayCommand::Subscribe(subscription.clone())
+                }) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        gloo::console::error!("Error subscribing: {:?}", e);
+                        false
+                    }
+                }
+            }
             RelayAction::Close => {
                 match self.close_ws() {
                     Ok(_) => (),
@@ -121,16 +386,106 @@ impl Component for RelayProvider {
                 false
             }
             RelayAction::Event(event) => {
-                if let RelayEvents::EVENT(_, ref _note) = event {
-                    // Add notification for new event.
-                    ToastifyOptions::new_event_received("note").show();
+                self.relay_events.emit(event);
+                false
+            }
+            RelayAction::Notice(notice) => {
+                ctx.props().on_notice.emit(notice);
+                false
+            }
+            RelayAction::UniqueNote(_, note) if note.get_kind() == NIP46_KIND => {
+                // Remote signer traffic is ephemeral, it never reaches the stores.
+                if let Some(NostrSigner::Remote(signer)) = &self.auth_signer {
+                    signer.handle_response(&note);
                 }
-                self.add_event(event);
-                true
+                false
             }
-            RelayAction::UniqueNote(note) => {
-                self.add_unique_note(note);
-                true
+            RelayAction::UniqueNote(subscription_id, note) => {
+                self.subscription_streams
+                    .note(&subscription_id, note.clone());
+                self.add_relay_list(ctx, &note);
+                let profile_changed = NostrProfile::from_note(&note)
+                    .map(|profile| self.add_profile(profile, true))
+                    .unwrap_or_default();
+                let inserted = self.note_store.insert(note.clone());
+                if inserted {
+                    ctx.props()
+                        .on_notice
+                        .emit(RelayNotice::NoteReceived(note.clone()));
+                }
+                if inserted && self.offline_cache {
+                    spawn_local(async move {
+                        if let Err(e) = CachedNote::from(note).save_to_store().await {
+                            gloo::console::error!("Error caching note: {:?}", e);
+                        }
+                    });
+                }
+                inserted || profile_changed
+            }
+            RelayAction::LoadCachedNotes(notes) => {
+                let changed = notes.iter().fold(false, |inserted, note| {
+                    self.add_relay_list(ctx, note);
+                    let profile_changed = NostrProfile::from_note(note)
+                        .map(|profile| self.add_profile(profile, false))
+                        .unwrap_or_default();
+                    self.note_store.insert(note.clone()) || profile_changed || inserted
+                });
+                self.release_held_subscriptions(ctx, &notes);
+                changed
+            }
+            RelayAction::RequestProfiles(pubkeys) => {
+                self.request_profiles(pubkeys);
+                false
+            }
+            RelayAction::LoadProfiles(profiles) => {
+                profiles.into_iter().fold(false, |changed, profile| {
+                    self.add_profile(profile, false) || changed
+                })
+            }
+            RelayAction::LoadPendingNotes(notes) => {
+                self.outbox.extend(notes);
+                self.flush_outbox(ctx);
+                false
+            }
+            RelayAction::EndOfStoredEvents(relay_url, subscription_id) => {
+                let finished = self.add_end_of_stored_events(relay_url, subscription_id.clone());
+                let one_shot = self.relay_list_subscriptions.contains(&subscription_id)
+                    || self.profile_subscriptions.contains(&subscription_id);
+                if finished && one_shot {
+                    self.relay_list_subscriptions.remove(&subscription_id);
+                    self.profile_subscriptions.remove(&subscription_id);
+                    if let Err(e) = self.unsubscribe(subscription_id) {
+                        gloo::console::error!("Error closing one-shot request: {:?}", e);
+                    }
+                }
+                false
+            }
+            RelayAction::RelayState(state) => {
+                match state.auth.challenge() {
+                    Some(challenge) => {
+                        self.auth_challenges
+                            .insert(state.relay.url.clone(), challenge.to_string());
+                        self.authenticate_relay(&state.relay.url, challenge);
+                    }
+                    None => {
+                        self.auth_challenges.remove(&state.relay.url);
+                    }
+                }
+                let changed = self.set_relay_state(state);
+                self.flush_outbox(ctx);
+                changed
+            }
+            RelayAction::IdentityChanged(identity) => {
+                let signer = identity.get_signer();
+                let active_pubkey = identity.active_pubkey();
+                self.identity = Some(identity);
+                if signer != self.auth_signer {
+                    self.auth_signer = signer;
+                    self.attach_remote_signer();
+                    self.authenticate_relays();
+                }
+                self.follow_active_profile(active_pubkey);
+                false
             }
             RelayAction::Unsubscribe(filter) => match self.unsubscribe(filter) {
                 Ok(_) => true,
@@ -139,6 +494,35 @@ impl Component for RelayProvider {
                     false
                 }
             },
+            RelayAction::AddRelay(relay) => match self.add_relay(ctx, relay, true) {
+                Ok(_) => true,
+                Err(e) => {
+                    gloo::console::error!("Error adding relay: {:?}", e);
+                    false
+                }
+            },
+            RelayAction::RemoveRelay(url) => match self.remove_relay(&url, true) {
+                Ok(_) => true,
+                Err(e) => {
+                    gloo::console::error!("Error removing relay: {:?}", e);
+                    false
+                }
+            },
+            RelayAction::UpdateRelay(relay) => match self.update_relay(relay, true) {
+                Ok(_) => true,
+                Err(e) => {
+                    gloo::console::error!("Error updating relay: {:?}", e);
+                    false
+                }
+            },
+            RelayAction::LoadStoredRelays(stored, removed) => {
+                if stored.is_empty() && removed.is_empty() {
+                    return false;
+                }
+                let relays = UserRelay::merge_stored(&ctx.props().relays, stored, &removed);
+                self.sync_relays(ctx, relays, false);
+                true
+            }
         }
     }
 
@@ -151,151 +535,661 @@ impl Component for RelayProvider {
 }
 
 impl RelayProvider {
-    fn read_relays(
-        event_cb: Callback<RelayEvents>,
-        note_cb: Callback<SignedNote>,
-        relays: Vec<UserRelay>,
-    ) -> (
-        Sender<SignedNote>,
-        Sender<NostrSubscription>,
-        Sender<String>,
-        Sender<()>,
-    ) {
-        let (send_note_tx, send_note_rx) = unbounded::<SignedNote>();
-        let (filter_tx, filter_rx) = unbounded::<NostrSubscription>();
-        let (unsubscribe_tx, unsubscribe_rx) = unbounded::<String>();
-        let (close_tx, close_rx) = unbounded::<()>();
-        
+    fn spawn_relay(ctx: &Context<Self>, relay: UserRelay) -> RelayHandle {
+        let callbacks = RelayCallbacks {
+            event: ctx.link().callback(RelayAction::Event),
+            note: ctx
+                .link()
+                .callback(|(subscription_id, note)| RelayAction::UniqueNote(subscription_id, note)),
+            end_of_stored_events: ctx.link().callback(|(relay_url, subscription_id)| {
+                RelayAction::EndOfStoredEvents(relay_url, subscription_id)
+            }),
+            ok: ctx.link().callback(RelayAction::RelayOk),
+            state: ctx.link().callback(RelayAction::RelayState),
+            notice: ctx.link().callback(RelayAction::Notice),
+        };
+        RelayWorker::spawn(relay, callbacks)
+    }
+
+    /// Cached notes are loaded straight into the note store and the matching
+    /// subscription streams, so screens have data before the first relay
+    /// answers. The cache is pruned to the note store capacity first, it only
+    /// grows during a session.
+    fn load_offline_cache(ctx: &Context<Self>) {
+        let cached_notes_callback = ctx.link().callback(RelayAction::LoadCachedNotes);
+        let pending_notes_callback = ctx.link().callback(RelayAction::LoadPendingNotes);
+        let capacity = u32::try_from(ctx.props().note_store.capacity).unwrap_or(u32::MAX);
         spawn_local(async move {
-            // Show initial connection attempt
-            ToastifyOptions::new_relay_connected("Connecting to relay pool").show();
-            
-            let relay_pool = match nostro2::pool::RelayPool::new(
-                relays.iter().map(|relay| relay.url.clone()).collect(),
-            ).await {
-                Ok(pool) => {
-                    ToastifyOptions::new_relay_connected("Connected to relay pool").show();
-                    pool
-                },
+            if let Err(e) = CachedNote::prune(capacity).await {
+                gloo::console::error!("Error pruning cached notes: {:?}", e);
+            }
+            match CachedNote::load(capacity).await {
+                Ok(cached) => cached_notes_callback
+                    .emit(cached.into_iter().map(|cached| cached.note).collect()),
                 Err(e) => {
-                    ToastifyOptions::new_relay_error(&format!("Failed to create relay pool: {}", e))
-                        .show();
-                    return;
-                }
-            };
-    
-            let pooled_notes = relay_pool.pooled_notes();
-            let relay_events = relay_pool.all_events();
-            
-            loop {
-                tokio::select! {
-                    event = relay_events.recv() => {
-                        if let Ok(event) = event {
-                            event_cb.emit(event);
-                        }
-                    }
-                    note = pooled_notes.recv() => {
-                        if let Ok(event) = note {
-                            note_cb.emit(event);
-                            // Show notification for new note
-                            ToastifyOptions::new_event_received("note").show();
-                        }
-                    }
-                    note = send_note_rx.recv() => {
-                        if let Ok(note) = note {
-                            if let Err(e) = relay_pool.broadcast_note(note).await {
-                                ToastifyOptions::new_relay_error(&format!("Error broadcasting note: {}", e))
-                                    .show();
-                            }
-                        }
-                    }
-                    filter = filter_rx.recv() => {
-                        if let Ok(filter) = filter {
-                            if let Err(e) = relay_pool.subscribe(filter).await {
-                                ToastifyOptions::new_relay_error(&format!("Error subscribing: {}", e))
-                                    .show();
-                            }
-                        }
-                    }
-                    unsubscribe = unsubscribe_rx.recv() => {
-                        if let Ok(filter) = unsubscribe {
-                            if let Err(e) = relay_pool.cancel_subscription(filter).await {
-                                ToastifyOptions::new_relay_error(&format!("Error unsubscribing: {}", e))
-                                    .show();
-                            }
-                        }
-                    }
-                    _ = close_rx.recv() => {
-                        ToastifyOptions::new_relay_disconnected("Disconnecting from relay pool").show();
-                        let _ = relay_pool.close().await;
-                        break;
-                    }
+                    gloo::console::error!("Error loading cached notes: {:?}", e);
+                    // Held subscriptions still go out, with nothing cached.
+                    cached_notes_callback.emit(Vec::new());
                 }
             }
-            relay_pool.close().await.unwrap();
+            match PendingNote::load().await {
+                Ok(pending) => pending_notes_callback.emit(
+                    pending
+                        .into_iter()
+                        .map(|pending| (pending.note, pending.route))
+                        .collect(),
+                ),
+                Err(e) => gloo::console::error!("Error loading pending notes: {:?}", e),
+            }
         });
-        
-        (send_note_tx, filter_tx, unsubscribe_tx, close_tx)
     }
 
-    pub fn build_props(&self) -> NostrProps {
-        let _unique_notes = self
-            .relay_events
+    /// Brings the running relays in line with `relays`. Relays that stay keep
+    /// their sockets and subscriptions.
+    fn sync_relays(&mut self, ctx: &Context<Self>, relays: Vec<UserRelay>, persist: bool) {
+        let removed: Vec<UserRelay> = self
+            .relay_handles
             .iter()
-            .filter_map(|event| match event {
-                RelayEvents::EVENT(_, note) => Some(note.clone()),
-                _ => None,
-            })
-            .fold(HashMap::new(), |mut acc, note| {
-                acc.insert(note.get_id().to_string(), note);
-                acc
+            .filter(|handle| !self.discovered_relays.contains(&handle.relay.url))
+            .filter(|handle| !relays.iter().any(|relay| relay.url == handle.relay.url))
+            .map(|handle| handle.relay.clone())
+            .collect();
+        for relay in &removed {
+            if let Err(e) = self.remove_relay(&relay.url, false) {
+                gloo::console::error!("Error removing relay: {:?}", e);
+            }
+        }
+        for relay in relays.clone() {
+            if let Err(e) = self.add_relay(ctx, relay, false) {
+                gloo::console::error!("Error adding relay: {:?}", e);
+            }
+        }
+        if persist {
+            Self::persist_relay_list(relays, removed);
+        }
+    }
+
+    fn add_relay(
+        &mut self,
+        ctx: &Context<Self>,
+        relay: UserRelay,
+        persist: bool,
+    ) -> Result<(), JsValue> {
+        if self
+            .relay_handles
+            .iter()
+            .any(|handle| handle.relay.url == relay.url)
+        {
+            self.discovered_relays.remove(&relay.url);
+            return self.update_relay(relay, persist);
+        }
+        let handle = Self::spawn_relay(ctx, relay.clone());
+        if relay.read {
+            for subscription in self.subscriptions.values() {
+                handle.send(RelayCommand::Subscribe(subscription.clone()))?;
+            }
+        }
+        self.relay_states
+            .push(RelayConnectionState::new(relay.clone()));
+        self.relay_handles.push(handle);
+        if persist {
+            Self::persist_relay(relay);
+        }
+        Ok(())
+    }
+
+    fn remove_relay(&mut self, url: &str, persist: bool) -> Result<(), JsValue> {
+        let position = self
+            .relay_handles
+            .iter()
+            .position(|handle| handle.relay.url == url)
+            .ok_or(JsValue::from_str("Relay not found"))?;
+        let handle = self.relay_handles.remove(position);
+        self.relay_states.retain(|state| state.relay.url != url);
+        self.discovered_relays.remove(url);
+        if persist {
+            let relay = handle.relay.clone();
+            spawn_local(async move {
+                let removed = async {
+                    let transaction = relay_transaction().await?;
+                    transaction.put(RemovedRelay {
+                        url: relay.url.clone(),
+                    })?;
+                    transaction.delete(&relay)?;
+                    transaction.commit().await
+                };
+                if let Err(e) = removed.await {
+                    gloo::console::error!("Error deleting stored relay: {:?}", e);
+                }
             });
+        }
+        handle.send(RelayCommand::Close)
+    }
+
+    fn update_relay(&mut self, relay: UserRelay, persist: bool) -> Result<(), JsValue> {
+        let subscriptions: Vec<NostrSubscription> = self.subscriptions.values().cloned().collect();
+        let handle = self
+            .relay_handles
+            .iter_mut()
+            .find(|handle| handle.relay.url == relay.url)
+            .ok_or(JsValue::from_str("Relay not found"))?;
+        if handle.relay == relay {
+            return Ok(());
+        }
+        let was_read = handle.relay.read;
+        handle.relay = relay.clone();
+        match (was_read, relay.read) {
+            (false, true) => {
+                for subscription in subscriptions {
+                    handle.send(RelayCommand::Subscribe(subscription))?;
+                }
+            }
+            (true, false) => {
+                for subscription in subscriptions {
+                    handle.send(RelayCommand::Unsubscribe(subscription.id()))?;
+                }
+            }
+            _ => {}
+        }
+        if let Some(state) = self
+            .relay_states
+            .iter_mut()
+            .find(|state| state.relay.url == relay.url)
+        {
+            state.relay = relay.clone();
+        }
+        if persist {
+            Self::persist_relay(relay);
+        }
+        Ok(())
+    }
+
+    fn persist_relay(relay: UserRelay) {
+        Self::persist_relay_list(vec![relay], vec![]);
+    }
+
+    /// Saves the whole relay list at once, a failure leaves the stored list as
+    /// it was. Saved relays are no longer marked as removed.
+    fn persist_relay_list(relays: Vec<UserRelay>, removed: Vec<UserRelay>) {
+        spawn_local(async move {
+            let saved = async {
+                let transaction = relay_transaction().await?;
+                let restored: Vec<RemovedRelay> = relays
+                    .iter()
+                    .map(|relay| RemovedRelay {
+                        url: relay.url.clone(),
+                    })
+                    .collect();
+                transaction.delete_many(&restored)?;
+                transaction.delete_many(&removed)?;
+                transaction.put_many(relays)?;
+                transaction.commit().await
+            };
+            if let Err(e) = saved.await {
+                gloo::console::error!("Error saving relay list: {:?}", e);
+            }
+        });
+    }
+
+    pub fn build_props(&self) -> NostrProps {
         props!(NostrProps {
-            relay_events: self.relay_events.clone(),
-            unique_notes: self.unique_notes.clone(),
+            relay_states: self.relay_states.clone(),
             send_note: self.send_note_callback.clone(),
+            send_note_to: self.send_note_to_callback.clone(),
+            publish: self.publish_callback.clone(),
             subscribe: self.subscribe_callback.clone(),
+            subscribe_to: self.subscribe_to_callback.clone(),
             unsubscribe: self.unsubscribe_callback.clone(),
+            add_relay: self.add_relay_callback.clone(),
+            remove_relay: self.remove_relay_callback.clone(),
+            update_relay: self.update_relay_callback.clone(),
             close: self.close_callback.clone(),
         })
     }
 
-    fn send_nostr_note(&self, signed_note: SignedNote) -> Result<(), JsValue> {
-        let _ = self
-            .sender_channel
-            .try_send(signed_note)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        Ok(())
+    /// Every note is tracked until each targeted relay answers OK or times
+    /// out. Notes sent while none of their relays is open wait in the outbox.
+    fn send_nostr_note(
+        &mut self,
+        ctx: &Context<Self>,
+        signed_note: SignedNote,
+        route: RelayRoute,
+        on_status: Option<Callback<PublishStatus>>,
+    ) -> Result<(), JsValue> {
+        let relay_urls: Vec<String> = self
+            .relay_handles
+            .iter()
+            .filter(|handle| route.accepts(&handle.relay))
+            .map(|handle| handle.relay.url.clone())
+            .collect();
+        if relay_urls.is_empty() {
+            return Err(JsValue::from_str(&format!("No relays match {:?}", route)));
+        }
+        let tracker = PublishTracker::new(signed_note.clone(), relay_urls, on_status);
+        tracker.notify();
+        self.publishes
+            .insert(signed_note.get_id().to_string(), tracker);
+        if !self.is_reachable(&route) {
+            if self.offline_cache {
+                let pending = PendingNote::new(signed_note.clone(), route.clone());
+                spawn_local(async move {
+                    if let Err(e) = pending.save_to_store().await {
+                        gloo::console::error!("Error saving pending note: {:?}", e);
+                    }
+                });
+            }
+            self.outbox.push((signed_note, route));
+            return Ok(());
+        }
+        self.dispatch_note(ctx, signed_note, &route)
     }
 
-    fn subscribe(&self, filter: NostrSubscription) -> Result<(), JsValue> {
-        let _ = self
-            .filter_channel
-            .try_send(filter)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    /// Sends the note and arms a timeout for every relay it goes to.
+    fn dispatch_note(
+        &mut self,
+        ctx: &Context<Self>,
+        note: SignedNote,
+        route: &RelayRoute,
+    ) -> Result<(), JsValue> {
+        self.send_to_relays(route, || RelayCommand::SendNote(note.clone()))?;
+        let note_id = note.get_id().to_string();
+        let Some(tracker) = self.publishes.get(&note_id) else {
+            return Ok(());
+        };
+        for (relay_url, _) in tracker.status.relays.iter().filter(|(url, _)| {
+            self.relay_handles
+                .iter()
+                .any(|handle| &handle.relay.url == url && route.accepts(&handle.relay))
+        }) {
+            let attempt = tracker.attempt(relay_url);
+            let timeout = ctx.link().callback(|(note_id, relay_url, attempt)| {
+                RelayAction::PublishTimeout(note_id, relay_url, attempt)
+            });
+            let note_id = note_id.clone();
+            let relay_url = relay_url.clone();
+            spawn_local(async move {
+                TimeoutFuture::new(PUBLISH_TIMEOUT_MS).await;
+                timeout.emit((note_id, relay_url, attempt));
+            });
+        }
         Ok(())
     }
 
-    fn unsubscribe(&self, filter: String) -> Result<(), JsValue> {
-        let _ = self
-            .unsubscribe_channel
-            .try_send(filter)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        Ok(())
+    fn publish_updated(&mut self, ctx: &Context<Self>, note_id: &str, update: PublishUpdate) {
+        if !matches!(update, PublishUpdate::Updated) {
+            return;
+        }
+        if let Some(tracker) = self.publishes.get(note_id) {
+            tracker.notify();
+            if tracker.status.is_settled() {
+                ctx.props()
+                    .on_notice
+                    .emit(RelayNotice::Published(tracker.status.clone()));
+                self.publishes.remove(note_id);
+            }
+        }
+    }
+
+    fn flush_outbox(&mut self, ctx: &Context<Self>) {
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.outbox)
+            .into_iter()
+            .partition(|(_, route)| self.is_reachable(route));
+        self.outbox = waiting;
+        for (note, route) in ready {
+            if let Err(e) = self.dispatch_note(ctx, note.clone(), &route) {
+                gloo::console::error!("Error sending queued note: {:?}", e);
+                continue;
+            }
+            if self.offline_cache {
+                spawn_local(async move {
+                    if let Err(e) = PendingNote::new(note, route).delete_from_store().await {
+                        gloo::console::error!("Error clearing pending note: {:?}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    fn is_reachable(&self, route: &RelayRoute) -> bool {
+        self.relay_states
+            .iter()
+            .any(|state| state.is_open() && route.accepts(&state.relay))
     }
 
-    fn add_event(&mut self, event: RelayEvents) {
-        self.relay_events.push(event);
+    fn subscribe(&mut self, ctx: &Context<Self>, filter: NostrSubscription) -> Result<(), JsValue> {
+        if let Some(held) = self.held_subscriptions.as_mut() {
+            held.push(filter);
+            return Ok(());
+        }
+        self.replay_stored_notes(&filter);
+        self.open_subscription(ctx, filter, None)
     }
-    fn add_unique_note(&mut self, note: SignedNote) {
-        self.unique_notes.push(note);
+
+    fn open_subscription(
+        &mut self,
+        ctx: &Context<Self>,
+        filter: NostrSubscription,
+        since: Option<u64>,
+    ) -> Result<(), JsValue> {
+        self.subscriptions.insert(filter.id(), filter.clone());
+        self.route_to_authors(ctx, &filter);
+        self.send_to_relays(&RelayRoute::Read, || match since {
+            Some(since) => RelayCommand::SubscribeSince(filter.clone(), since),
+            None => RelayCommand::Subscribe(filter.clone()),
+        })
+    }
+
+    /// Startup subscriptions get their cached notes right away and only ask
+    /// relays for notes newer than the newest of them.
+    fn release_held_subscriptions(&mut self, ctx: &Context<Self>, cached: &[SignedNote]) {
+        for subscription in self.held_subscriptions.take().unwrap_or_default() {
+            let mut since = None;
+            for note in cached
+                .iter()
+                .filter(|note| subscription_matches(&subscription, note))
+            {
+                since = since.max(Some(note.get_created_at()));
+                self.subscription_streams
+                    .note(&subscription.id(), note.clone());
+            }
+            if let Err(e) = self.open_subscription(ctx, subscription, since) {
+                gloo::console::error!("Error subscribing: {:?}", e);
+            }
+        }
+    }
+
+    /// A new subscription starts with the matching notes already received.
+    fn replay_stored_notes(&self, subscription: &NostrSubscription) {
+        let notes = self.note_store.notes().latest(usize::MAX);
+        for note in notes
+            .into_iter()
+            .filter(|note| subscription_matches(subscription, note))
+        {
+            self.subscription_streams
+                .note(&subscription.id(), (*note).clone());
+        }
+    }
+
+    /// Workers only close subscriptions they hold, so every relay is told,
+    /// including discovered ones.
+    fn unsubscribe(&mut self, filter: String) -> Result<(), JsValue> {
+        if let Some(held) = self.held_subscriptions.as_mut() {
+            held.retain(|subscription| subscription.id() != filter);
+        }
+        self.subscriptions.remove(&filter);
+        self.end_of_stored_events.remove(&filter);
+        self.send_to_relays(&RelayRoute::All, || {
+            RelayCommand::Unsubscribe(filter.clone())
+        })
+    }
+
+    /// Outbox model: author filters also go to the write relays the authors
+    /// publish in their NIP-65 relay lists. Unknown lists are requested first
+    /// and the subscription follows once they arrive.
+    fn route_to_authors(&mut self, ctx: &Context<Self>, subscription: &NostrSubscription) {
+        let authors = subscription_authors(subscription);
+        if authors.is_empty() {
+            return;
+        }
+        self.request_relay_lists(&authors);
+        for url in self
+            .relay_lists
+            .write_relays_for(&authors, MAX_RELAYS_PER_AUTHOR)
+        {
+            if let Err(e) = self.send_to_discovered(ctx, url, subscription.clone()) {
+                gloo::console::error!("Error routing subscription: {:?}", e);
+            }
+        }
+    }
+
+    fn request_relay_lists(&mut self, authors: &[String]) {
+        let missing: Vec<String> = authors
+            .iter()
+            .filter(|author| self.relay_lists.get(author).is_none())
+            .filter(|author| !self.requested_relay_lists.contains(*author))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        self.requested_relay_lists.extend(missing.iter().cloned());
+        let request = NostrFilter::default()
+            .new_kind(RELAY_LIST_KIND)
+            .new_authors(missing)
+            .subscribe();
+        self.relay_list_subscriptions.insert(request.id());
+        if let Err(e) = self.send_to_relays(&RelayRoute::Read, || {
+            RelayCommand::Subscribe(request.clone())
+        }) {
+            gloo::console::error!("Error requesting relay lists: {:?}", e);
+        }
+    }
+
+    /// Hands the active account's profile to the `NostrIdProvider`, fetching
+    /// it first if needed.
+    fn follow_active_profile(&mut self, active_pubkey: Option<String>) {
+        if active_pubkey == self.active_pubkey {
+            return;
+        }
+        self.active_pubkey = active_pubkey.clone();
+        let Some(pubkey) = active_pubkey else {
+            return;
+        };
+        match (self.profiles.get(&pubkey), &self.identity) {
+            (Some(profile), Some(identity)) => {
+                identity.dispatch(NostrIdAction::LoadProfile(profile));
+            }
+            _ => self.request_profiles(vec![pubkey]),
+        }
+    }
+
+    /// Kind 0 requests are batched and closed on EOSE, like relay list requests.
+    fn request_profiles(&mut self, pubkeys: Vec<String>) {
+        let missing: Vec<String> = pubkeys
+            .into_iter()
+            .filter(|pubkey| self.profiles.get(pubkey).is_none())
+            .filter(|pubkey| !self.requested_profiles.contains(pubkey))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        self.requested_profiles.extend(missing.iter().cloned());
+        let request = NostrFilter::default()
+            .new_kind(METADATA_KIND)
+            .new_authors(missing)
+            .subscribe();
+        self.profile_subscriptions.insert(request.id());
+        if let Err(e) = self.send_to_relays(&RelayRoute::Read, || {
+            RelayCommand::Subscribe(request.clone())
+        }) {
+            gloo::console::error!("Error requesting profiles: {:?}", e);
+        }
+    }
+
+    /// Returns true if the profile is newer than the cached one.
+    fn add_profile(&mut self, profile: NostrProfile, persist: bool) -> bool {
+        if !self.profiles.insert(profile.clone()) {
+            return false;
+        }
+        if let Some(identity) = &self.identity {
+            if self.active_pubkey.as_ref() == Some(&profile.pubkey) {
+                identity.dispatch(NostrIdAction::LoadProfile(profile.clone()));
+            }
+        }
+        if persist {
+            spawn_local(async move {
+                if let Err(e) = profile.save_to_store().await {
+                    gloo::console::error!("Error caching profile: {:?}", e);
+                }
+            });
+        }
+        true
+    }
+
+    fn add_relay_list(&mut self, ctx: &Context<Self>, note: &SignedNote) {
+        let Some(list) = AuthorRelayList::from_note(note) else {
+            return;
+        };
+        let pubkey = list.pubkey.clone();
+        if !self.relay_lists.insert(list) {
+            return;
+        }
+        let waiting: Vec<NostrSubscription> = self
+            .subscriptions
+            .values()
+            .filter(|subscription| subscription_authors(subscription).contains(&pubkey))
+            .cloned()
+            .collect();
+        for subscription in waiting {
+            self.route_to_authors(ctx, &subscription);
+        }
+    }
+
+    /// Discovered relays are neither read nor write relays of the user, they
+    /// only carry the subscriptions routed to them and are never persisted.
+    fn send_to_discovered(
+        &mut self,
+        ctx: &Context<Self>,
+        url: String,
+        subscription: NostrSubscription,
+    ) -> Result<(), JsValue> {
+        self.discover_relay(ctx, &url);
+        match self
+            .relay_handles
+            .iter()
+            .find(|handle| handle.relay.url == url)
+        {
+            Some(handle) if !handle.relay.read => {
+                handle.send(RelayCommand::Subscribe(subscription))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Connects `url` as a discovered relay unless it is already running.
+    fn discover_relay(&mut self, ctx: &Context<Self>, url: &str) {
+        if self
+            .relay_handles
+            .iter()
+            .any(|handle| handle.relay.url == url)
+        {
+            return;
+        }
+        let relay = UserRelay {
+            url: url.to_string(),
+            read: false,
+            write: false,
+        };
+        self.relay_handles
+            .push(Self::spawn_relay(ctx, relay.clone()));
+        self.relay_states.push(RelayConnectionState::new(relay));
+        self.discovered_relays.insert(url.to_string());
+    }
+
+    fn send_to_relays<F>(&self, route: &RelayRoute, command: F) -> Result<(), JsValue>
+    where
+        F: Fn() -> RelayCommand,
+    {
+        let mut handles = self
+            .relay_handles
+            .iter()
+            .filter(|handle| route.accepts(&handle.relay))
+            .peekable();
+        if handles.peek().is_none() {
+            return Err(JsValue::from_str(&format!("No relays match {:?}", route)));
+        }
+        handles.try_for_each(|handle| handle.send(command()))
+    }
+
+    /// A subscription has finished loading once every open read relay has
+    /// sent EOSE for it. Relays that are down do not hold it back.
+    fn add_end_of_stored_events(&mut self, relay_url: String, subscription_id: String) -> bool {
+        let finished = self
+            .end_of_stored_events
+            .entry(subscription_id.clone())
+            .or_default();
+        finished.insert(relay_url);
+        let waiting = self
+            .relay_states
+            .iter()
+            .filter(|state| state.relay.read && state.is_open())
+            .any(|state| !finished.contains(&state.relay.url));
+        if !waiting {
+            self.subscription_streams
+                .end_of_stored_events(&subscription_id);
+        }
+        !waiting
+    }
+    /// Workers report their own copy of the relay, so the provider's flags win
+    /// and reports from removed relays are ignored.
+    fn set_relay_state(&mut self, state: RelayConnectionState) -> bool {
+        match self
+            .relay_states
+            .iter_mut()
+            .find(|known| known.relay.url == state.relay.url)
+        {
+            Some(known) => {
+                known.status = state.status;
+                known.last_error = state.last_error;
+                known.retry_count = state.retry_count;
+                known.auth = state.auth;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A NIP-46 signer talks to its bunker over our relays.
+    fn attach_remote_signer(&self) {
+        if let Some(NostrSigner::Remote(signer)) = &self.auth_signer {
+            signer.attach(Nip46Transport {
+                send_note_to: self.send_note_to_callback.clone(),
+                subscribe_to: self.subscribe_to_callback.clone(),
+            });
+        }
+    }
+
+    /// Answers every open NIP-42 challenge, e.g. once keys become available.
+    fn authenticate_relays(&self) {
+        for (relay_url, challenge) in &self.auth_challenges {
+            self.authenticate_relay(relay_url, challenge);
+        }
+    }
+
+    fn authenticate_relay(&self, relay_url: &str, challenge: &str) {
+        let Some(signer) = self.auth_signer.clone() else {
+            return;
+        };
+        let Some(handle) = self
+            .relay_handles
+            .iter()
+            .find(|handle| handle.relay.url == relay_url)
+            .cloned()
+        else {
+            return;
+        };
+        let note = match auth_note(&signer.pubkey(), relay_url, challenge) {
+            Ok(note) => note,
+            Err(e) => {
+                gloo::console::error!("Error building auth note: {:?}", e.to_string());
+                return;
+            }
+        };
+        let challenge = challenge.to_string();
+        spawn_local(async move {
+            let sent = signer
+                .sign_event(note)
+                .await
+                .and_then(|note| handle.send(RelayCommand::Authenticate(challenge, note)));
+            if let Err(e) = sent {
+                gloo::console::error!("Error authenticating to relay: {:?}", e);
+            }
+        });
     }
 
     fn close_ws(&self) -> Result<(), JsValue> {
-        let _ = self
-            .close_channel
-            .try_send(())
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        Ok(())
+        self.relay_handles
+            .iter()
+            .try_for_each(|handle| handle.send(RelayCommand::Close))
     }
 }
@@ -0,0 +1,324 @@
+use std::{
+    cell::{Ref, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use nostro2::{
+    notes::SignedNote,
+    relays::{NostrFilter, NostrSubscription},
+};
+use yew::prelude::*;
+
+use super::note_store::note_tags;
+use crate::key_manager::NostrIdStore;
+
+/// Most notes a single subscription keeps, the oldest by `created_at` go first.
+pub const SUBSCRIPTION_CAPACITY: usize = 5_000;
+
+/// Notes of one subscription. Every state of the reducer shares it, so a new
+/// note is pushed in place instead of copying everything received so far.
+#[derive(Debug, Default)]
+struct StreamNotes {
+    notes: Vec<SignedNote>,
+    note_ids: HashSet<String>,
+}
+impl StreamNotes {
+    /// Returns false for duplicates, and for a note older than everything
+    /// kept once the stream is full.
+    fn insert(&mut self, note: SignedNote, capacity: usize) -> bool {
+        let id = note.get_id().to_string();
+        if capacity == 0 || self.note_ids.contains(&id) {
+            return false;
+        }
+        if self.notes.len() >= capacity {
+            let Some((oldest, created_at)) = self
+                .notes
+                .iter()
+                .enumerate()
+                .map(|(position, note)| (position, note.get_created_at()))
+                .min_by_key(|(_, created_at)| *created_at)
+            else {
+                return false;
+            };
+            if note.get_created_at() <= created_at {
+                return false;
+            }
+            let evicted = self.notes.remove(oldest);
+            self.note_ids.remove(evicted.get_id());
+        }
+        self.note_ids.insert(id);
+        self.notes.push(note);
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SubscriptionStream {
+    id: Option<String>,
+    notes: Rc<RefCell<StreamNotes>>,
+    revision: u64,
+    finished_loading: bool,
+}
+impl PartialEq for SubscriptionStream {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.notes, &other.notes)
+            && self.revision == other.revision
+            && self.id == other.id
+            && self.finished_loading == other.finished_loading
+    }
+}
+impl SubscriptionStream {
+    pub fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+    /// Notes in the order they arrived, at most `SUBSCRIPTION_CAPACITY`.
+    pub fn notes(&self) -> Ref<'_, [SignedNote]> {
+        Ref::map(self.notes.borrow(), |stream| stream.notes.as_slice())
+    }
+    pub fn latest(&self) -> Option<SignedNote> {
+        self.notes.borrow().notes.last().cloned()
+    }
+    /// True once every connected read relay has sent EOSE for this subscription.
+    pub fn finished_loading(&self) -> bool {
+        self.finished_loading
+    }
+}
+
+pub enum SubscriptionAction {
+    Open(String),
+    Note(SignedNote),
+    EndOfStoredEvents,
+    Clear,
+}
+impl Reducible for SubscriptionStream {
+    type Action = SubscriptionAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        match action {
+            SubscriptionAction::Open(id) => Rc::new(SubscriptionStream {
+                id: Some(id),
+                ..Default::default()
+            }),
+            SubscriptionAction::Note(note) => {
+                if !self.notes.borrow_mut().insert(note, SUBSCRIPTION_CAPACITY) {
+                    return self;
+                }
+                Rc::new(SubscriptionStream {
+                    revision: self.revision + 1,
+                    ..(*self).clone()
+                })
+            }
+            SubscriptionAction::EndOfStoredEvents => Rc::new(SubscriptionStream {
+                finished_loading: true,
+                ..(*self).clone()
+            }),
+            SubscriptionAction::Clear => Rc::new(SubscriptionStream::default()),
+        }
+    }
+}
+pub type SubscriptionHandle = UseReducerHandle<SubscriptionStream>;
+
+/// Routes notes to the components that opened each subscription. The context
+/// value never changes, so consumers only re-render for their own notes.
+#[derive(Clone)]
+pub struct NostrSubscriptions {
+    streams: Rc<RefCell<HashMap<String, UseReducerDispatcher<SubscriptionStream>>>>,
+    subscribe: Callback<NostrSubscription>,
+    unsubscribe: Callback<String>,
+}
+impl PartialEq for NostrSubscriptions {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.streams, &other.streams)
+    }
+}
+impl NostrSubscriptions {
+    pub fn new(subscribe: Callback<NostrSubscription>, unsubscribe: Callback<String>) -> Self {
+        Self {
+            streams: Rc::new(RefCell::new(HashMap::new())),
+            subscribe,
+            unsubscribe,
+        }
+    }
+    pub fn open(
+        &self,
+        subscription: NostrSubscription,
+        dispatcher: UseReducerDispatcher<SubscriptionStream>,
+    ) {
+        dispatcher.dispatch(SubscriptionAction::Open(subscription.id()));
+        self.streams
+            .borrow_mut()
+            .insert(subscription.id(), dispatcher);
+        self.subscribe.emit(subscription);
+    }
+    pub fn close(&self, subscription_id: String) {
+        self.streams.borrow_mut().remove(&subscription_id);
+        self.unsubscribe.emit(subscription_id);
+    }
+    pub fn note(&self, subscription_id: &str, note: SignedNote) {
+        if let Some(dispatcher) = self.dispatcher(subscription_id) {
+            dispatcher.dispatch(SubscriptionAction::Note(note));
+        }
+    }
+    pub fn end_of_stored_events(&self, subscription_id: &str) {
+        if let Some(dispatcher) = self.dispatcher(subscription_id) {
+            dispatcher.dispatch(SubscriptionAction::EndOfStoredEvents);
+        }
+    }
+    fn dispatcher(
+        &self,
+        subscription_id: &str,
+    ) -> Option<UseReducerDispatcher<SubscriptionStream>> {
+        self.streams.borrow().get(subscription_id).cloned()
+    }
+}
+
+/// Whether a note passes the subscription's filter, read from its wire form.
+/// `limit` only applies to what relays send and is ignored.
+pub fn subscription_matches(subscription: &NostrSubscription, note: &SignedNote) -> bool {
+    let Ok(message) = serde_json::to_value(subscription) else {
+        return false;
+    };
+    let Some(filter) = message.get(2).and_then(|filter| filter.as_object()) else {
+        return false;
+    };
+    let contains = |values: &serde_json::Value, wanted: &str| {
+        values
+            .as_array()
+            .is_some_and(|values| values.iter().any(|value| value.as_str() == Some(wanted)))
+    };
+    filter
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .all(|(field, value)| match field.as_str() {
+            "ids" => contains(value, note.get_id()),
+            "authors" => contains(value, note.get_pubkey()),
+            "kinds" => value.as_array().is_some_and(|kinds| {
+                kinds
+                    .iter()
+                    .any(|kind| kind.as_u64() == Some(u64::from(note.get_kind())))
+            }),
+            "since" => value
+                .as_u64()
+                .map_or(true, |since| note.get_created_at() >= since),
+            "until" => value
+                .as_u64()
+                .map_or(true, |until| note.get_created_at() <= until),
+            field => match field.strip_prefix('#') {
+                Some(name) => note_tags(note).iter().any(|tag| {
+                    tag.first().map(String::as_str) == Some(name)
+                        && tag
+                            .get(1)
+                            .is_some_and(|tag_value| contains(value, tag_value))
+                }),
+                None => true,
+            },
+        })
+}
+
+/// Subscribes to `filter` while the component is mounted and returns only the
+/// notes that arrive for that subscription.
+#[hook]
+pub fn use_nostr_subscription(filter: NostrFilter) -> SubscriptionHandle {
+    use_optional_subscription(Some(filter))
+}
+
+/// Subscribes to the filter built from the active account's pubkey. Switching
+/// accounts closes the old subscription and opens one for the new pubkey,
+/// nothing is subscribed while signed out.
+#[hook]
+pub fn use_account_subscription<F>(filter: F) -> SubscriptionHandle
+where
+    F: Fn(&str) -> NostrFilter,
+{
+    let identity = use_context::<NostrIdStore>().expect("No NostrIdProvider found");
+    use_optional_subscription(identity.active_pubkey().map(|pubkey| filter(&pubkey)))
+}
+
+#[hook]
+fn use_optional_subscription(filter: Option<NostrFilter>) -> SubscriptionHandle {
+    let subscriptions = use_context::<NostrSubscriptions>().expect("No relay context found");
+    let stream = use_reducer(SubscriptionStream::default);
+    // Filters are compared by their wire form, a changed filter resubscribes.
+    let filter_key = filter
+        .as_ref()
+        .map(|filter| serde_json::to_string(filter).unwrap_or_default());
+    let dispatcher = stream.dispatcher();
+    use_effect_with(filter_key, move |_| {
+        let subscription = filter.map(NostrSubscription::new);
+        let subscription_id = subscription.as_ref().map(|subscription| subscription.id());
+        match subscription {
+            Some(subscription) => subscriptions.open(subscription, dispatcher),
+            None => dispatcher.dispatch(SubscriptionAction::Clear),
+        }
+        move || {
+            if let Some(subscription_id) = subscription_id {
+                subscriptions.close(subscription_id)
+            }
+        }
+    });
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn _subscription_stream_capacity() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let signed_note = |created_at: u64| {
+            let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Minion note");
+            let mut note = serde_json::to_value(note).unwrap();
+            note["created_at"] = created_at.into();
+            keys.sign_nostr_event(serde_json::from_value(note).unwrap())
+        };
+        let mut stream = StreamNotes::default();
+        let first = signed_note(100);
+        assert!(stream.insert(first.clone(), 2));
+        assert!(!stream.insert(first, 2));
+        assert!(stream.insert(signed_note(300), 2));
+        // Full, so the oldest goes and an even older note is not kept.
+        assert!(stream.insert(signed_note(200), 2));
+        assert!(!stream.insert(signed_note(50), 2));
+        let created: Vec<u64> = stream
+            .notes
+            .iter()
+            .map(|note| note.get_created_at())
+            .collect();
+        assert_eq!(created, vec![300, 200]);
+        assert_eq!(stream.note_ids.len(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn _subscription_matches() {
+        let keys = nostro2::userkeys::UserKeys::generate();
+        let note = nostro2::notes::Note::new(&keys.get_public_key(), 1, "Minion note");
+        let mut note = serde_json::to_value(note).unwrap();
+        note["created_at"] = 1_000.into();
+        note["tags"] = serde_json::json!([["p", "a".repeat(64)]]);
+        let note = keys.sign_nostr_event(serde_json::from_value(note).unwrap());
+        let matches =
+            |filter: NostrFilter| subscription_matches(&NostrSubscription::new(filter), &note);
+        assert!(matches(NostrFilter::default().new_kind(1)));
+        assert!(matches(
+            NostrFilter::default()
+                .new_kinds(vec![0, 1])
+                .new_authors(vec![keys.get_public_key()])
+                .new_tag("p", vec!["a".repeat(64)])
+                .new_limit(1)
+        ));
+        assert!(!matches(NostrFilter::default().new_kind(7)));
+        assert!(!matches(
+            NostrFilter::default().new_authors(vec!["b".repeat(64)])
+        ));
+        assert!(!matches(
+            NostrFilter::default().new_tag("p", vec!["b".repeat(64)])
+        ));
+        assert!(!matches(
+            NostrFilter::default().new_tag("e", vec!["a".repeat(64)])
+        ));
+    }
+}
@@ -1,6 +1,8 @@
-use crate::relay_pool::NostrProps;
+use crate::key_manager::short_npub;
+use crate::relay_pool::{use_nostr_subscription, use_profiles};
 use crate::widgets::ag_grid::{AgGridComponent, create_column};
 use nostro2::notes::SignedNote;
+use nostro2::relays::NostrFilter;
 use serde::Serialize;
 use yew::prelude::*;
 
@@ -8,6 +10,7 @@ use yew::prelude::*;
 struct NostrNoteRow {
     id: String,
     pubkey: String,
+    author: String,
     content: String,
     created_at: u64,  
     kind: u32,        
@@ -18,6 +21,7 @@ impl From<&SignedNote> for NostrNoteRow {
         NostrNoteRow {
             id: note.get_id().to_string(),
             pubkey: note.get_pubkey().to_string(),
+            author: short_npub(&note.get_pubkey().to_string()),
             content: note.get_content().to_string(),
             created_at: note.get_created_at(),
             kind: note.get_kind(),
@@ -27,27 +31,25 @@ impl From<&SignedNote> for NostrNoteRow {
 
 #[function_component(NostrNotesGrid)]
 pub fn nostr_notes_grid() -> Html {
-    let relay_ctx = use_context::<NostrProps>().expect("No relay context found");
-    let rows = use_state(Vec::new);
-    {
-        let rows = rows.clone();
-        let notes = relay_ctx.unique_notes.clone();
-
-        use_effect_with(
-            notes,
-            move |notes| {
-                // Convert notes to row data
-                let new_rows: Vec<NostrNoteRow> = notes
-                    .iter()
-                    .filter(|note| note.get_kind() == 1)
-                    .map(NostrNoteRow::from)
-                    .collect();
-                
-                rows.set(new_rows);
-                || ()
+    let text_notes = use_nostr_subscription(NostrFilter::default().new_kind(1).new_limit(100));
+    let profiles = use_profiles(
+        text_notes
+            .notes()
+            .iter()
+            .map(|note| note.get_pubkey().to_string())
+            .collect(),
+    );
+    let rows: Vec<NostrNoteRow> = text_notes
+        .notes()
+        .iter()
+        .map(|note| {
+            let mut row = NostrNoteRow::from(note);
+            if let Some(profile) = profiles.get(&row.pubkey) {
+                row.author = profile.display_label();
             }
-        );
-    }
+            row
+        })
+        .collect();
 
     let columns = vec![
         {
@@ -55,7 +57,7 @@ pub fn nostr_notes_grid() -> Html {
             col.width = Some(400);
             col
         },
-        create_column("pubkey", "Author"),
+        create_column("author", "Author"),
         create_column("created_at", "Time"),
     ];
 
@@ -63,7 +65,7 @@ pub fn nostr_notes_grid() -> Html {
         <div class="w-full h-full">
             <h2 class="text-xl mb-4">{"Nostr Text Notes (Kind 1)"}</h2>
             <AgGridComponent<NostrNoteRow>
-                data={(*rows).clone()}
+                data={rows}
                 columns={columns}
                 class={classes!("h-[500px]")}
             />
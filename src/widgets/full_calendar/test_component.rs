@@ -1,4 +1,5 @@
 use yew::prelude::*;
+use crate::relay_pool::note_store::use_note_store;
 use crate::relay_pool::relay_pool::NostrProps;
 use super::{FullCalendarComponent, FullCalendarEvent};
 use js_sys::Date;
@@ -11,6 +12,7 @@ use crate::widgets::toastify::ToastifyOptions;
 #[function_component(FullCalendarTest)]
 pub fn calendar_test() -> Html {
     let relay_ctx = use_context::<NostrProps>().expect("No relay context found");
+    let note_store = use_note_store();
     let events = use_state(Vec::new);
     // Set up subscription for calendar events
     {
@@ -127,11 +129,12 @@ let handle_date_select = {
     // Update events when notes change
     {
         let events = events.clone();
-        let notes = relay_ctx.unique_notes.clone();
+        let notes = note_store.clone();
         
         use_effect_with(notes, move |notes| {
-            gloo::console::log!("Received notes update, total notes:", notes.len());
-            let calendar_events: Vec<FullCalendarEvent> = notes
+            let calendar_notes = notes.notes().by_kind(31924, usize::MAX);
+            gloo::console::log!("Received notes update, total notes:", calendar_notes.len());
+            let calendar_events: Vec<FullCalendarEvent> = calendar_notes
                 .iter()
                 .filter_map(|note| {
                     if note.get_kind() == 31924 {
@@ -44,7 +44,7 @@ impl ToastifyOptions {
 
     pub fn new_relay_disconnected(relay_url: &str) -> Self {
         ToastifyOptions {
-            text: format!("Disconnected from relay: {}. Please refresh the app.", relay_url),
+            text: format!("Disconnected from relay: {}. Reconnecting...", relay_url),
             duration: u32::MAX,
             close: true,
             gravity: "top",
@@ -72,6 +72,17 @@ impl ToastifyOptions {
         }
     }
 
+    pub fn new_notes_received(count: usize) -> Self {
+        let text = match count {
+            1 => "1 new note".to_string(),
+            count => format!("{} new notes", count),
+        };
+        ToastifyOptions {
+            text,
+            ..Self::new_event_received("note")
+        }
+    }
+
     pub fn new_relay_error(error: &str) -> Self {
         ToastifyOptions {
             text: format!("Relay error: {}", error),